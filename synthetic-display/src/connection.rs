@@ -0,0 +1,170 @@
+use crate::source::Renderer;
+use crate::Options;
+
+use std::fmt;
+use std::io::{self, Write, BufWriter};
+use std::net::{TcpStream, Shutdown};
+use std::sync::mpsc::{self, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use virtual_display_core::displays::Displays;
+use virtual_display_core::protocol::{self, FrameEncoder, EncodeError};
+
+/// how often the statistics get printed
+const STATS_INTERVAL: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum Error {
+	Connecting(io::Error),
+	Transmission(io::Error),
+	/// a source could not be loaded, retrying does not help
+	Source(io::Error),
+	Image(EncodeError)
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Connecting(e) => write!(f, "could not connect {e}"),
+			Self::Transmission(e) => write!(f, "transmission failed {e}"),
+			Self::Source(e) => write!(f, "could not load source {e}"),
+			Self::Image(e) => write!(f, "could not encode frame {e}")
+		}
+	}
+}
+
+/// Closes the connection once dropped, so the thread reading the displays
+/// stops
+struct CloseOnDrop(TcpStream);
+
+impl Drop for CloseOnDrop {
+	fn drop(&mut self) {
+		let _ = self.0.shutdown(Shutdown::Both);
+	}
+}
+
+/// Receives the displays the server sends, every message is the length
+/// followed by the displays as json.
+fn read_displays(
+	mut stream: TcpStream,
+	tx: mpsc::Sender<io::Result<Option<Displays>>>
+) {
+	loop {
+		let r = protocol::read_displays(&mut stream);
+
+		let failed = r.is_err();
+		if tx.send(r).is_err() || failed {
+			return
+		}
+	}
+}
+
+/// Sends frames until the connection fails.
+///
+/// Frames are encoded like the driver does, so only the tiles which changed
+/// get sent.
+pub fn connection_loop(options: &Options) -> Result<(), Error> {
+	let stream = TcpStream::connect(&options.addr)
+		.map_err(Error::Connecting)?;
+	eprintln!("connected to {}", options.addr);
+
+	let reader = stream.try_clone().map_err(Error::Transmission)?;
+	let _close = CloseOnDrop(
+		stream.try_clone().map_err(Error::Transmission)?
+	);
+	let (tx, rx) = mpsc::channel();
+	thread::spawn(move || read_displays(reader, tx));
+
+	let mut writer = BufWriter::new(stream);
+	let mut displays = Displays { inner: vec![] };
+	let mut renderers: Vec<Renderer> = vec![];
+	let mut encoder = FrameEncoder::new();
+
+	let interval = Duration::from_secs_f64(1. / options.fps.max(1) as f64);
+	let started_at = Instant::now();
+	let mut next_frame_at = started_at;
+	let mut frame = 0u64;
+
+	let mut stats_at = started_at;
+	let mut stats_frames = 0u64;
+	let mut stats_bytes = 0usize;
+	let mut buffer = vec![];
+
+	loop {
+		// apply the latest displays
+		loop {
+			let new = match rx.try_recv() {
+				Ok(Ok(d)) => d,
+				Ok(Err(e)) => return Err(Error::Transmission(e)),
+				Err(TryRecvError::Empty) => break,
+				Err(TryRecvError::Disconnected) => {
+					return Err(Error::Transmission(
+						io::ErrorKind::UnexpectedEof.into()
+					))
+				}
+			};
+
+			displays = new.unwrap_or(Displays { inner: vec![] });
+			renderers = create_renderers(options, &displays)?;
+			encoder.reset();
+		}
+
+		let elapsed = started_at.elapsed();
+		let images = renderers.iter()
+			.map(|r| r.render(frame, elapsed).into_raw())
+			.collect();
+
+		let errors = encoder.encode_pixels(&displays, images);
+		if let Some(e) = errors.into_iter().next() {
+			return Err(Error::Image(e))
+		}
+
+		buffer.clear();
+		encoder.write_to(&mut buffer).map_err(Error::Transmission)?;
+		writer.write_all(&buffer).map_err(Error::Transmission)?;
+		writer.flush().map_err(Error::Transmission)?;
+
+		frame += 1;
+		stats_frames += 1;
+		stats_bytes += buffer.len();
+
+		if stats_at.elapsed() >= STATS_INTERVAL {
+			let secs = stats_at.elapsed().as_secs_f64();
+			eprintln!(
+				"{} displays, {:.1} fps, {:.0} KB/s",
+				displays.inner.len(),
+				stats_frames as f64 / secs,
+				stats_bytes as f64 / secs / 1000.
+			);
+			stats_at = Instant::now();
+			stats_frames = 0;
+			stats_bytes = 0;
+		}
+
+		// if encoding is too slow don't try to catch up
+		next_frame_at = (next_frame_at + interval).max(Instant::now());
+		thread::sleep(next_frame_at.saturating_duration_since(Instant::now()));
+	}
+}
+
+/// creates a renderer for every display with the source from the options
+fn create_renderers(
+	options: &Options,
+	displays: &Displays
+) -> Result<Vec<Renderer>, Error> {
+	let names: Vec<_> = displays.inner.iter()
+		.map(|d| d.name.as_str())
+		.collect();
+	eprintln!("received displays {names:?}");
+
+	displays.inner.iter()
+		.map(|display| {
+			Renderer::new(
+				options.source(&display.name),
+				display.width,
+				display.height
+			).map_err(Error::Source)
+		})
+		.collect()
+}
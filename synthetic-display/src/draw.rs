@@ -0,0 +1,141 @@
+use image::{RgbaImage, Rgba};
+
+pub const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+pub const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+pub const GREEN: Rgba<u8> = Rgba([0, 220, 0, 255]);
+
+/// the parts of a rectangle outside of the image are ignored
+pub fn fill_rect(
+	image: &mut RgbaImage,
+	x: i64,
+	y: i64,
+	width: u32,
+	height: u32,
+	color: Rgba<u8>
+) {
+	let x_start = x.max(0) as u32;
+	let y_start = y.max(0) as u32;
+	let x_end = (x + width as i64).clamp(0, image.width() as i64) as u32;
+	let y_end = (y + height as i64).clamp(0, image.height() as i64) as u32;
+
+	for y in y_start..y_end {
+		for x in x_start..x_end {
+			image.put_pixel(x, y, color);
+		}
+	}
+}
+
+/// draws a line with the given thickness by stamping squares along it
+pub fn line(
+	image: &mut RgbaImage,
+	from: (f64, f64),
+	to: (f64, f64),
+	thickness: u32,
+	color: Rgba<u8>
+) {
+	let steps = (to.0 - from.0).abs().max((to.1 - from.1).abs()).ceil() as u32;
+	let half = thickness as f64 / 2.;
+
+	for i in 0..=steps {
+		let t = i as f64 / steps.max(1) as f64;
+		let x = from.0 + (to.0 - from.0) * t;
+		let y = from.1 + (to.1 - from.1) * t;
+		fill_rect(
+			image,
+			(x - half).round() as i64,
+			(y - half).round() as i64,
+			thickness,
+			thickness,
+			color
+		);
+	}
+}
+
+// the segments of a seven segment display
+//  aaa
+// f   b
+//  ggg
+// e   c
+//  ddd
+const SEGMENTS: [u8; 10] = [
+	0b0111111, // 0 abcdef
+	0b0000110, // 1 bc
+	0b1011011, // 2 abdeg
+	0b1001111, // 3 abcdg
+	0b1100110, // 4 bcfg
+	0b1101101, // 5 acdfg
+	0b1111101, // 6 acdefg
+	0b0000111, // 7 abc
+	0b1111111, // 8 abcdefg
+	0b1101111  // 9 abcdfg
+];
+
+/// How wide a character of `text` is, a digit is half as wide as high
+pub fn char_width(height: u32) -> u32 {
+	height / 2
+}
+
+/// Draws digits and colons like a seven segment display, other characters
+/// are skipped.
+pub fn text(
+	image: &mut RgbaImage,
+	x: i64,
+	y: i64,
+	height: u32,
+	text: &str,
+	color: Rgba<u8>
+) {
+	let width = char_width(height) as i64;
+	let thick = (height / 10).max(1);
+	let half = height as i64 / 2;
+	// the space between two characters
+	let gap = width / 3;
+
+	let mut x = x;
+	for c in text.chars() {
+		let segments = match c {
+			':' => {
+				let dot_x = x + width / 2 - thick as i64 / 2;
+				fill_rect(image, dot_x, y + half / 2, thick, thick, color);
+				fill_rect(image, dot_x, y + half * 3 / 2, thick, thick, color);
+				x += width + gap;
+				continue
+			},
+			c => match c.to_digit(10) {
+				Some(d) => SEGMENTS[d as usize],
+				None => {
+					x += width + gap;
+					continue
+				}
+			}
+		};
+
+		let w = width as u32;
+		let h = half as u32;
+		let right = x + width - thick as i64;
+		// (x, y, width, height) of every segment
+		let rects = [
+			(x, y, w, thick),
+			(right, y, thick, h),
+			(right, y + half, thick, h),
+			(x, y + height as i64 - thick as i64, w, thick),
+			(x, y + half, thick, h),
+			(x, y, thick, h),
+			(x, y + half - thick as i64 / 2, w, thick)
+		];
+		for (i, (rx, ry, rw, rh)) in rects.into_iter().enumerate() {
+			if segments & (1 << i) != 0 {
+				fill_rect(image, rx, ry, rw, rh, color);
+			}
+		}
+
+		x += width + gap;
+	}
+}
+
+/// how wide `text` will be when drawn with the given height
+pub fn text_width(height: u32, text: &str) -> u32 {
+	let width = char_width(height);
+	let len = text.chars().count() as u32;
+	(width + width / 3) * len
+}
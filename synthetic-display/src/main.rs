@@ -0,0 +1,115 @@
+//! Speaks the same protocol as the virtual display driver but renders test
+//! patterns instead of capturing a screen, so the server and the mfd pages
+//! can be developed and load tested without windows and dcs.
+
+mod draw;
+mod source;
+use source::Source;
+mod connection;
+use connection::Error;
+
+use std::{env, thread, process};
+use std::collections::HashMap;
+use std::time::Duration;
+
+const USAGE: &str = "\
+usage: synthetic-display [options]
+
+options:
+	--addr <addr>              the driver port of the server
+	                           (default 127.0.0.1:5476)
+	--fps <fps>                how many frames are sent per second
+	                           (default 30)
+	--source <source>          what every display shows (default pattern)
+	--display <name>=<source>  what a specific display shows
+
+sources:
+	pattern                    color bars, a moving square and a counter
+	clock                      the current time and a sweeping hand
+	<directory>                the images in the directory one after
+	                           another";
+
+#[derive(Debug)]
+pub struct Options {
+	pub addr: String,
+	pub fps: u32,
+	/// used for every display which has no source in `displays`
+	pub default_source: Source,
+	/// the source of a display by name
+	pub displays: HashMap<String, Source>
+}
+
+impl Options {
+	fn parse(mut args: impl Iterator<Item=String>) -> Result<Self, String> {
+		let mut options = Self {
+			addr: "127.0.0.1:5476".into(),
+			fps: 30,
+			default_source: Source::Pattern,
+			displays: HashMap::new()
+		};
+
+		while let Some(arg) = args.next() {
+			let mut value = || args.next()
+				.ok_or_else(|| format!("{arg} needs a value"));
+
+			match arg.as_str() {
+				"--addr" => options.addr = value()?,
+				"--fps" => {
+					options.fps = value()?.parse()
+						.ok().filter(|fps| *fps > 0)
+						.ok_or("--fps needs to be a number above 0")?;
+				},
+				"--source" => options.default_source = Source::parse(&value()?),
+				"--display" => {
+					let value = value()?;
+					let (name, source) = value.split_once('=')
+						.ok_or("--display needs to be <name>=<source>")?;
+					options.displays.insert(name.into(), Source::parse(source));
+				},
+				"--help" | "-h" => return Err(String::new()),
+				arg => return Err(format!("unknown option {arg}"))
+			}
+		}
+
+		Ok(options)
+	}
+
+	pub fn source(&self, name: &str) -> Source {
+		self.displays.get(name)
+			.unwrap_or(&self.default_source)
+			.clone()
+	}
+}
+
+fn main() {
+	let options = match Options::parse(env::args().skip(1)) {
+		Ok(o) => o,
+		Err(e) => {
+			if !e.is_empty() {
+				eprintln!("{e}\n");
+			}
+			eprintln!("{USAGE}");
+			process::exit(1)
+		}
+	};
+
+	// reconnects like the driver does
+	loop {
+		match connection::connection_loop(&options) {
+			Ok(_) => break,
+			// the server is probably not running yet
+			Err(e @ Error::Connecting(_)) => {
+				eprintln!("{e} to {}", options.addr);
+				thread::sleep(Duration::from_secs(5));
+			},
+			Err(e @ Error::Source(_)) => {
+				eprintln!("{e}");
+				process::exit(1)
+			},
+			Err(e) => {
+				eprintln!("{e}");
+				thread::sleep(Duration::from_secs(1));
+			}
+		}
+	}
+}
@@ -0,0 +1,215 @@
+use crate::draw::{self, BLACK, WHITE, GREEN};
+
+use std::{fs, io};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::f64::consts::PI;
+
+use image::{RgbaImage, Rgba};
+use image::imageops::{self, FilterType};
+
+/// What a display shows
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+	/// color bars with a moving square and a frame counter
+	Pattern,
+	/// the current time as digits and a sweeping hand
+	Clock,
+	/// cycles through the images in the directory, ordered by their name
+	Images(PathBuf)
+}
+
+impl Source {
+	/// `pattern`, `clock` or the path to a directory
+	pub fn parse(s: &str) -> Self {
+		match s {
+			"pattern" => Self::Pattern,
+			"clock" => Self::Clock,
+			path => Self::Images(path.into())
+		}
+	}
+}
+
+const BARS: [Rgba<u8>; 7] = [
+	Rgba([192, 192, 192, 255]),
+	Rgba([192, 192, 0, 255]),
+	Rgba([0, 192, 192, 255]),
+	Rgba([0, 192, 0, 255]),
+	Rgba([192, 0, 192, 255]),
+	Rgba([192, 0, 0, 255]),
+	Rgba([0, 0, 192, 255])
+];
+
+/// Renders the frames of a display
+#[derive(Debug)]
+pub struct Renderer {
+	source: Source,
+	width: u32,
+	height: u32,
+	/// the images of the directory already resized to the display
+	images: Vec<RgbaImage>
+}
+
+impl Renderer {
+	/// loads the images if the source is a directory
+	pub fn new(source: Source, width: u32, height: u32) -> io::Result<Self> {
+		let images = match &source {
+			Source::Images(dir) => load_images(dir, width, height)?,
+			_ => vec![]
+		};
+
+		Ok(Self { source, width, height, images })
+	}
+
+	pub fn render(&self, frame: u64, elapsed: Duration) -> RgbaImage {
+		match self.source {
+			Source::Pattern => self.pattern(frame),
+			Source::Clock => self.clock(elapsed),
+			Source::Images(_) => {
+				let i = frame as usize % self.images.len();
+				self.images[i].clone()
+			}
+		}
+	}
+
+	fn pattern(&self, frame: u64) -> RgbaImage {
+		let (width, height) = (self.width, self.height);
+		let mut image = RgbaImage::from_pixel(width, height, BLACK);
+
+		let bar_width = width.div_ceil(BARS.len() as u32);
+		for (i, color) in BARS.into_iter().enumerate() {
+			let x = (i as u32 * bar_width) as i64;
+			draw::fill_rect(&mut image, x, 0, bar_width, height / 2, color);
+		}
+
+		// a grid makes scaling artifacts visible
+		for x in (0..width).step_by(64) {
+			draw::fill_rect(&mut image, x as i64, 0, 1, height, WHITE);
+		}
+		for y in (0..height).step_by(64) {
+			draw::fill_rect(&mut image, 0, y as i64, width, 1, WHITE);
+		}
+
+		// moves across the lower half, four pixels per frame
+		let size = (width.min(height) / 8).max(1);
+		let range = width.saturating_sub(size).max(1) as u64;
+		let x = (frame * 4 % (range * 2)) as i64;
+		// bounce back at the edge
+		let x = if x > range as i64 { range as i64 * 2 - x } else { x };
+		let y = (height / 2 + height / 8) as i64;
+		draw::fill_rect(&mut image, x, y, size, size, GREEN);
+
+		let digit_height = (height / 8).max(10);
+		let text = format!("{frame:06}");
+		let text_x = width.saturating_sub(
+			draw::text_width(digit_height, &text)
+		) / 2;
+		let text_y = height.saturating_sub(digit_height + digit_height / 2);
+		draw::text(
+			&mut image,
+			text_x as i64,
+			text_y as i64,
+			digit_height,
+			&text,
+			WHITE
+		);
+
+		image
+	}
+
+	fn clock(&self, elapsed: Duration) -> RgbaImage {
+		let (width, height) = (self.width, self.height);
+		let mut image = RgbaImage::from_pixel(width, height, BLACK);
+
+		let now = SystemTime::now().duration_since(UNIX_EPOCH)
+			.unwrap_or_default();
+		let secs = now.as_secs();
+		let text = format!(
+			"{:02}:{:02}:{:02}",
+			secs / 3600 % 24,
+			secs / 60 % 60,
+			secs % 60
+		);
+
+		let digit_height = (height / 6).max(10);
+		let text_x = width.saturating_sub(
+			draw::text_width(digit_height, &text)
+		) / 2;
+		draw::text(
+			&mut image,
+			text_x as i64,
+			(digit_height / 2) as i64,
+			digit_height,
+			&text,
+			GREEN
+		);
+
+		// one rotation every 10 seconds so every frame differs
+		let center = (width as f64 / 2., height as f64 * 0.6);
+		let radius = width.min(height) as f64 * 0.3;
+		let angle = elapsed.as_secs_f64() / 10. * 2. * PI;
+		let hand = (
+			center.0 + angle.sin() * radius,
+			center.1 - angle.cos() * radius
+		);
+		let thickness = (width.min(height) / 80).max(1);
+		draw::line(&mut image, center, hand, thickness, WHITE);
+
+		// the ticks around the hand
+		for i in 0..12 {
+			let angle = i as f64 / 12. * 2. * PI;
+			let tick = (
+				center.0 + angle.sin() * radius * 1.1,
+				center.1 - angle.cos() * radius * 1.1
+			);
+			draw::fill_rect(
+				&mut image,
+				tick.0 as i64 - thickness as i64,
+				tick.1 as i64 - thickness as i64,
+				thickness * 2,
+				thickness * 2,
+				WHITE
+			);
+		}
+
+		image
+	}
+}
+
+fn load_images(
+	dir: &Path,
+	width: u32,
+	height: u32
+) -> io::Result<Vec<RgbaImage>> {
+	let mut paths: Vec<_> = fs::read_dir(dir)
+		.and_then(|entries| {
+			entries.map(|entry| entry.map(|e| e.path())).collect()
+		})
+		.map_err(|e| {
+			io::Error::new(e.kind(), format!("{} {e}", dir.display()))
+		})?;
+	paths.sort();
+
+	let mut images = vec![];
+	for path in paths {
+		// other files in the directory are skipped
+		let image = match image::open(&path) {
+			Ok(i) => i,
+			Err(_) => continue
+		};
+
+		let image = image.into_rgba8();
+		images.push(
+			imageops::resize(&image, width, height, FilterType::Triangle)
+		);
+	}
+
+	if images.is_empty() {
+		return Err(io::Error::other(format!(
+			"no images found in {}",
+			dir.display()
+		)))
+	}
+
+	Ok(images)
+}
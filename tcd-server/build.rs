@@ -25,29 +25,29 @@ fn main() {
 
 
 	let mut s = String::new();
-	write!(s, "use fire::{{memory_file, fs::MemoryFile, FireBuilder}};\n\n")
+	writeln!(s, "use fire::{{memory_file, fs::MemoryFile, FireBuilder}};\n")
 		.unwrap();
 
 	// add index
 	let index_path = canonicalize("../tcd-ui/dist/index.html").unwrap();
-	write!(s, "const INDEX: MemoryFile = \
-		memory_file!(\"/\", {index_path:?});\n").unwrap();
+	writeln!(s, "const INDEX: MemoryFile = \
+		memory_file!(\"/\", {index_path:?});").unwrap();
 
 	for (i, (uri, path)) in files.iter().enumerate() {
-		write!(s, "const FILE_{i}: MemoryFile = \
-			memory_file!({uri:?}, {path:?});\n").unwrap();
+		writeln!(s, "const FILE_{i}: MemoryFile = \
+			memory_file!({uri:?}, {path:?});").unwrap();
 	}
 
-	write!(s, "\npub fn add_routes(fire: &mut FireBuilder) {{\n").unwrap();
+	writeln!(s, "\npub fn add_routes(fire: &mut FireBuilder) {{").unwrap();
 
 	// add index
-	write!(s, "\tfire.add_route(INDEX);\n").unwrap();
+	writeln!(s, "\tfire.add_route(INDEX);").unwrap();
 
 	for (i, _) in files.iter().enumerate() {
-		write!(s, "\tfire.add_route(FILE_{i});\n").unwrap();
+		writeln!(s, "\tfire.add_route(FILE_{i});").unwrap();
 	}
 
-	write!(s, "}}\n").unwrap();
+	writeln!(s, "}}").unwrap();
 
 	
 	fs::write(files_routes, s).unwrap();
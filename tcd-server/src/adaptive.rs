@@ -0,0 +1,295 @@
+use crate::displays::Format;
+
+use tokio::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+/// how much a new measurement counts
+const SMOOTHING: f64 = 0.25;
+const QUALITY_STEP: u8 = 10;
+
+/// The bounds in which the frame rate and jpeg quality of a mfds client
+/// get adapted
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamLimits {
+	pub min_fps: u32,
+	pub max_fps: u32,
+	pub min_jpeg_quality: u8,
+	/// the quality of a display is never increased above what it is
+	/// configured with
+	pub max_jpeg_quality: u8,
+	/// the bits per second of every display streamed as h264
+	pub h264_bitrate: u32
+}
+
+impl Default for StreamLimits {
+	fn default() -> Self {
+		Self {
+			min_fps: 2,
+			max_fps: 30,
+			min_jpeg_quality: 30,
+			max_jpeg_quality: 100,
+			h264_bitrate: 2_000_000
+		}
+	}
+}
+
+/// Measures how fast a client aknowledges frames and adapts the frame rate
+/// and quality to it.
+///
+/// The latency of the connection is the fastest round trip seen, what the
+/// round trip takes longer is the time needed to transfer the frames. If
+/// the transfer takes longer than the frame interval, the quality get's
+/// lowered first and then the frame rate. If it is fast enough both get
+/// increased again. A high latency alone does not lower anything since
+/// it is not caused by the size of the frames.
+#[derive(Debug)]
+pub struct Adaptive {
+	limits: StreamLimits,
+	/// the smoothed round trip time
+	rtt: Option<Duration>,
+	/// the fastest round trip time
+	min_rtt: Option<Duration>,
+	/// the smoothed bytes per second
+	throughput: Option<f64>,
+	fps: f64,
+	quality: u8,
+	last_sent: Option<Instant>
+}
+
+impl Adaptive {
+	pub fn new(limits: &StreamLimits) -> Self {
+		let max_fps = limits.max_fps.max(1);
+		let min_fps = limits.min_fps.clamp(1, max_fps);
+		let max_quality = limits.max_jpeg_quality.clamp(1, 100);
+		let min_quality = limits.min_jpeg_quality.clamp(1, max_quality);
+
+		Self {
+			limits: StreamLimits {
+				min_fps,
+				max_fps,
+				min_jpeg_quality: min_quality,
+				max_jpeg_quality: max_quality,
+				h264_bitrate: limits.h264_bitrate
+			},
+			rtt: None,
+			min_rtt: None,
+			throughput: None,
+			fps: max_fps as f64,
+			quality: max_quality,
+			last_sent: None
+		}
+	}
+
+	fn interval(&self) -> Duration {
+		Duration::from_secs_f64(1. / self.fps)
+	}
+
+	/// when the next frame may be sent
+	pub fn next_frame_at(&self) -> Instant {
+		match self.last_sent {
+			Some(sent) => sent + self.interval(),
+			None => Instant::now()
+		}
+	}
+
+	pub fn may_send(&self) -> bool {
+		self.next_frame_at() <= Instant::now()
+	}
+
+	pub fn sent(&mut self) {
+		self.last_sent = Some(Instant::now());
+	}
+
+	/// needs to be called when the client aknowledged frames with the given
+	/// size
+	pub fn aknowledged(&mut self, bytes: usize) {
+		if let Some(sent) = self.last_sent {
+			self.measured(sent.elapsed(), bytes);
+		}
+	}
+
+	fn measured(&mut self, rtt: Duration, bytes: usize) {
+		let min_rtt = self.min_rtt.map(|m| m.min(rtt)).unwrap_or(rtt);
+		self.min_rtt = Some(min_rtt);
+
+		let smoothed = match self.rtt {
+			Some(prev) => prev.mul_f64(1. - SMOOTHING) + rtt.mul_f64(SMOOTHING),
+			None => rtt
+		};
+		self.rtt = Some(smoothed);
+
+		let throughput = bytes as f64 / rtt.as_secs_f64().max(0.001);
+		self.throughput = Some(match self.throughput {
+			Some(prev) => prev * (1. - SMOOTHING) + throughput * SMOOTHING,
+			None => throughput
+		});
+
+		self.adapt(smoothed.saturating_sub(min_rtt));
+	}
+
+	/// transfer is how long the frames took to be sent
+	fn adapt(&mut self, transfer: Duration) {
+		let interval = self.interval();
+		let min_fps = self.limits.min_fps as f64;
+		let max_fps = self.limits.max_fps as f64;
+
+		if transfer > interval {
+			// too slow, lower the quality first since a lower frame rate is
+			// more noticable
+			if self.quality > self.limits.min_jpeg_quality {
+				self.quality = self.quality.saturating_sub(QUALITY_STEP)
+					.max(self.limits.min_jpeg_quality);
+			} else {
+				self.fps = (self.fps * 0.75).max(min_fps);
+			}
+		} else if transfer < interval / 2 {
+			if self.fps < max_fps {
+				self.fps = (self.fps + 1.).min(max_fps);
+			} else {
+				self.quality = self.quality.saturating_add(QUALITY_STEP)
+					.min(self.limits.max_jpeg_quality);
+			}
+		}
+	}
+
+	/// the client did not aknowledge in time
+	pub fn timed_out(&mut self) {
+		self.adapt(self.interval() * 2);
+	}
+
+	/// Returns the jpeg quality a display with the given format should be
+	/// sent with, None if it does not need to be lowered.
+	pub fn quality(&self, format: Format) -> Option<u8> {
+		match format {
+			Format::Jpeg { quality } => {
+				Some(self.quality).filter(|q| *q < quality)
+			},
+			// lossless formats can only adapt the frame rate
+			Format::Png | Format::Webp => None
+		}
+	}
+
+	pub fn stats(&self) -> AdaptiveStats {
+		AdaptiveStats {
+			rtt_ms: self.rtt.map(|r| r.as_millis() as u64),
+			throughput: self.throughput.map(|t| t as u64),
+			fps: self.fps.round() as u32,
+			jpeg_quality: self.quality
+		}
+	}
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AdaptiveStats {
+	pub rtt_ms: Option<u64>,
+	/// in bytes per second
+	pub throughput: Option<u64>,
+	pub fps: u32,
+	pub jpeg_quality: u8
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const FRAME: usize = 50_000;
+
+	fn adaptive() -> Adaptive {
+		let mut adaptive = Adaptive::new(&StreamLimits::default());
+		// the latency of the connection
+		adaptive.measured(Duration::from_millis(5), 100);
+		adaptive
+	}
+
+	fn measure(adaptive: &mut Adaptive, rtt_ms: u64, times: usize) {
+		for _ in 0..times {
+			adaptive.measured(Duration::from_millis(rtt_ms), FRAME);
+		}
+	}
+
+	#[test]
+	fn quality_is_lowered_before_the_frame_rate() {
+		let mut adaptive = adaptive();
+
+		// a 30 fps interval is 33ms
+		measure(&mut adaptive, 100, 3);
+		let stats = adaptive.stats();
+		assert_eq!(stats.fps, 30);
+		assert!(stats.jpeg_quality < 100);
+
+		measure(&mut adaptive, 100, 20);
+		let stats = adaptive.stats();
+		assert_eq!(stats.jpeg_quality, 30);
+		assert!(stats.fps < 30);
+
+		// the frame rate stops once the transfer fits the interval
+		measure(&mut adaptive, 100, 50);
+		let fps = adaptive.stats().fps;
+		assert!((5..=10).contains(&fps), "{fps}");
+	}
+
+	#[test]
+	fn fast_clients_get_more_fps_then_quality() {
+		let mut adaptive = Adaptive::new(&StreamLimits {
+			max_fps: 10,
+			min_jpeg_quality: 50,
+			max_jpeg_quality: 80,
+			..Default::default()
+		});
+		for _ in 0..20 {
+			adaptive.timed_out();
+		}
+		let stats = adaptive.stats();
+		assert_eq!((stats.fps, stats.jpeg_quality), (2, 50));
+
+		measure(&mut adaptive, 5, 1);
+		assert_eq!(adaptive.stats().fps, 3);
+		assert_eq!(adaptive.stats().jpeg_quality, 50);
+
+		measure(&mut adaptive, 5, 7);
+		assert_eq!(adaptive.stats().fps, 10);
+		assert_eq!(adaptive.stats().jpeg_quality, 50);
+
+		measure(&mut adaptive, 5, 10);
+		assert_eq!(adaptive.stats().jpeg_quality, 80);
+	}
+
+	#[test]
+	fn latency_alone_does_not_lower() {
+		let mut adaptive = Adaptive::new(&StreamLimits::default());
+		measure(&mut adaptive, 150, 50);
+
+		let stats = adaptive.stats();
+		assert_eq!((stats.fps, stats.jpeg_quality), (30, 100));
+	}
+
+	#[test]
+	fn lowered_quality_only_for_jpeg() {
+		let mut adaptive = adaptive();
+		let jpeg = Format::Jpeg { quality: 80 };
+		assert_eq!(adaptive.quality(jpeg), None);
+
+		adaptive.timed_out();
+		adaptive.timed_out();
+		adaptive.timed_out();
+		assert_eq!(adaptive.quality(jpeg), Some(70));
+		assert_eq!(adaptive.quality(Format::Png), None);
+	}
+
+	#[test]
+	fn limits_are_clamped() {
+		let adaptive = Adaptive::new(&StreamLimits {
+			min_fps: 50,
+			max_fps: 0,
+			min_jpeg_quality: 120,
+			max_jpeg_quality: 0,
+			..Default::default()
+		});
+		assert_eq!(adaptive.limits.min_fps, 1);
+		assert_eq!(adaptive.limits.max_fps, 1);
+		assert_eq!(adaptive.limits.min_jpeg_quality, 1);
+		assert_eq!(adaptive.limits.max_jpeg_quality, 1);
+	}
+}
@@ -2,13 +2,22 @@ use std::fmt;
 
 use serde::{Serialize, Deserialize};
 
+use fire::Response;
+use fire::into::IntoResponse;
 use fire_api::error::{ApiError, Error as ErrorTrait, StatusCode};
 
 
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Error {
 	Internal(String),
-	Request(String)
+	Request(String),
+	Unauthorized(String),
+	Forbidden(String),
+	NotFound(String),
+	TooManyRequests(String),
+	/// the request is valid but cannot be answered yet, for example a
+	/// display without a frame
+	Unavailable(String)
 }
 
 impl ApiError for Error {
@@ -23,11 +32,27 @@ impl ApiError for Error {
 	fn status_code(&self) -> StatusCode {
 		match self {
 			Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
-			Self::Request(_) => StatusCode::BAD_REQUEST
+			Self::Request(_) => StatusCode::BAD_REQUEST,
+			Self::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+			Self::Forbidden(_) => StatusCode::FORBIDDEN,
+			Self::NotFound(_) => StatusCode::NOT_FOUND,
+			Self::TooManyRequests(_) => StatusCode::TOO_MANY_REQUESTS,
+			Self::Unavailable(_) => StatusCode::SERVICE_UNAVAILABLE
 		}
 	}
 }
 
+/// used by routes which are not an api
+impl IntoResponse for Error {
+	fn into_response(self) -> Response {
+		Response::builder()
+			.status_code(self.status_code())
+			.content_type("application/json")
+			.body(serde_json::to_vec(&self).unwrap())
+			.build()
+	}
+}
+
 impl fmt::Display for Error {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		fmt::Debug::fmt(self, f)
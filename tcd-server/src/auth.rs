@@ -0,0 +1,692 @@
+use crate::api_error::Error;
+use crate::config::data_dir;
+use crate::net::local_ip;
+use crate::connections::Connections;
+
+use std::{fs, io};
+use std::fmt::Write;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use rand::Rng;
+
+use serde::{Serialize, Deserialize};
+
+use fire::FireBuilder;
+use fire::header::RequestHeader;
+use fire_api::{api, Request, Method};
+
+const TOKENS_FILE: &str = "tokens.json";
+/// every http api call needs to contain this header
+pub const TOKEN_HEADER: &str = "tcd-token";
+/// how many digits a pairing code has
+const PAIRING_CODE_DIGITS: u32 = 10;
+/// a pairing code which was not redeemed in time get's replaced
+const PAIRING_CODE_LIFETIME: Duration = Duration::from_secs(10 * 60);
+/// after this many wrong codes an ip get's locked out
+const MAX_PAIRING_ATTEMPTS: u32 = 5;
+/// how long an ip is locked out, the attempts are also forgotten after
+/// this time
+const PAIRING_LOCKOUT: Duration = Duration::from_secs(5 * 60);
+
+/// What a paired device is allowed to do
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Role {
+	/// can only subscribe to outputs and mfd frames
+	Observer,
+	/// can send inputs, if categories is set only to controls of those
+	/// categories
+	Operator {
+		#[serde(default)]
+		categories: Option<Vec<String>>
+	}
+}
+
+impl Role {
+	/// returns if an input to a control of the given category is allowed
+	///
+	/// A control without a category is only allowed if the operator is not
+	/// restricted.
+	pub fn can_input(&self, category: Option<&str>) -> bool {
+		match (self, category) {
+			(Self::Observer, _) => false,
+			(Self::Operator { categories: None }, _) => true,
+			(Self::Operator { categories: Some(cats) }, Some(cat)) => {
+				cats.iter().any(|c| c == cat)
+			},
+			(Self::Operator { categories: Some(_) }, None) => false
+		}
+	}
+
+	/// only unrestricted operators can manage the paired devices
+	pub fn is_admin(&self) -> bool {
+		matches!(self, Self::Operator { categories: None })
+	}
+}
+
+/// A valid token and what it is allowed to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Session {
+	/// the id of the token, used to update open connections if the token
+	/// get's revoked or changes it's role
+	pub token_id: u64,
+	pub role: Role
+}
+
+impl Default for Role {
+	/// tokens which were created before roles existed had all rights
+	fn default() -> Self {
+		Self::Operator { categories: None }
+	}
+}
+
+/// The tokens of all paired devices and the current pairing code.
+#[derive(Debug, Clone)]
+pub struct Tokens {
+	inner: Arc<Mutex<Inner>>,
+	scheme: &'static str,
+	port: u16
+}
+
+#[derive(Debug)]
+struct Inner {
+	list: Vec<TokenEntry>,
+	pairing_code: String,
+	/// the role a device get's which redeems the pairing code
+	pairing_role: Role,
+	pairing_expires_at: Instant,
+	/// the wrong codes per ip, a wrong code does not replace the code so
+	/// another device cannot invalidate the code the user sees
+	failed_attempts: HashMap<IpAddr, FailedAttempts>
+}
+
+#[derive(Debug)]
+struct FailedAttempts {
+	count: u32,
+	last: Instant
+}
+
+impl FailedAttempts {
+	fn is_expired(&self, now: Instant) -> bool {
+		self.last + PAIRING_LOCKOUT <= now
+	}
+
+	fn is_locked(&self, now: Instant) -> bool {
+		self.count >= MAX_PAIRING_ATTEMPTS && !self.is_expired(now)
+	}
+}
+
+impl Inner {
+	/// Returns the role of the pairing code and uses it up by letting it
+	/// expire.
+	///
+	/// Wrong codes are counted per ip.
+	fn use_pairing_code(
+		&mut self,
+		code: &str,
+		ip: IpAddr,
+		now: Instant
+	) -> Result<Role, Error> {
+		self.failed_attempts.retain(|_, a| !a.is_expired(now));
+		if self.failed_attempts.get(&ip).is_some_and(|a| a.is_locked(now)) {
+			return Err(Error::TooManyRequests(
+				"too many wrong pairing codes, try again later".into()
+			))
+		}
+
+		if self.pairing_expires_at <= now {
+			eprintln!("the pairing code expired");
+
+			return Err(Error::Unauthorized(
+				"the pairing code expired, use the new one".into()
+			))
+		}
+
+		if !constant_time_eq(&self.pairing_code, code) {
+			let attempts = self.failed_attempts.entry(ip)
+				.or_insert(FailedAttempts { count: 0, last: now });
+			attempts.count += 1;
+			attempts.last = now;
+			if attempts.count >= MAX_PAIRING_ATTEMPTS {
+				eprintln!("too many wrong pairing codes from {ip}");
+			}
+
+			return Err(Error::Unauthorized("wrong pairing code".into()))
+		}
+
+		self.failed_attempts.remove(&ip);
+		self.pairing_expires_at = now;
+
+		Ok(self.pairing_role.clone())
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TokenEntry {
+	id: u64,
+	name: String,
+	token: String,
+	#[serde(default)]
+	role: Role,
+	/// unix timestamp in seconds
+	created_at: u64
+}
+
+impl Tokens {
+	/// Loads the tokens from the data directory and prints the first pairing
+	/// code.
+	///
+	/// The scheme and port are used to print the pairing url.
+	pub fn load(scheme: &'static str, port: u16) -> io::Result<Self> {
+		let path = data_dir().join(TOKENS_FILE);
+		let list = match fs::read(path) {
+			Ok(v) => serde_json::from_slice(&v).map_err(io::Error::other)?,
+			Err(e) if e.kind() == io::ErrorKind::NotFound => vec![],
+			Err(e) => return Err(e)
+		};
+
+		let this = Self {
+			inner: Arc::new(Mutex::new(Inner {
+				list,
+				pairing_code: String::new(),
+				pairing_role: Role::default(),
+				pairing_expires_at: Instant::now(),
+				failed_attempts: HashMap::new()
+			})),
+			scheme, port
+		};
+
+		this.new_pairing_code(&mut this.lock(), Role::default());
+
+		Ok(this)
+	}
+
+	fn lock(&self) -> MutexGuard<'_, Inner> {
+		self.inner.lock().unwrap()
+	}
+
+	/// returns the session of the token or None if the token is not valid
+	pub fn session(&self, token: &str) -> Option<Session> {
+		self.lock().list.iter()
+			.find(|entry| constant_time_eq(&entry.token, token))
+			.map(|entry| Session {
+				token_id: entry.id,
+				role: entry.role.clone()
+			})
+	}
+
+	/// returns the role of the token or None if the token is not valid
+	pub fn role(&self, token: &str) -> Option<Role> {
+		self.session(token).map(|s| s.role)
+	}
+
+	/// checks that the request contains a valid token
+	pub fn check_header(&self, header: &RequestHeader) -> Result<Role, Error> {
+		let token = header.value(TOKEN_HEADER)
+			.ok_or_else(|| Error::Unauthorized("token missing".into()))?;
+
+		self.role(token)
+			.ok_or_else(|| Error::Unauthorized("invalid token".into()))
+	}
+
+	/// Like `check_header` but also accepts the token as `?token=`, for urls
+	/// which are opened by other programs which can't set headers.
+	pub fn check_header_or_query(
+		&self,
+		header: &RequestHeader
+	) -> Result<Session, Error> {
+		let token = header.value(TOKEN_HEADER)
+			.map(|token| token.to_string())
+			.or_else(|| header.to_url()
+			.and_then(|url| {
+				url.parse_query_pairs()
+					.find(|(key, _)| key == "token")
+					.map(|(_, value)| value.into_owned())
+			}))
+			.ok_or_else(|| Error::Unauthorized("token missing".into()))?;
+
+		self.session(&token)
+			.ok_or_else(|| Error::Unauthorized("invalid token".into()))
+	}
+
+	/// checks that the request contains a token which is allowed to manage
+	/// the paired devices
+	pub fn check_admin(&self, header: &RequestHeader) -> Result<(), Error> {
+		if self.check_header(header)?.is_admin() {
+			Ok(())
+		} else {
+			Err(Error::Forbidden("only operators can manage devices".into()))
+		}
+	}
+
+	/// generates a new pairing code and prints it to the console
+	fn new_pairing_code(&self, inner: &mut Inner, role: Role) -> String {
+		let max = 10u64.pow(PAIRING_CODE_DIGITS);
+		let code = format!(
+			"{:0width$}",
+			rand::thread_rng().gen_range(0..max),
+			width = PAIRING_CODE_DIGITS as usize
+		);
+
+		eprintln!("pairing code: {code}");
+		if let Some(ip) = local_ip() {
+			eprintln!(
+				"or open {}://{ip}:{}/?pair={code} on the device",
+				self.scheme,
+				self.port
+			);
+		}
+
+		inner.pairing_code = code.clone();
+		inner.pairing_role = role;
+		inner.pairing_expires_at = Instant::now() + PAIRING_CODE_LIFETIME;
+
+		code
+	}
+
+	/// the code can only be used once
+	fn redeem(
+		&self,
+		code: &str,
+		name: String,
+		ip: IpAddr
+	) -> Result<String, Error> {
+		let mut inner = self.lock();
+		let now = Instant::now();
+
+		let role = inner.use_pairing_code(code, ip, now);
+		// an expired or used up code get's replaced
+		if inner.pairing_expires_at <= now {
+			self.new_pairing_code(&mut inner, Role::default());
+		}
+		let role = role?;
+
+		let token = new_token();
+		let id = inner.list.iter().map(|e| e.id + 1).max().unwrap_or(0);
+		let created_at = SystemTime::now().duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		inner.list.push(TokenEntry {
+			id, name, role,
+			token: token.clone(),
+			created_at
+		});
+		save(&inner.list)
+			.map_err(|e| Error::Internal(e.to_string()))?;
+
+		Ok(token)
+	}
+
+	fn list(&self) -> Vec<TokenInfo> {
+		self.lock().list.iter()
+			.map(|entry| TokenInfo {
+				id: entry.id,
+				name: entry.name.clone(),
+				role: entry.role.clone(),
+				created_at: entry.created_at
+			})
+			.collect()
+	}
+
+	fn set_role(&self, id: u64, role: Role) -> Result<(), Error> {
+		let mut inner = self.lock();
+
+		let entry = inner.list.iter_mut()
+			.find(|entry| entry.id == id)
+			.ok_or_else(|| Error::Request(format!("token {id} not found")))?;
+		entry.role = role;
+
+		save(&inner.list)
+			.map_err(|e| Error::Internal(e.to_string()))
+	}
+
+	fn revoke(&self, id: u64) -> Result<(), Error> {
+		let mut inner = self.lock();
+
+		let prev_len = inner.list.len();
+		inner.list.retain(|entry| entry.id != id);
+		if inner.list.len() == prev_len {
+			return Err(Error::Request(format!("token {id} not found")))
+		}
+
+		save(&inner.list)
+			.map_err(|e| Error::Internal(e.to_string()))
+	}
+}
+
+fn save(list: &[TokenEntry]) -> io::Result<()> {
+	let dir = data_dir();
+	fs::create_dir_all(&dir)?;
+
+	let v = serde_json::to_vec_pretty(list)?;
+	fs::write(dir.join(TOKENS_FILE), v)
+}
+
+/// compares in constant time so the time does not reveal how many bytes
+/// matched
+fn constant_time_eq(a: &str, b: &str) -> bool {
+	if a.len() != b.len() {
+		return false
+	}
+
+	a.bytes().zip(b.bytes())
+		.fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+/// returns 32 random bytes hex encoded
+fn new_token() -> String {
+	let bytes: [u8; 32] = rand::thread_rng().gen();
+
+	let mut s = String::with_capacity(bytes.len() * 2);
+	for b in bytes {
+		write!(s, "{b:02x}").unwrap();
+	}
+
+	s
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TokenInfo {
+	pub id: u64,
+	pub name: String,
+	pub role: Role,
+	pub created_at: u64
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RedeemReq {
+	code: String,
+	/// a name to identify the device
+	name: String
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RedeemResp {
+	token: String
+}
+
+impl Request for RedeemReq {
+	type Response = RedeemResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/pairing/redeem";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(RedeemReq)]
+fn redeem(
+	req: RedeemReq,
+	header: &RequestHeader,
+	tokens: &Tokens
+) -> Result<RedeemResp, Error> {
+	tokens.redeem(&req.code, req.name, header.address().ip())
+		.map(|token| RedeemResp { token })
+}
+
+/// Generates a new pairing code so a paired device can show it to another
+/// device
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairingCodeReq {
+	/// the role the other device will get
+	#[serde(default)]
+	role: Role
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PairingCodeResp {
+	code: String
+}
+
+impl Request for PairingCodeReq {
+	type Response = PairingCodeResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/pairing/code";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(PairingCodeReq)]
+fn pairing_code(
+	req: PairingCodeReq,
+	header: &RequestHeader,
+	tokens: &Tokens
+) -> Result<PairingCodeResp, Error> {
+	tokens.check_admin(header)?;
+
+	let code = tokens.new_pairing_code(&mut tokens.lock(), req.role);
+	Ok(PairingCodeResp { code })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TokensReq;
+
+impl Request for TokensReq {
+	type Response = Vec<TokenInfo>;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/tokens";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(TokensReq)]
+fn tokens_list(
+	_req: TokensReq,
+	header: &RequestHeader,
+	tokens: &Tokens
+) -> Result<Vec<TokenInfo>, Error> {
+	tokens.check_admin(header)?;
+
+	Ok(tokens.list())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RevokeReq {
+	id: u64
+}
+
+impl Request for RevokeReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/tokens/revoke";
+	const METHOD: Method = Method::POST;
+}
+
+/// open connections of the token get closed
+#[api(RevokeReq)]
+fn revoke(
+	req: RevokeReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	connections: &Connections
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	tokens.revoke(req.id)?;
+	connections.update_role(req.id, None);
+
+	Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SetRoleReq {
+	id: u64,
+	role: Role
+}
+
+impl Request for SetRoleReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/tokens/role";
+	const METHOD: Method = Method::POST;
+}
+
+/// the new role is also applied to open connections
+#[api(SetRoleReq)]
+fn set_role(
+	req: SetRoleReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	connections: &Connections
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	tokens.set_role(req.id, req.role.clone())?;
+	connections.update_role(req.id, Some(req.role));
+
+	Ok(())
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(redeem);
+	fire.add_route(pairing_code);
+	fire.add_route(tokens_list);
+	fire.add_route(revoke);
+	fire.add_route(set_role);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::net::Ipv4Addr;
+
+	const CODE: &str = "0123456789";
+	const IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+	const OTHER_IP: IpAddr = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 11));
+
+	fn inner(now: Instant) -> Inner {
+		Inner {
+			list: vec![],
+			pairing_code: CODE.into(),
+			pairing_role: Role::Observer,
+			pairing_expires_at: now + PAIRING_CODE_LIFETIME,
+			failed_attempts: HashMap::new()
+		}
+	}
+
+	fn is_unauthorized(r: Result<Role, Error>) -> bool {
+		matches!(r, Err(Error::Unauthorized(_)))
+	}
+
+	fn is_locked(r: Result<Role, Error>) -> bool {
+		matches!(r, Err(Error::TooManyRequests(_)))
+	}
+
+	#[test]
+	fn pairing_code() {
+		let now = Instant::now();
+		let mut inner = inner(now);
+
+		assert!(is_unauthorized(inner.use_pairing_code("1", IP, now)));
+		assert_eq!(inner.use_pairing_code(CODE, IP, now), Ok(Role::Observer));
+		// a right code resets the wrong attempts
+		assert!(inner.failed_attempts.is_empty());
+	}
+
+	#[test]
+	fn single_use() {
+		let now = Instant::now();
+		let mut inner = inner(now);
+
+		assert!(inner.use_pairing_code(CODE, IP, now).is_ok());
+		assert!(is_unauthorized(inner.use_pairing_code(CODE, IP, now)));
+		assert!(is_unauthorized(inner.use_pairing_code(CODE, OTHER_IP, now)));
+	}
+
+	#[test]
+	fn expiry() {
+		let now = Instant::now();
+		let mut inner = inner(now);
+
+		let before = now + PAIRING_CODE_LIFETIME - Duration::from_secs(1);
+		assert!(inner.use_pairing_code(CODE, IP, before).is_ok());
+
+		let mut inner = self::inner(now);
+		let expired = now + PAIRING_CODE_LIFETIME;
+		assert!(is_unauthorized(inner.use_pairing_code(CODE, IP, expired)));
+	}
+
+	#[test]
+	fn lockout() {
+		let now = Instant::now();
+		let mut inner = inner(now);
+
+		for _ in 0..MAX_PAIRING_ATTEMPTS {
+			assert!(is_unauthorized(inner.use_pairing_code("1", IP, now)));
+		}
+
+		// even the right code is rejected
+		assert!(is_locked(inner.use_pairing_code(CODE, IP, now)));
+		assert!(is_locked(inner.use_pairing_code("1", IP, now)));
+
+		// other ips are not affected
+		assert!(is_unauthorized(inner.use_pairing_code("1", OTHER_IP, now)));
+
+		let later = now + PAIRING_LOCKOUT - Duration::from_secs(1);
+		assert!(is_locked(inner.use_pairing_code(CODE, IP, later)));
+
+		let later = now + PAIRING_LOCKOUT;
+		assert_eq!(
+			inner.use_pairing_code(CODE, IP, later),
+			Ok(Role::Observer)
+		);
+	}
+
+	#[test]
+	fn one_wrong_code_less_does_not_lock() {
+		let now = Instant::now();
+		let mut inner = inner(now);
+
+		for _ in 1..MAX_PAIRING_ATTEMPTS {
+			assert!(is_unauthorized(inner.use_pairing_code("1", IP, now)));
+		}
+
+		assert!(inner.use_pairing_code(CODE, IP, now).is_ok());
+	}
+
+	#[test]
+	fn can_input() {
+		assert!(!Role::Observer.can_input(None));
+		assert!(!Role::Observer.can_input(Some("UFC")));
+
+		let operator = Role::Operator { categories: None };
+		assert!(operator.can_input(None));
+		assert!(operator.can_input(Some("UFC")));
+
+		let restricted = Role::Operator {
+			categories: Some(vec!["UFC".into(), "Left MFCD".into()])
+		};
+		assert!(restricted.can_input(Some("UFC")));
+		assert!(restricted.can_input(Some("Left MFCD")));
+		assert!(!restricted.can_input(Some("Right MFCD")));
+		assert!(!restricted.can_input(Some("ufc")));
+		assert!(!restricted.can_input(None));
+
+		let none = Role::Operator { categories: Some(vec![]) };
+		assert!(!none.can_input(Some("UFC")));
+		assert!(!none.can_input(None));
+	}
+
+	#[test]
+	fn is_admin() {
+		assert!(!Role::Observer.is_admin());
+		assert!(Role::Operator { categories: None }.is_admin());
+		assert!(!Role::Operator { categories: Some(vec![]) }.is_admin());
+	}
+
+	#[test]
+	fn constant_time() {
+		assert!(constant_time_eq("", ""));
+		assert!(constant_time_eq("abc", "abc"));
+		assert!(!constant_time_eq("abc", "abd"));
+		assert!(!constant_time_eq("abc", "xbc"));
+		assert!(!constant_time_eq("abc", "ab"));
+		assert!(!constant_time_eq("ab", "abc"));
+		assert!(!constant_time_eq("", "a"));
+	}
+}
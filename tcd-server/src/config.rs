@@ -0,0 +1,77 @@
+use crate::adaptive::StreamLimits;
+
+use std::{env, fs, io};
+use std::path::PathBuf;
+use std::time::Duration;
+
+use serde::{Serialize, Deserialize};
+
+const CONFIG_FILE: &str = "config.json";
+
+/// Returns the directory where tcd stores it's data.
+///
+/// This is `%APPDATA%\tcd` or `tcd-data` in the current directory if
+/// APPDATA is not set. The directory might not exist yet.
+pub fn data_dir() -> PathBuf {
+	env::var("APPDATA")
+		.map(|appdata| PathBuf::from(appdata).join("tcd"))
+		.unwrap_or_else(|_| PathBuf::from("tcd-data"))
+}
+
+/// The configuration of the server, stored in `config.json` in the data
+/// directory.
+///
+/// Every field has a default so new fields can be added without breaking
+/// existing files.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+	/// controls which need to be armed before an input is forwarded
+	pub guarded_controls: Vec<String>,
+	/// how long a guarded control stays armed
+	pub guard_window_ms: u64,
+	/// serve https with a self signed certificate
+	pub tls: bool,
+	/// advertise the server on the local network so clients can find it
+	pub mdns: bool,
+	/// the bounds of the adaptive frame rate and quality of the mfds stream
+	pub stream: StreamLimits
+}
+
+impl Config {
+	/// Loads the config, if it does not exist yet the default config get's
+	/// written so it can be edited.
+	pub fn load() -> io::Result<Self> {
+		let path = data_dir().join(CONFIG_FILE);
+		match fs::read(&path) {
+			Ok(v) => serde_json::from_slice(&v).map_err(io::Error::other),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				let config = Self::default();
+				fs::create_dir_all(data_dir())?;
+				fs::write(path, serde_json::to_vec_pretty(&config)?)?;
+				Ok(config)
+			},
+			Err(e) => Err(e)
+		}
+	}
+
+	pub fn is_guarded(&self, name: &str) -> bool {
+		self.guarded_controls.iter().any(|c| c == name)
+	}
+
+	pub fn guard_window(&self) -> Duration {
+		Duration::from_millis(self.guard_window_ms)
+	}
+}
+
+impl Default for Config {
+	fn default() -> Self {
+		Self {
+			guarded_controls: vec![],
+			guard_window_ms: 3000,
+			tls: false,
+			mdns: true,
+			stream: StreamLimits::default()
+		}
+	}
+}
@@ -0,0 +1,257 @@
+use crate::api_error::Error;
+use crate::auth::{Tokens, Session, Role};
+use crate::adaptive::AdaptiveStats;
+
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::sync::watch;
+use tokio::time::{self, Duration, Instant, Interval, MissedTickBehavior};
+
+use serde::{Serialize, Deserialize};
+
+use fire::FireBuilder;
+use fire::header::RequestHeader;
+use fire::ws::{WebSocket, CloseCode};
+use fire_api::{api, Request, Method};
+
+/// how often the server sends a ping to the client
+pub const PING_INTERVAL: Duration = Duration::from_secs(5);
+/// if the client does not send anything (not even a pong) in this time it
+/// is considered dead and get's disconnected
+pub const LIVENESS_TIMEOUT: Duration = Duration::from_secs(15);
+/// if the client does not aknowledge a frame in this time it is considered
+/// stalled and get's resynced
+pub const ACK_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Keeps track of all websocket connections
+#[derive(Debug, Clone)]
+pub struct Connections {
+	inner: Arc<Mutex<Inner>>
+}
+
+#[derive(Debug)]
+struct Inner {
+	next_id: u64,
+	list: Vec<Arc<Stats>>
+}
+
+#[derive(Debug)]
+struct Stats {
+	id: u64,
+	endpoint: &'static str,
+	token_id: u64,
+	/// None if the token was revoked
+	role: watch::Sender<Option<Role>>,
+	connected_at: u64,
+	dropped_frames: AtomicU64,
+	ack_timeouts: AtomicU64,
+	adaptive: Mutex<Option<AdaptiveStats>>
+}
+
+impl Connections {
+	pub fn new() -> Self {
+		Self {
+			inner: Arc::new(Mutex::new(Inner {
+				next_id: 0,
+				list: vec![]
+			}))
+		}
+	}
+
+	/// the connection get's removed once the returned value is dropped
+	pub fn register(
+		&self,
+		endpoint: &'static str,
+		session: &Session
+	) -> Connection {
+		let mut inner = self.inner.lock().unwrap();
+
+		let connected_at = SystemTime::now().duration_since(UNIX_EPOCH)
+			.map(|d| d.as_secs())
+			.unwrap_or(0);
+
+		let (role, role_rx) = watch::channel(Some(session.role.clone()));
+		let stats = Arc::new(Stats {
+			id: inner.next_id,
+			endpoint,
+			token_id: session.token_id,
+			role,
+			connected_at,
+			dropped_frames: AtomicU64::new(0),
+			ack_timeouts: AtomicU64::new(0),
+			adaptive: Mutex::new(None)
+		});
+		inner.next_id += 1;
+		inner.list.push(stats.clone());
+
+		Connection {
+			connections: self.clone(),
+			stats,
+			role: role_rx
+		}
+	}
+
+	/// notifies all open connections of the token, None if the token was
+	/// revoked
+	pub fn update_role(&self, token_id: u64, role: Option<Role>) {
+		let inner = self.inner.lock().unwrap();
+
+		inner.list.iter()
+			.filter(|stats| stats.token_id == token_id)
+			.for_each(|stats| {
+				stats.role.send_replace(role.clone());
+			});
+	}
+
+	pub fn list(&self) -> Vec<ConnectionInfo> {
+		let inner = self.inner.lock().unwrap();
+
+		inner.list.iter()
+			.map(|stats| ConnectionInfo {
+				id: stats.id,
+				endpoint: stats.endpoint.into(),
+				connected_at: stats.connected_at,
+				dropped_frames: stats.dropped_frames.load(Ordering::Relaxed),
+				ack_timeouts: stats.ack_timeouts.load(Ordering::Relaxed),
+				adaptive: stats.adaptive.lock().unwrap().clone()
+			})
+			.collect()
+	}
+}
+
+#[derive(Debug)]
+pub struct Connection {
+	connections: Connections,
+	stats: Arc<Stats>,
+	role: watch::Receiver<Option<Role>>
+}
+
+impl Connection {
+	/// completes once the role of the token changed, returns None if the
+	/// token was revoked
+	pub async fn role_changed(&mut self) -> Option<Role> {
+		// the sender lives as long as the connection
+		let _ = self.role.changed().await;
+		self.role.borrow_and_update().clone()
+	}
+
+	/// a new frame could not be sent because the client has not aknowledged
+	/// the previous one
+	pub fn frame_dropped(&self) {
+		self.stats.dropped_frames.fetch_add(1, Ordering::Relaxed);
+	}
+
+	pub fn set_adaptive(&self, adaptive: AdaptiveStats) {
+		*self.stats.adaptive.lock().unwrap() = Some(adaptive);
+	}
+
+	/// the timeouts are shown in the connections list
+	pub fn ack_timed_out(&self) {
+		self.stats.ack_timeouts.fetch_add(1, Ordering::Relaxed);
+	}
+}
+
+impl Drop for Connection {
+	fn drop(&mut self) {
+		let mut inner = self.connections.inner.lock().unwrap();
+		inner.list.retain(|stats| stats.id != self.stats.id);
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Heartbeat {
+	Ping
+}
+
+/// Sends pings and detects clients which don't respond anymore
+#[derive(Debug)]
+pub struct Liveness {
+	interval: Interval,
+	last_received: Instant
+}
+
+impl Liveness {
+	pub fn new() -> Self {
+		let mut interval = time::interval_at(
+			Instant::now() + PING_INTERVAL,
+			PING_INTERVAL
+		);
+		interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+		Self {
+			interval,
+			last_received: Instant::now()
+		}
+	}
+
+	/// needs to be called every time a message from the client is received
+	pub fn received(&mut self) {
+		self.last_received = Instant::now();
+	}
+
+	/// completes when a ping should be sent
+	pub async fn tick(&mut self) {
+		self.interval.tick().await;
+	}
+
+	/// sends a ping or closes the connection if the client did not respond
+	/// for too long
+	///
+	/// Returns false if the connection was closed.
+	pub async fn ping(&self, ws: &mut WebSocket) -> Result<bool, Error> {
+		if self.last_received.elapsed() > LIVENESS_TIMEOUT {
+			ws.close(
+				CloseCode::Away,
+				"no response from the client".into()
+			).await;
+			return Ok(false)
+		}
+
+		ws.serialize(&Heartbeat::Ping).await
+			.map_err(|e| Error::Internal(e.to_string()))?;
+
+		Ok(true)
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionInfo {
+	pub id: u64,
+	pub endpoint: String,
+	/// unix timestamp in seconds
+	pub connected_at: u64,
+	/// frames which were not sent because the client did not aknowledge
+	pub dropped_frames: u64,
+	pub ack_timeouts: u64,
+	/// the current frame rate and quality of a mfds connection
+	pub adaptive: Option<AdaptiveStats>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConnectionsReq;
+
+impl Request for ConnectionsReq {
+	type Response = Vec<ConnectionInfo>;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/connections";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(ConnectionsReq)]
+fn connections_list(
+	_req: ConnectionsReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	connections: &Connections
+) -> Result<Vec<ConnectionInfo>, Error> {
+	tokens.check_header(header)?;
+
+	Ok(connections.list())
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(connections_list);
+}
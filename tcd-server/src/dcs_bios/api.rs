@@ -1,8 +1,16 @@
 use super::DcsBios;
 use super::controls::{Input, Outputs};
+use super::control_definitions::ControlDefinitions;
+use super::subscriptions::{Subscriptions, SubscribeOptions};
+use super::guards::Guards;
 use crate::api_error::Error;
+use crate::displays::DisplaySetup;
+use crate::handshake::{handshake, update_role, ServerHello, Encoding};
+use crate::connections::{Connections, Liveness, ACK_TIMEOUT};
+use crate::auth::Tokens;
+use crate::config::Config;
 
-use std::collections::HashSet;
+use tokio::time::{self, Instant};
 
 use serde::{Serialize, Deserialize};
 
@@ -13,14 +21,19 @@ use fire::ws::WebSocket;
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum Request {
 	Subscribe(String),
+	SubscribeWithOptions {
+		name: String,
+		options: SubscribeOptions
+	},
 	Unsubscribe(String),
+	/// needs to be sent before an input to a guarded control
+	Arm(String),
 	Input(Input),
-	Aknowledge
-}
-
-#[derive(Debug, Clone, Serialize, Deserialize)]
-struct Announce {
-	pub len: u32
+	/// puts a momentary control back, a guarded control which was pressed
+	/// can always be released to the value which belongs to the press
+	Release(Input),
+	Aknowledge,
+	Pong
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -29,43 +42,97 @@ struct Response {
 	pub outputs: Outputs
 }
 
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Notice {
+	/// the role of the client does not allow an input to this control
+	InputDenied(String),
+	/// the guarded control is armed and the next input within the window
+	/// will be forwarded
+	Armed {
+		name: String,
+		window_ms: u64
+	},
+	/// an input to a guarded control was sent without arming it first or
+	/// the window expired
+	NotArmed(String),
+	/// a recording is replayed, so inputs would change the live cockpit
+	Replaying(String)
+}
+
 #[ws("/api/controls/stream")]
-async fn ws_api(mut ws: WebSocket, dcs_bios: &DcsBios) -> Result<(), Error> {
+async fn ws_api(
+	mut ws: WebSocket,
+	dcs_bios: &DcsBios,
+	control_defs: &ControlDefinitions,
+	display_setup: &DisplaySetup,
+	connections: &Connections,
+	tokens: &Tokens,
+	config: &Config
+) -> Result<(), Error> {
+	let hello = ServerHello::new(dcs_bios, display_setup, config);
+	let (encoding, session) = match handshake(&mut ws, hello, tokens).await? {
+		Some((hello, session)) => (hello.encoding, session),
+		None => return Ok(())
+	};
+
+	let mut connection = connections.register("controls", &session);
+	let mut role = session.role;
+	let mut liveness = Liveness::new();
+
 	let mut dcs_bios = dcs_bios.clone();
-	let mut subscribed: HashSet<String> = HashSet::new();
+	let mut subscribed = Subscriptions::new();
+	let mut guards = Guards::new();
 	let mut was_aknowledged = true;
+	let mut sent_at = Instant::now();
 
 	loop {
-		tokio::select! {
-			_ = dcs_bios.changed(),
-				if !subscribed.is_empty() && was_aknowledged
-			=> {
-				// we need to store the responses before sending
-				// to hold the watch Lock as short as possible
-				let mut responses = Vec::with_capacity(subscribed.len());
-				{
-					let outputs = dcs_bios.borrow();
-					for name in &subscribed {
-						if let Some(outputs) = outputs.get(name) {
-							responses.push(Response {
-								name: name.clone(),
-								outputs: outputs.clone()
-							});
-						}
+		let deadline = subscribed.next_deadline();
+
+		let responses = tokio::select! {
+			_ = dcs_bios.changed(), if !subscribed.is_empty() => {
+				// the changes will be sent once the client aknowledges
+				if !was_aknowledged {
+					// most changes are to controls the client is not
+					// subscribed to
+					if subscribed.has_changes(&dcs_bios.borrow()) {
+						connection.frame_dropped();
 					}
+					continue
 				}
 
-				ws.serialize(&Announce {
-					len: responses.len() as u32
-				}).await
-					.map_err(|e| Error::Internal(e.to_string()))?;
+				// we need to store the responses before sending
+				// to hold the watch Lock as short as possible
+				subscribed.updates(&dcs_bios.borrow())
+			},
+			// a change was held back because of the deadband or rate limit
+			_ = time::sleep_until(deadline.unwrap_or_else(Instant::now)),
+				if deadline.is_some() && was_aknowledged
+			=> {
+				subscribed.updates(&dcs_bios.borrow())
+			},
+			// the client is stalled, let's send everything again
+			_ = time::sleep_until(sent_at + ACK_TIMEOUT), if !was_aknowledged => {
+				connection.ack_timed_out();
+				was_aknowledged = true;
 
-				for response in responses {
-					ws.serialize(&response).await
-						.map_err(|e| Error::Internal(e.to_string()))?;
+				subscribed.resync();
+				subscribed.updates(&dcs_bios.borrow())
+			},
+			_ = liveness.tick() => {
+				if !liveness.ping(&mut ws).await? {
+					return Ok(())
 				}
 
-				was_aknowledged = false;
+				continue
+			},
+			// inputs are checked against the new role
+			new_role = connection.role_changed() => {
+				role = match update_role(&mut ws, new_role).await? {
+					Some(r) => r,
+					None => return Ok(())
+				};
+
+				continue
 			},
 			req = ws.deserialize() => {
 				let req = req.map_err(|e| Error::Internal(e.to_string()))?;
@@ -74,25 +141,202 @@ async fn ws_api(mut ws: WebSocket, dcs_bios: &DcsBios) -> Result<(), Error> {
 					None => return Ok(())
 				};
 
+				liveness.received();
+
 				match req {
 					Request::Subscribe(name) => {
-						subscribed.insert(name);
+						subscribed.subscribe(name, SubscribeOptions::default());
+						continue
+					},
+					Request::SubscribeWithOptions { name, options } => {
+						subscribed.subscribe(name, options);
+						continue
 					},
 					Request::Unsubscribe(name) => {
-						subscribed.remove(&name);
+						subscribed.unsubscribe(&name);
+						continue
+					},
+					Request::Arm(name) => {
+						let category = control_defs.category(&name);
+						let notice = if !role.can_input(category.as_deref()) {
+							Notice::InputDenied(name)
+						} else {
+							guards.arm(name.clone(), config.guard_window());
+							Notice::Armed {
+								name,
+								window_ms: config.guard_window_ms
+							}
+						};
+
+						ws.serialize(&notice).await
+							.map_err(|e| Error::Internal(e.to_string()))?;
+
+						continue
 					},
 					Request::Input(inp) => {
-						dcs_bios.send(inp).await;
+						let name = inp.name().to_string();
+						let category = control_defs.category(&name);
+
+						if !role.can_input(category.as_deref()) {
+							ws.serialize(&Notice::InputDenied(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else if !dcs_bios.accepts_inputs() {
+							ws.serialize(&Notice::Replaying(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else if config.is_guarded(&name)
+							&& !guards.confirm(&name, inp.value())
+						{
+							ws.serialize(&Notice::NotArmed(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else {
+							// a playback could have started in the meantime
+							let _ = dcs_bios.send(inp).await;
+						}
+
+						continue
+					},
+					Request::Release(inp) => {
+						let name = inp.name().to_string();
+						let category = control_defs.category(&name);
+
+						if !role.can_input(category.as_deref()) {
+							ws.serialize(&Notice::InputDenied(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else if !dcs_bios.accepts_inputs() {
+							ws.serialize(&Notice::Replaying(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else if config.is_guarded(&name)
+							&& !guards.release(&name, inp.value())
+						{
+							ws.serialize(&Notice::NotArmed(name)).await
+								.map_err(|e| Error::Internal(e.to_string()))?;
+						} else {
+							// a playback could have started in the meantime
+							let _ = dcs_bios.send(inp).await;
+						}
+
+						continue
 					},
 					Request::Aknowledge => {
 						was_aknowledged = true;
-					}
+						// send everything that changed in the meantime
+						subscribed.updates(&dcs_bios.borrow())
+					},
+					Request::Pong => continue
 				}
 			}
+		};
+
+		// if nothing changed the client does not need to aknowledge anything
+		if responses.is_empty() {
+			continue
+		}
+
+		send_responses(&mut ws, encoding, responses).await?;
+
+		was_aknowledged = false;
+		sent_at = Instant::now();
+	}
+}
+
+async fn send_responses(
+	ws: &mut WebSocket,
+	encoding: Encoding,
+	responses: Vec<(String, Outputs)>
+) -> Result<(), Error> {
+	let responses: Vec<_> = responses.into_iter()
+		.map(|(name, outputs)| Response { name, outputs })
+		.collect();
+
+	match encoding {
+		Encoding::Json => {
+			ws.serialize(&responses).await
+				.map_err(|e| Error::Internal(e.to_string()))?;
+		},
+		Encoding::MessagePack => {
+			let bytes = rmp_serde::to_vec_named(&responses)
+				.map_err(|e| Error::Internal(e.to_string()))?;
+
+			ws.send(bytes).await
+				.map_err(|e| Error::Internal(e.to_string()))?;
 		}
 	}
+
+	Ok(())
 }
 
 pub(crate) fn handle(fire: &mut FireBuilder) {
 	fire.add_raw_route(ws_api);
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::{env, fs};
+	use std::fmt::Write;
+	use std::collections::BTreeMap;
+
+	use serde_json::{json, Value};
+
+	/// the ui tests it's MessagePack decoder against these
+	const FIXTURES: &str = concat!(
+		env!("CARGO_MANIFEST_DIR"),
+		"/../tcd-ui/src/lib/msgpack.fixtures.json"
+	);
+
+	fn hex(bytes: &[u8]) -> String {
+		bytes.iter().fold(String::new(), |mut s, b| {
+			write!(s, "{b:02x}").unwrap();
+			s
+		})
+	}
+
+	fn fixture(value: impl Serialize) -> Value {
+		let bytes = rmp_serde::to_vec_named(&value).unwrap();
+		json!({ "value": value, "msgpack": hex(&bytes) })
+	}
+
+	/// Set `UPDATE_FIXTURES=1` to write the fixtures after changing them.
+	#[test]
+	fn msgpack_fixtures() {
+		let responses: Vec<Response> = serde_json::from_value(json!([
+			{ "name": "UFC_COMM1_DISPLAY", "outputs": [{ "String": "12" }] },
+			{ "name": "PITCH", "outputs": [{ "Integer": -32768 }] },
+			{ "name": "LANDING_GEAR", "outputs": [
+				{ "Integer": 1 }, { "Integer": 255 }, { "Integer": 32767 }
+			] }
+		])).unwrap();
+
+		let long_list: Vec<u32> = (0..20).collect();
+		let long_map: BTreeMap<String, u8> = (0..20)
+			.map(|i| (format!("key{i}"), i))
+			.collect();
+
+		let fixtures = json!([
+			fixture(responses),
+			fixture(Vec::<Response>::new()),
+			fixture((u32::MAX, u64::from(u32::MAX) + 1, -129i64, i64::MIN)),
+			fixture((0.5f32, -1.25f64, true, false, ())),
+			fixture("ä".repeat(40)),
+			fixture("x".repeat(300)),
+			fixture(long_list),
+			fixture(long_map)
+		]);
+
+		let path = FIXTURES;
+		let json = serde_json::to_string_pretty(&fixtures).unwrap() + "\n";
+		if env::var_os("UPDATE_FIXTURES").is_some() {
+			fs::write(path, json).unwrap();
+			return
+		}
+
+		let current: Value = serde_json::from_str(
+			&fs::read_to_string(path).unwrap()
+		).unwrap();
+		assert!(
+			current == fixtures,
+			"the MessagePack fixtures are outdated, run with UPDATE_FIXTURES=1"
+		);
+	}
+}
@@ -42,10 +42,17 @@ impl ControlDefinitions {
 		})
 	}
 
-	pub(super) fn lock(&self) -> MutexGuard<InnerControlDefinitions> {
+	pub(super) fn lock(&self) -> MutexGuard<'_, InnerControlDefinitions> {
 		self.inner.lock().unwrap()
 	}
 
+	/// returns the category of a control of the metadata or the currently
+	/// loaded aircraft
+	pub fn category(&self, name: &str) -> Option<String> {
+		self.lock().control(name)
+			.map(|def| def.category.clone())
+	}
+
 	// pub fn control_outputs(&self, name: &str, buffer: &[u8]) -> Outputs {
 	// 	let defs = self.inner.lock().unwrap();
 	// 	defs.control_outputs(name, buffer)
@@ -113,17 +120,21 @@ impl InnerControlDefinitions {
 		})
 	}
 
-	pub fn control_outputs(
-		&self,
-		name: &str,
-		buffer: &[u8]
-	) -> Outputs {
+	fn control(&self, name: &str) -> Option<&RawControl> {
 		self.raw_metadata.get(name)
 			.or_else(|| {
 				self.aircraft.as_ref()
 					.and_then(|a| self.aircrafts.get(a))
 					.and_then(|a| a.raw_defs.get(name))
 			})
+	}
+
+	pub fn control_outputs(
+		&self,
+		name: &str,
+		buffer: &[u8]
+	) -> Outputs {
+		self.control(name)
 			.map(|def| def.outputs(buffer))
 			.unwrap_or_else(Outputs::new)
 	}
@@ -175,16 +186,13 @@ impl AicraftControlDefs {
 	}
 
 	fn specific_fixes(&mut self, aircraft: &str) {
-		match aircraft {
-			"F-16C_50" => {
-				// make output type of DED_LINE_1-5 DedLine
-				for i in 1..=5 {
-					let name = format!("DED_LINE_{}", i);
-					let control = self.raw_defs.get_mut(&name).unwrap();
-					control.outputs[0].kind = RawOutputKind::DedLine;
-				}
-			},
-			_ => {}
+		if aircraft == "F-16C_50" {
+			// make output type of DED_LINE_1-5 DedLine
+			for i in 1..=5 {
+				let name = format!("DED_LINE_{}", i);
+				let control = self.raw_defs.get_mut(&name).unwrap();
+				control.outputs[0].kind = RawOutputKind::DedLine;
+			}
 		}
 	}
 }
@@ -206,13 +214,12 @@ impl File {
 		let s = fs::read_to_string(path).await?;
 
 		serde_json::from_str(&s)
-			.map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+			.map_err(io::Error::other)
 	}
 
 	pub fn insert_defs(self, raw: &mut RawControls, defs: &mut ControlDefs) {
-		let controls = self.inner.into_iter()
-			.flat_map(|(_, controls)| controls.into_iter())
-			.map(|(name, val)| (name, val));
+		let controls = self.inner.into_values()
+			.flat_map(|controls| controls.into_iter());
 
 		for (name, control) in controls {
 			defs.insert(name.clone(), control.to_def());
@@ -245,7 +252,7 @@ impl RawControls {
 		self.inner.get_mut(name)
 	}
 
-	pub fn iter(&self) -> hash_map::Iter<String, RawControl> {
+	pub fn iter(&self) -> hash_map::Iter<'_, String, RawControl> {
 		self.inner.iter()
 	}
 }
@@ -386,7 +393,7 @@ impl RawOutput {
 
 				let mut string = String::with_capacity(str_bytes.len());
 
-				for (i, byte) in str_bytes.into_iter().enumerate() {
+				for (i, byte) in str_bytes.iter().enumerate() {
 					let mask = 1 << i as u32;
 					let is_inversed = (inverse & mask) > 0;
 					if is_inversed {
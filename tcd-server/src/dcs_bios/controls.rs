@@ -92,9 +92,13 @@ impl ControlOutputs {
 	pub fn insert(&mut self, name: String, outputs: Outputs) {
 		self.inner.insert(name, outputs);
 	}
+
+	pub fn iter(&self) -> impl Iterator<Item=(&String, &Outputs)> {
+		self.inner.iter()
+	}
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Outputs {
 	inner: Vec<Output>
@@ -113,6 +117,22 @@ impl Outputs {
 			_ => None
 		})
 	}
+
+	/// returns true if a string changed or an integer changed by at least
+	/// `min_change`
+	pub fn exceeds_deadband(&self, other: &Outputs, min_change: u16) -> bool {
+		if self.inner.len() != other.inner.len() {
+			return true
+		}
+
+		self.inner.iter().zip(&other.inner).any(|(a, b)| match (a, b) {
+			(Output::Integer(a), Output::Integer(b)) => {
+				a != b &&
+					(*a as i32 - *b as i32).unsigned_abs() >= min_change as u32
+			},
+			(a, b) => a != b
+		})
+	}
 }
 
 impl From<Vec<Output>> for Outputs {
@@ -121,7 +141,7 @@ impl From<Vec<Output>> for Outputs {
 	}
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Output {
 	String(String),
 	Integer(i16)
@@ -133,13 +153,23 @@ pub struct Input {
 	value: InputValue
 }
 
+impl Input {
+	pub fn name(&self) -> &str {
+		&self.name
+	}
+
+	pub fn value(&self) -> InputValue {
+		self.value
+	}
+}
+
 impl fmt::Display for Input {
 	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
 		write!(f, "{} {}", self.name, self.value)
 	}
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum InputValue {
 	Increase,
 	Decrease,
@@ -182,4 +212,43 @@ impl fmt::Display for InputValue {
 // 	// 3Pos2CommandSwitch,
 // 	// 3Pos2CommandSwitchOpenClose,
 // 	VariableStepDial
-// }
\ No newline at end of file
+// }
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn outputs(list: &[Output]) -> Outputs {
+		Outputs::from(list.to_vec())
+	}
+
+	#[test]
+	fn deadband() {
+		let a = outputs(&[Output::Integer(100)]);
+
+		assert!(!a.exceeds_deadband(&outputs(&[Output::Integer(109)]), 10));
+		assert!(a.exceeds_deadband(&outputs(&[Output::Integer(110)]), 10));
+		assert!(a.exceeds_deadband(&outputs(&[Output::Integer(90)]), 10));
+		// no deadband
+		assert!(!a.exceeds_deadband(&a, 0));
+		assert!(a.exceeds_deadband(&outputs(&[Output::Integer(101)]), 0));
+
+		// the difference does not overflow
+		let min = outputs(&[Output::Integer(i16::MIN)]);
+		let max = outputs(&[Output::Integer(i16::MAX)]);
+		assert!(min.exceeds_deadband(&max, u16::MAX));
+	}
+
+	#[test]
+	fn deadband_only_applies_to_integers() {
+		let a = outputs(&[Output::String("ON".into()), Output::Integer(1)]);
+		let b = outputs(&[Output::String("OFF".into()), Output::Integer(1)]);
+		assert!(a.exceeds_deadband(&b, 100));
+		assert!(!a.exceeds_deadband(&a.clone(), 100));
+
+		// a different kind or length is always a change
+		let c = outputs(&[Output::Integer(1)]);
+		assert!(a.exceeds_deadband(&c, 100));
+		let d = outputs(&[Output::String("1".into())]);
+		assert!(c.exceeds_deadband(&d, 100));
+	}
+}
@@ -0,0 +1,187 @@
+use super::controls::InputValue;
+
+use std::collections::HashMap;
+
+use tokio::time::{Duration, Instant};
+
+/// Keeps track of which guarded control a client has armed.
+///
+/// Only one control can be armed at a time and the arming is used up by
+/// the next input to it. A pressed control can then be released once, but
+/// only back to the value which belongs to the press.
+#[derive(Debug)]
+pub(super) struct Guards {
+	armed: Option<Armed>,
+	/// guarded controls which were pressed but not released yet with the
+	/// value they were pressed with, a release is always allowed so no
+	/// switch stays pressed
+	pressed: HashMap<String, InputValue>
+}
+
+#[derive(Debug)]
+struct Armed {
+	name: String,
+	until: Instant
+}
+
+impl Guards {
+	pub fn new() -> Self {
+		Self {
+			armed: None,
+			pressed: HashMap::new()
+		}
+	}
+
+	/// arms the control, replacing any other armed control
+	pub fn arm(&mut self, name: String, window: Duration) {
+		self.armed = Some(Armed {
+			name,
+			until: Instant::now() + window
+		});
+	}
+
+	/// returns true if the control was armed and the window has not
+	/// expired, this consumes the arming
+	pub fn confirm(&mut self, name: &str, value: InputValue) -> bool {
+		match &self.armed {
+			Some(armed) if armed.name == name => {
+				let valid = armed.until >= Instant::now();
+				self.armed = None;
+				if valid {
+					self.pressed.insert(name.to_string(), value);
+				}
+				valid
+			},
+			// an input to another control does not disarm
+			_ => false
+		}
+	}
+
+	/// returns true if the control was pressed and the value puts it back
+	pub fn release(&mut self, name: &str, value: InputValue) -> bool {
+		let pressed = match self.pressed.get(name) {
+			Some(p) => *p,
+			None => return false
+		};
+
+		if rest_value(pressed) != Some(value) {
+			return false
+		}
+
+		self.pressed.remove(name);
+		true
+	}
+}
+
+/// Returns the value which puts a momentary control back after it was
+/// pressed with `pressed`.
+///
+/// A push button goes from 1 back to 0, a three way switch from 0 or 2
+/// back to the center. Other inputs cannot be released.
+fn rest_value(pressed: InputValue) -> Option<InputValue> {
+	match pressed {
+		InputValue::Integer(1) => Some(InputValue::Integer(0)),
+		InputValue::Integer(0 | 2) => Some(InputValue::Integer(1)),
+		_ => None
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const WINDOW: Duration = Duration::from_secs(60);
+	const PRESS: InputValue = InputValue::Integer(1);
+	const RELEASE: InputValue = InputValue::Integer(0);
+
+	#[test]
+	fn arm_press_release() {
+		let mut guards = Guards::new();
+		assert!(!guards.confirm("MASTER_ARM", PRESS));
+
+		guards.arm("MASTER_ARM".into(), WINDOW);
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+		assert!(guards.release("MASTER_ARM", RELEASE));
+
+		// the pair consumed the arming
+		assert!(!guards.confirm("MASTER_ARM", PRESS));
+		assert!(!guards.release("MASTER_ARM", RELEASE));
+	}
+
+	#[test]
+	fn arming_is_single_use() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), WINDOW);
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+		assert!(!guards.confirm("MASTER_ARM", PRESS));
+		assert!(!guards.confirm("MASTER_ARM", InputValue::Toggle));
+	}
+
+	#[test]
+	fn release_without_press() {
+		let mut guards = Guards::new();
+		assert!(!guards.release("MASTER_ARM", RELEASE));
+
+		guards.arm("MASTER_ARM".into(), WINDOW);
+		assert!(!guards.release("MASTER_ARM", RELEASE));
+		// still armed
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+	}
+
+	#[test]
+	fn release_needs_the_rest_value() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), WINDOW);
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+
+		assert!(!guards.release("MASTER_ARM", InputValue::Toggle));
+		assert!(!guards.release("MASTER_ARM", InputValue::Integer(2)));
+		assert!(guards.release("MASTER_ARM", RELEASE));
+
+		// a three way switch goes back to the center
+		guards.arm("GEAR_LEVER".into(), WINDOW);
+		assert!(guards.confirm("GEAR_LEVER", InputValue::Integer(2)));
+		assert!(!guards.release("GEAR_LEVER", InputValue::Integer(0)));
+		assert!(guards.release("GEAR_LEVER", InputValue::Integer(1)));
+	}
+
+	#[test]
+	fn toggles_cannot_be_released() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), WINDOW);
+		assert!(guards.confirm("MASTER_ARM", InputValue::Toggle));
+
+		assert!(!guards.release("MASTER_ARM", InputValue::Toggle));
+		assert!(!guards.release("MASTER_ARM", RELEASE));
+	}
+
+	#[test]
+	fn release_after_the_window() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), Duration::from_millis(50));
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+
+		std::thread::sleep(Duration::from_millis(60));
+		// the switch was pressed so it can always be released
+		assert!(guards.release("MASTER_ARM", RELEASE));
+	}
+
+	#[test]
+	fn window_expires() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), Duration::ZERO);
+
+		std::thread::sleep(Duration::from_millis(2));
+		assert!(!guards.confirm("MASTER_ARM", PRESS));
+		assert!(!guards.release("MASTER_ARM", RELEASE));
+	}
+
+	#[test]
+	fn other_controls_do_not_disarm() {
+		let mut guards = Guards::new();
+		guards.arm("MASTER_ARM".into(), WINDOW);
+
+		assert!(!guards.confirm("GEAR_LEVER", PRESS));
+		assert!(guards.confirm("MASTER_ARM", PRESS));
+	}
+}
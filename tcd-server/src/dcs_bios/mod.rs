@@ -2,11 +2,15 @@ pub mod api;
 mod stream;
 use stream::Stream;
 pub mod controls;
+mod subscriptions;
+mod guards;
 use controls::{ControlOutputs, Input};
 pub mod control_definitions;
 use control_definitions::ControlDefinitions;
+use crate::debrief::Replaying;
 
-use std::io;
+use std::{io, fmt};
+use std::sync::Arc;
 
 use tokio::time::{self, Duration};
 use tokio::task::JoinHandle;
@@ -15,32 +19,47 @@ use tokio::sync::{watch, mpsc};
 #[derive(Debug, Clone)]
 pub(crate) struct DcsBios {
 	recv: watch::Receiver<ControlOutputs>,
-	sender: mpsc::Sender<Input>
+	/// used to replay recordings
+	tx: Arc<watch::Sender<ControlOutputs>>,
+	sender: mpsc::Sender<Input>,
+	/// inputs would change the live cockpit while a recording is shown
+	replaying: Replaying
 }
 
 impl DcsBios {
-	pub fn new(control_defs: ControlDefinitions) -> (Self, JoinHandle<()>) {
+	/// while replaying the outputs from dcs-bios are ignored
+	pub fn new(
+		control_defs: ControlDefinitions,
+		replaying: Replaying
+	) -> (Self, JoinHandle<()>) {
 		let (tx, rx) = watch::channel(ControlOutputs::new());
+		let tx = Arc::new(tx);
 		let (tx_2, rx_2) = mpsc::channel(20);
 
 		let this = Self {
 			recv: rx,
-			sender: tx_2
+			tx: tx.clone(),
+			sender: tx_2,
+			replaying: replaying.clone()
 		};
 
 		let task = tokio::spawn(async move {
-			let tx = tx;
 			let mut rx = rx_2;
 
 			loop {
-				let r = stream_task(control_defs.clone(), &tx, &mut rx).await;
+				let r = stream_task(
+					control_defs.clone(),
+					&tx,
+					&mut rx,
+					&replaying
+				).await;
 				match r {
 					Ok(_) => break,
 					Err(Error::Connecting(_)) => {
 						time::sleep(Duration::from_secs(5)).await;
 					},
 					Err(e) => {
-						eprintln!("dcs-bios error {:?}", e);
+						eprintln!("dcs-bios error {e}");
 						time::sleep(Duration::from_secs(1)).await;
 					}
 				}
@@ -58,8 +77,32 @@ impl DcsBios {
 		self.recv.borrow()
 	}
 
-	pub async fn send(&self, input: Input) {
+	/// returns the currently loaded aircraft
+	pub fn aircraft(&self) -> Option<String> {
+		self.recv.borrow().get("_ACFT_NAME")
+			.and_then(|outputs| outputs.clone().into_string())
+			.filter(|aircraft| !aircraft.is_empty())
+	}
+
+	/// replaces all outputs, used to replay recordings
+	pub fn replace_outputs(&self, outputs: ControlOutputs) {
+		self.tx.send_replace(outputs);
+	}
+
+	/// inputs are not accepted while a recording is replayed
+	pub fn accepts_inputs(&self) -> bool {
+		!self.replaying.is_active()
+	}
+
+	/// returns false if the input was dropped because a recording is
+	/// replayed
+	pub async fn send(&self, input: Input) -> bool {
+		if !self.accepts_inputs() {
+			return false
+		}
+
 		self.sender.send(input).await.expect("dcs-bios task failed");
+		true
 	}
 }
 
@@ -69,10 +112,20 @@ enum Error {
 	Transmission(io::Error)
 }
 
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Connecting(e) => write!(f, "could not connect {e}"),
+			Self::Transmission(e) => write!(f, "transmission failed {e}")
+		}
+	}
+}
+
 async fn stream_task(
 	control_defs: ControlDefinitions,
 	tx: &watch::Sender<ControlOutputs>,
-	rx: &mut mpsc::Receiver<Input>
+	rx: &mut mpsc::Receiver<Input>,
+	replaying: &Replaying
 ) -> Result<(), Error> {
 	let mut stream = Stream::connect().await
 		.map_err(Error::Connecting)?;
@@ -113,7 +166,9 @@ async fn stream_task(
 
 			drop(defs);
 
-			tx.send_replace(outputs);
+			if !replaying.is_active() {
+				tx.send_replace(outputs);
+			}
 		}
 
 		// check if we should send something
@@ -123,4 +178,36 @@ async fn stream_task(
 				.map_err(Error::Transmission)?;
 		}
 	}
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use serde_json::json;
+
+	#[tokio::test]
+	async fn inputs_are_dropped_while_replaying() {
+		let replaying = Replaying::new();
+		let (tx, recv) = watch::channel(ControlOutputs::new());
+		let (sender, mut rx) = mpsc::channel(20);
+		let dcs_bios = DcsBios {
+			recv,
+			tx: Arc::new(tx),
+			sender,
+			replaying: replaying.clone()
+		};
+		let input: Input = serde_json::from_value(json!({
+			"name": "MASTER_ARM",
+			"value": "Toggle"
+		})).unwrap();
+
+		replaying.set(true);
+		assert!(!dcs_bios.accepts_inputs());
+		assert!(!dcs_bios.send(input.clone()).await);
+		assert!(rx.try_recv().is_err());
+
+		replaying.set(false);
+		assert!(dcs_bios.send(input).await);
+		assert_eq!(rx.try_recv().unwrap().name(), "MASTER_ARM");
+	}
+}
@@ -0,0 +1,329 @@
+use super::controls::{ControlOutputs, Outputs};
+
+use std::collections::HashMap;
+
+use tokio::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+
+/// the longest time a change below the deadband is held back before it
+/// get's sent anyway, so jitter below the deadband is flushed at 2 Hz and
+/// the final value of a needle which came to rest is always delivered
+const MAX_DEADBAND_DELAY: Duration = Duration::from_millis(500);
+
+/// lower rates are clamped, an update every 10 seconds is slow enough
+const MIN_RATE: f32 = 0.1;
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SubscribeOptions {
+	/// the minimum difference an integer output needs to have compared to
+	/// the last sent value before it get's sent again
+	#[serde(default)]
+	pub min_change: Option<u16>,
+	/// the maximum amount of updates per second, at least `MIN_RATE`
+	#[serde(default)]
+	pub max_rate: Option<f32>
+}
+
+impl SubscribeOptions {
+	fn min_interval(&self) -> Option<Duration> {
+		self.max_rate
+			.filter(|rate| *rate > 0.0)
+			.map(|rate| Duration::from_secs_f32(1.0 / rate.max(MIN_RATE)))
+	}
+}
+
+#[derive(Debug)]
+struct Subscription {
+	options: SubscribeOptions,
+	last_sent: Option<Outputs>,
+	last_sent_at: Option<Instant>,
+	pending: Option<Pending>
+}
+
+/// A change which could not be sent yet
+#[derive(Debug, Clone, Copy)]
+struct Pending {
+	since: Instant,
+	// if the change exceeded the deadband
+	significant: bool
+}
+
+impl Subscription {
+	fn new(options: SubscribeOptions) -> Self {
+		Self {
+			options,
+			last_sent: None,
+			last_sent_at: None,
+			pending: None
+		}
+	}
+
+	fn rate_deadline(&self) -> Option<Instant> {
+		let interval = self.options.min_interval()?;
+		self.last_sent_at.map(|at| at + interval)
+	}
+
+	/// returns the time at which the pending change can be sent
+	fn deadline(&self) -> Option<Instant> {
+		let pending = self.pending?;
+		let settled = if pending.significant {
+			pending.since
+		} else {
+			pending.since + MAX_DEADBAND_DELAY
+		};
+
+		Some(match self.rate_deadline() {
+			Some(rate) => rate.max(settled),
+			None => settled
+		})
+	}
+
+	/// returns the outputs if they should be sent now
+	fn update(&mut self, outputs: &Outputs, now: Instant) -> Option<Outputs> {
+		let significant = match &self.last_sent {
+			Some(last) if last == outputs => {
+				self.pending = None;
+				return None
+			},
+			Some(last) => last.exceeds_deadband(
+				outputs,
+				self.options.min_change.unwrap_or(0)
+			),
+			None => true
+		};
+
+		let pending = self.pending.get_or_insert(Pending {
+			since: now,
+			significant
+		});
+		pending.significant |= significant;
+
+		if self.deadline().map(|d| d > now).unwrap_or(false) {
+			return None
+		}
+
+		self.last_sent = Some(outputs.clone());
+		self.last_sent_at = Some(now);
+		self.pending = None;
+
+		Some(outputs.clone())
+	}
+}
+
+/// Keeps track of what was sent to a client for each subscribed control.
+#[derive(Debug)]
+pub(super) struct Subscriptions {
+	inner: HashMap<String, Subscription>
+}
+
+impl Subscriptions {
+	pub fn new() -> Self {
+		Self {
+			inner: HashMap::new()
+		}
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.inner.is_empty()
+	}
+
+	/// subscribing again resets the state so the current value will be
+	/// sent with the next update
+	pub fn subscribe(&mut self, name: String, options: SubscribeOptions) {
+		self.inner.insert(name, Subscription::new(options));
+	}
+
+	pub fn unsubscribe(&mut self, name: &str) {
+		self.inner.remove(name);
+	}
+
+	/// forgets what was sent, so the next update contains every value
+	pub fn resync(&mut self) {
+		for sub in self.inner.values_mut() {
+			*sub = Subscription::new(sub.options.clone());
+		}
+	}
+
+	/// the earliest time at which a pending change can be sent
+	pub fn next_deadline(&self) -> Option<Instant> {
+		self.inner.values()
+			.filter_map(Subscription::deadline)
+			.min()
+	}
+
+	/// returns true if a subscribed output differs from what was sent
+	pub fn has_changes(&self, outputs: &ControlOutputs) -> bool {
+		self.inner.iter()
+			.any(|(name, sub)| {
+				let outputs = match outputs.get(name) {
+					Some(o) => o,
+					None => return false
+				};
+				sub.last_sent.as_ref() != Some(outputs)
+			})
+	}
+
+	/// returns all outputs which should be sent to the client
+	pub fn updates(
+		&mut self,
+		outputs: &ControlOutputs
+	) -> Vec<(String, Outputs)> {
+		let now = Instant::now();
+
+		self.inner.iter_mut()
+			.filter_map(|(name, sub)| {
+				let outputs = outputs.get(name)?;
+				sub.update(outputs, now)
+					.map(|outputs| (name.clone(), outputs))
+			})
+			.collect()
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::dcs_bios::controls::Output;
+
+	const MS: Duration = Duration::from_millis(1);
+
+	fn integer(value: i16) -> Outputs {
+		Outputs::from(vec![Output::Integer(value)])
+	}
+
+	fn subscription(
+		min_change: Option<u16>,
+		max_rate: Option<f32>
+	) -> Subscription {
+		Subscription::new(SubscribeOptions { min_change, max_rate })
+	}
+
+	#[test]
+	fn only_changes_are_sent() {
+		let mut sub = subscription(None, None);
+		let now = Instant::now();
+
+		assert_eq!(sub.update(&integer(1), now), Some(integer(1)));
+		assert_eq!(sub.update(&integer(1), now), None);
+		assert_eq!(sub.update(&integer(2), now), Some(integer(2)));
+		assert_eq!(sub.deadline(), None);
+	}
+
+	#[test]
+	fn small_changes_are_held_back() {
+		let mut sub = subscription(Some(10), None);
+		let start = Instant::now();
+
+		// the first value is always sent
+		assert_eq!(sub.update(&integer(100), start), Some(integer(100)));
+
+		assert_eq!(sub.update(&integer(105), start), None);
+		assert_eq!(sub.deadline(), Some(start + MAX_DEADBAND_DELAY));
+		// the pending change keeps it's time
+		let later = start + 100 * MS;
+		assert_eq!(sub.update(&integer(106), later), None);
+		assert_eq!(sub.deadline(), Some(start + MAX_DEADBAND_DELAY));
+
+		// the settled value get's delivered after the delay
+		let flush = start + MAX_DEADBAND_DELAY;
+		assert_eq!(sub.update(&integer(107), flush), Some(integer(107)));
+		assert_eq!(sub.deadline(), None);
+
+		// a big change is sent immediately
+		assert_eq!(sub.update(&integer(117), flush), Some(integer(117)));
+	}
+
+	#[test]
+	fn going_back_clears_the_pending_change() {
+		let mut sub = subscription(Some(10), None);
+		let start = Instant::now();
+
+		sub.update(&integer(100), start);
+		assert_eq!(sub.update(&integer(105), start), None);
+		assert_eq!(sub.update(&integer(100), start + MS), None);
+		assert_eq!(sub.deadline(), None);
+	}
+
+	#[test]
+	fn rate_limit() {
+		// at most every 250ms
+		let mut sub = subscription(None, Some(4.0));
+		let start = Instant::now();
+
+		assert_eq!(sub.update(&integer(1), start), Some(integer(1)));
+		assert_eq!(sub.update(&integer(2), start + 10 * MS), None);
+		assert_eq!(sub.deadline(), Some(start + 250 * MS));
+		assert_eq!(sub.update(&integer(3), start + 50 * MS), None);
+
+		// the latest value is sent once the deadline is reached
+		let deadline = start + 250 * MS;
+		assert_eq!(sub.update(&integer(3), deadline), Some(integer(3)));
+		assert_eq!(sub.update(&integer(4), deadline + MS), None);
+		assert_eq!(sub.deadline(), Some(deadline + 250 * MS));
+	}
+
+	#[test]
+	fn rate_limit_and_deadband() {
+		let mut sub = subscription(Some(10), Some(4.0));
+		let start = Instant::now();
+
+		sub.update(&integer(0), start);
+		// a small change waits for the deadband delay not only the rate
+		assert_eq!(sub.update(&integer(1), start + 200 * MS), None);
+		assert_eq!(
+			sub.deadline(),
+			Some(start + 200 * MS + MAX_DEADBAND_DELAY)
+		);
+
+		// a big change only waits for the rate
+		let mut sub = subscription(Some(10), Some(4.0));
+		sub.update(&integer(0), start);
+		assert_eq!(sub.update(&integer(50), start + 10 * MS), None);
+		assert_eq!(sub.deadline(), Some(start + 250 * MS));
+	}
+
+	#[test]
+	fn subscriptions_deliver_the_settled_value() {
+		let mut subs = Subscriptions::new();
+		subs.subscribe("ALT".into(), SubscribeOptions {
+			min_change: Some(10),
+			max_rate: None
+		});
+
+		let mut outputs = ControlOutputs::new();
+		outputs.insert("ALT".into(), integer(100));
+		assert_eq!(subs.updates(&outputs).len(), 1);
+		assert!(!subs.has_changes(&outputs));
+
+		outputs.insert("ALT".into(), integer(101));
+		assert!(subs.has_changes(&outputs));
+		assert!(subs.updates(&outputs).is_empty());
+		let deadline = subs.next_deadline().unwrap();
+		assert!(deadline > Instant::now());
+
+		std::thread::sleep(MAX_DEADBAND_DELAY);
+		assert_eq!(subs.updates(&outputs), [("ALT".into(), integer(101))]);
+		assert_eq!(subs.next_deadline(), None);
+
+		// a resync sends everything again
+		subs.resync();
+		assert_eq!(subs.updates(&outputs).len(), 1);
+	}
+
+	fn min_interval(max_rate: f32) -> Option<Duration> {
+		SubscribeOptions { min_change: None, max_rate: Some(max_rate) }
+			.min_interval()
+	}
+
+	#[test]
+	fn rates() {
+		assert_eq!(min_interval(4.0), Some(Duration::from_millis(250)));
+		assert_eq!(min_interval(0.0), None);
+		assert_eq!(min_interval(-1.0), None);
+		assert_eq!(min_interval(f32::NAN), None);
+		assert_eq!(min_interval(f32::INFINITY), Some(Duration::ZERO));
+		// would overflow the duration
+		assert_eq!(min_interval(1e-40), Some(Duration::from_secs(10)));
+	}
+}
@@ -0,0 +1,287 @@
+use super::{Recorder, Playback};
+use super::archive;
+use super::playback::PlaybackStatus;
+use crate::api_error::Error;
+use crate::auth::Tokens;
+
+use std::{fs, io};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+
+use fire::FireBuilder;
+use fire::header::RequestHeader;
+use fire_api::{api, Request, Method};
+
+fn unix_secs(time: SystemTime) -> u64 {
+	time.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordingInfo {
+	name: String,
+	/// in bytes
+	size: u64,
+	/// unix timestamp in seconds
+	created_at: u64
+}
+
+fn list_recordings() -> io::Result<Vec<RecordingInfo>> {
+	let dir = match fs::read_dir(archive::recordings_dir()) {
+		Ok(dir) => dir,
+		Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+		Err(e) => return Err(e)
+	};
+
+	let mut list = vec![];
+	for entry in dir {
+		let entry = entry?;
+		let path = entry.path();
+		if path.extension().map(|e| e != archive::EXTENSION).unwrap_or(true) {
+			continue
+		}
+		let name = match path.file_stem().and_then(|s| s.to_str()) {
+			Some(n) => n.to_string(),
+			None => continue
+		};
+
+		let metadata = entry.metadata()?;
+		let created_at = metadata.created()
+			.or_else(|_| metadata.modified())
+			.map(unix_secs)
+			.unwrap_or(0);
+		list.push(RecordingInfo { name, size: metadata.len(), created_at });
+	}
+
+	// newest first
+	list.sort_by_key(|r| std::cmp::Reverse(r.created_at));
+
+	Ok(list)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordingsReq;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct RecordingsResp {
+	recordings: Vec<RecordingInfo>,
+	/// the name of the running recording
+	recording: Option<String>,
+	playback: Option<PlaybackStatus>
+}
+
+impl Request for RecordingsReq {
+	type Response = RecordingsResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/recordings";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(RecordingsReq)]
+fn recordings_list(
+	_req: RecordingsReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	recorder: &Recorder,
+	playback: &Playback
+) -> Result<RecordingsResp, Error> {
+	tokens.check_header(header)?;
+
+	Ok(RecordingsResp {
+		recordings: list_recordings()
+			.map_err(|e| Error::Internal(e.to_string()))?,
+		recording: recorder.current(),
+		playback: playback.status()
+	})
+}
+
+/// Starts recording the frames and outputs, if no name is given
+/// `session-{unix timestamp}` is used
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StartRecordingReq {
+	#[serde(default)]
+	name: Option<String>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StartRecordingResp {
+	name: String
+}
+
+impl Request for StartRecordingReq {
+	type Response = StartRecordingResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/recordings/start";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(StartRecordingReq)]
+fn recording_start(
+	req: StartRecordingReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	recorder: &Recorder,
+	playback: &Playback
+) -> Result<StartRecordingResp, Error> {
+	tokens.check_admin(header)?;
+
+	// the replayed data should not be recorded again
+	if playback.status().is_some() {
+		return Err(Error::Request("cannot record while replaying".into()))
+	}
+
+	let name = req.name.unwrap_or_else(|| {
+		format!("session-{}", unix_secs(SystemTime::now()))
+	});
+	recorder.start(name.clone())?;
+
+	Ok(StartRecordingResp { name })
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StopRecordingReq;
+
+impl Request for StopRecordingReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/recordings/stop";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(StopRecordingReq)]
+fn recording_stop(
+	_req: StopRecordingReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	recorder: &Recorder
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	if !recorder.stop() {
+		return Err(Error::Request("not recording".into()))
+	}
+
+	Ok(())
+}
+
+/// Replays a recording through `/api/mfds` and `/api/controls/stream`
+/// instead of the live data
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StartPlaybackReq {
+	name: String
+}
+
+impl Request for StartPlaybackReq {
+	type Response = PlaybackStatus;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/playback/start";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(StartPlaybackReq)]
+async fn playback_start(
+	req: StartPlaybackReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	recorder: &Recorder,
+	playback: &Playback
+) -> Result<PlaybackStatus, Error> {
+	tokens.check_admin(header)?;
+
+	if recorder.current().is_some() {
+		return Err(Error::Request("cannot replay while recording".into()))
+	}
+
+	playback.start(req.name).await
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct StopPlaybackReq;
+
+impl Request for StopPlaybackReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/playback/stop";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(StopPlaybackReq)]
+async fn playback_stop(
+	_req: StopPlaybackReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	playback: &Playback
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	playback.stop().await;
+
+	Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct PausePlaybackReq {
+	paused: bool
+}
+
+impl Request for PausePlaybackReq {
+	type Response = PlaybackStatus;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/playback/pause";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(PausePlaybackReq)]
+fn playback_pause(
+	req: PausePlaybackReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	playback: &Playback
+) -> Result<PlaybackStatus, Error> {
+	tokens.check_admin(header)?;
+
+	playback.set_paused(req.paused)
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SeekPlaybackReq {
+	position_ms: u64
+}
+
+impl Request for SeekPlaybackReq {
+	type Response = PlaybackStatus;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/playback/seek";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(SeekPlaybackReq)]
+fn playback_seek(
+	req: SeekPlaybackReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	playback: &Playback
+) -> Result<PlaybackStatus, Error> {
+	tokens.check_admin(header)?;
+
+	playback.seek(req.position_ms)
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(recordings_list);
+	fire.add_route(recording_start);
+	fire.add_route(recording_stop);
+	fire.add_route(playback_start);
+	fire.add_route(playback_stop);
+	fire.add_route(playback_pause);
+	fire.add_route(playback_seek);
+}
@@ -0,0 +1,281 @@
+use crate::config::data_dir;
+use crate::displays::FrameFormat;
+use crate::dcs_bios::controls::Outputs;
+
+use std::io::{self, Read, Seek, SeekFrom, BufReader};
+use std::fs::File;
+use std::path::{Path, PathBuf};
+
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+
+use serde::{Serialize, Deserialize};
+
+const MAGIC: &[u8; 6] = b"TCDREC";
+const VERSION: u8 = 1;
+const HEADER_LEN: u64 = MAGIC.len() as u64 + 1;
+pub const EXTENSION: &str = "tcdrec";
+
+/// the directory where recordings are stored, might not exist yet
+pub fn recordings_dir() -> PathBuf {
+	data_dir().join("recordings")
+}
+
+/// only allow simple names, so they can't escape the recordings directory
+pub fn valid_name(name: &str) -> bool {
+	!name.is_empty() && name.len() <= 64 &&
+		name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+pub fn path(name: &str) -> PathBuf {
+	recordings_dir().join(format!("{name}.{EXTENSION}"))
+}
+
+/// Describes what the data of a record contains
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub enum Meta {
+	Keyframe {
+		name: String,
+		format: FrameFormat
+	},
+	Tile {
+		name: String,
+		format: FrameFormat,
+		x: u32,
+		y: u32
+	},
+	/// only the outputs which changed, the record contains no data
+	Outputs(Vec<(String, Outputs)>)
+}
+
+fn invalid(e: impl ToString) -> io::Error {
+	io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+}
+
+// ┌──────┬───────┐
+// │Magic │Version│
+// ├──────┼───────┤
+// │  48  │   8   │
+// └──────┴───────┘
+//
+// Followed by records until the end of the file
+// ┌──┬───────┬────┬───────┬────────┐
+// │At│MetaLen│Meta│DataLen│  Data  │
+// ├──┼───────┼────┼───────┼────────┤
+// │64│  32   │ .. │  32   │$Len*8  │
+// └──┴───────┴────┴───────┴────────┘
+// At is in milliseconds since the start of the recording, Meta is
+// messagepack
+pub async fn write_header<W>(writer: &mut W) -> io::Result<()>
+where W: AsyncWrite + Unpin {
+	writer.write_all(MAGIC).await?;
+	writer.write_u8(VERSION).await
+}
+
+pub async fn write_record<W>(
+	writer: &mut W,
+	at_ms: u64,
+	meta: &Meta,
+	data: &[u8]
+) -> io::Result<()>
+where W: AsyncWrite + Unpin {
+	let meta = rmp_serde::to_vec(meta).map_err(invalid)?;
+
+	writer.write_u64(at_ms).await?;
+	writer.write_u32(meta.len() as u32).await?;
+	writer.write_all(&meta).await?;
+	writer.write_u32(data.len() as u32).await?;
+	writer.write_all(data).await
+}
+
+/// Where a record is stored in the file, outputs are kept in memory since
+/// they are small and all of them are needed to seek.
+#[derive(Debug, Clone)]
+pub struct Entry {
+	pub at_ms: u64,
+	/// the offset of the data
+	pub offset: u64,
+	pub len: u32,
+	pub meta: Meta
+}
+
+impl Entry {
+	/// the display the entry belongs to
+	pub fn display(&self) -> Option<&str> {
+		match &self.meta {
+			Meta::Keyframe { name, .. } | Meta::Tile { name, .. } => Some(name),
+			Meta::Outputs(_) => None
+		}
+	}
+}
+
+/// An opened recording
+#[derive(Debug)]
+pub struct Archive {
+	file: File,
+	pub entries: Vec<Entry>
+}
+
+impl Archive {
+	/// Reads the meta data of every record, a record whose data was not
+	/// written completely (for example because the server crashed) is
+	/// ignored.
+	///
+	/// Returns an error if the meta data would not fit into the file, the
+	/// length cannot be trusted then.
+	pub fn open(path: &Path) -> io::Result<Self> {
+		let mut reader = BufReader::new(File::open(path)?);
+		let file_len = reader.get_ref().metadata()?.len();
+
+		let mut header = [0u8; HEADER_LEN as usize];
+		reader.read_exact(&mut header)?;
+		if &header[..MAGIC.len()] != MAGIC {
+			return Err(invalid("not a recording"))
+		}
+		if header[MAGIC.len()] != VERSION {
+			return Err(invalid("unsupported recording version"))
+		}
+
+		let mut entries = vec![];
+		let mut pos = HEADER_LEN;
+		let mut meta_buf = vec![];
+		loop {
+			let mut fixed = [0u8; 12];
+			match reader.read_exact(&mut fixed) {
+				Ok(_) => {},
+				Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+				Err(e) => return Err(e)
+			}
+			let at_ms = u64::from_be_bytes(fixed[..8].try_into().unwrap());
+			let meta_len = u32::from_be_bytes(fixed[8..].try_into().unwrap());
+
+			// the length get's checked before allocating, so a corrupt
+			// file cannot allocate gigabytes
+			if pos + 12 + meta_len as u64 + 4 > file_len {
+				return Err(invalid("record is longer than the recording"))
+			}
+
+			meta_buf.resize(meta_len as usize, 0);
+			let mut len = [0u8; 4];
+			let r = reader.read_exact(&mut meta_buf)
+				.and_then(|_| reader.read_exact(&mut len));
+			match r {
+				Ok(_) => {},
+				Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => break,
+				Err(e) => return Err(e)
+			}
+			let len = u32::from_be_bytes(len);
+			let offset = pos + 12 + meta_len as u64 + 4;
+			if offset + len as u64 > file_len {
+				break
+			}
+
+			let meta = rmp_serde::from_slice(&meta_buf).map_err(invalid)?;
+			reader.seek_relative(len as i64)?;
+
+			entries.push(Entry { at_ms, offset, len, meta });
+			pos = offset + len as u64;
+		}
+
+		Ok(Self {
+			file: reader.into_inner(),
+			entries
+		})
+	}
+
+	pub fn duration_ms(&self) -> u64 {
+		self.entries.last().map(|e| e.at_ms).unwrap_or(0)
+	}
+
+	/// reads the data of the entry at the index
+	pub fn read_data(&mut self, i: usize) -> io::Result<Vec<u8>> {
+		let entry = &self.entries[i];
+		let mut data = vec![0; entry.len as usize];
+		self.file.seek(SeekFrom::Start(entry.offset))?;
+		self.file.read_exact(&mut data)?;
+
+		Ok(data)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::dcs_bios::controls::Output;
+
+	use std::fs;
+
+	#[tokio::test]
+	async fn round_trip() {
+		let path = std::env::temp_dir()
+			.join(format!("tcd-archive-{}.{EXTENSION}", std::process::id()));
+
+		let outputs = vec![(
+			"MASTER_ARM".to_string(),
+			Outputs::from(vec![
+				Output::Integer(1),
+				Output::String("ON".into())
+			])
+		)];
+		let records = [
+			(0, Meta::Keyframe {
+				name: "LEFT".into(),
+				format: FrameFormat::Jpeg
+			}, vec![1u8; 100]),
+			(15, Meta::Outputs(outputs.clone()), vec![]),
+			(40, Meta::Tile {
+				name: "LEFT".into(),
+				format: FrameFormat::Png,
+				x: 16,
+				y: 32
+			}, vec![2u8; 10])
+		];
+
+		let mut file = tokio::fs::File::create(&path).await.unwrap();
+		write_header(&mut file).await.unwrap();
+		for (at_ms, meta, data) in &records {
+			write_record(&mut file, *at_ms, meta, data).await.unwrap();
+		}
+		// a record which was not written completely
+		write_record(&mut file, 50, &records[0].1, &[3; 20]).await.unwrap();
+		file.flush().await.unwrap();
+		drop(file);
+		let len = fs::metadata(&path).unwrap().len();
+		fs::File::options().write(true).open(&path).unwrap()
+			.set_len(len - 5).unwrap();
+
+		let mut archive = Archive::open(&path).unwrap();
+		assert_eq!(archive.entries.len(), 3);
+		assert_eq!(archive.duration_ms(), 40);
+
+		assert_eq!(archive.entries[0].display(), Some("LEFT"));
+		assert_eq!(archive.read_data(0).unwrap(), records[0].2);
+
+		match &archive.entries[1].meta {
+			Meta::Outputs(o) => assert_eq!(*o, outputs),
+			m => panic!("unexpected {m:?}")
+		}
+		assert!(archive.read_data(1).unwrap().is_empty());
+
+		match archive.entries[2].meta {
+			Meta::Tile { format: FrameFormat::Png, x: 16, y: 32, .. } => {},
+			ref m => panic!("unexpected {m:?}")
+		}
+		assert_eq!(archive.read_data(2).unwrap(), records[2].2);
+
+		fs::write(&path, b"TCDREC\x02").unwrap();
+		assert!(Archive::open(&path).is_err());
+
+		// a corrupt meta length
+		let mut file = tokio::fs::File::create(&path).await.unwrap();
+		write_header(&mut file).await.unwrap();
+		file.write_u64(0).await.unwrap();
+		file.write_u32(u32::MAX).await.unwrap();
+		file.write_all(&[0; 20]).await.unwrap();
+		file.flush().await.unwrap();
+		drop(file);
+		assert!(Archive::open(&path).is_err());
+
+		fs::remove_file(&path).unwrap();
+	}
+}
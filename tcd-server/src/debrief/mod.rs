@@ -0,0 +1,35 @@
+//! Records the frames of the displays and the outputs of dcs-bios during a
+//! flight, so they can be replayed afterwards through the normal apis.
+
+pub mod api;
+mod archive;
+mod recorder;
+pub use recorder::Recorder;
+mod playback;
+pub use playback::Playback;
+
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Is set while a recording is replayed, the live frames from the driver and
+/// outputs from dcs-bios are ignored meanwhile.
+#[derive(Debug, Clone)]
+pub struct Replaying {
+	inner: Arc<AtomicBool>
+}
+
+impl Replaying {
+	pub fn new() -> Self {
+		Self {
+			inner: Arc::new(AtomicBool::new(false))
+		}
+	}
+
+	pub fn is_active(&self) -> bool {
+		self.inner.load(Ordering::Relaxed)
+	}
+
+	pub(crate) fn set(&self, active: bool) {
+		self.inner.store(active, Ordering::Relaxed);
+	}
+}
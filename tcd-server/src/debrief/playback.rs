@@ -0,0 +1,380 @@
+use super::Replaying;
+use super::archive::{self, Archive, Meta};
+use crate::api_error::Error;
+use crate::displays::{DisplayFrames, DisplayFrame, Frame, Tile};
+use crate::dcs_bios::controls::ControlOutputs;
+use crate::{VirtualDisplay, DcsBios};
+
+use std::io;
+use std::thread::{self, JoinHandle};
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant};
+use std::collections::HashMap;
+
+use tokio::task;
+
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlaybackStatus {
+	pub name: String,
+	pub position_ms: u64,
+	pub duration_ms: u64,
+	pub paused: bool
+}
+
+/// Replays a recording through the `VirtualDisplay` and `DcsBios`, so every
+/// client sees the recording instead of the live data.
+#[derive(Debug, Clone)]
+pub struct Playback {
+	inner: Arc<Mutex<Option<Running>>>,
+	/// held while starting or stopping, so two starts cannot both spawn a
+	/// player
+	changing: Arc<tokio::sync::Mutex<()>>,
+	replaying: Replaying,
+	virtual_display: VirtualDisplay,
+	dcs_bios: DcsBios
+}
+
+#[derive(Debug)]
+struct Running {
+	name: String,
+	clock: Arc<Mutex<Clock>>,
+	commands: mpsc::Sender<Command>,
+	thread: JoinHandle<()>
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Command {
+	Pause(bool),
+	Seek(u64)
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Clock {
+	/// the position when the playback was started, paused or seeked
+	base_ms: u64,
+	/// None if paused
+	started_at: Option<Instant>,
+	duration_ms: u64
+}
+
+impl Clock {
+	fn position_ms(&self) -> u64 {
+		let elapsed = self.started_at
+			.map(|s| s.elapsed().as_millis() as u64)
+			.unwrap_or(0);
+		(self.base_ms + elapsed).min(self.duration_ms)
+	}
+
+	fn set_paused(&mut self, paused: bool) {
+		if paused {
+			self.base_ms = self.position_ms();
+			self.started_at = None;
+		} else if self.started_at.is_none() {
+			self.started_at = Some(Instant::now());
+		}
+	}
+
+	fn seek(&mut self, position_ms: u64) {
+		self.base_ms = position_ms.min(self.duration_ms);
+		if self.started_at.is_some() {
+			self.started_at = Some(Instant::now());
+		}
+	}
+}
+
+impl Playback {
+	pub fn new(
+		replaying: Replaying,
+		virtual_display: VirtualDisplay,
+		dcs_bios: DcsBios
+	) -> Self {
+		Self {
+			inner: Arc::new(Mutex::new(None)),
+			changing: Arc::new(tokio::sync::Mutex::new(())),
+			replaying,
+			virtual_display,
+			dcs_bios
+		}
+	}
+
+	pub fn status(&self) -> Option<PlaybackStatus> {
+		let inner = self.inner.lock().unwrap();
+		let running = inner.as_ref()?;
+		let clock = *running.clock.lock().unwrap();
+
+		Some(PlaybackStatus {
+			name: running.name.clone(),
+			position_ms: clock.position_ms(),
+			duration_ms: clock.duration_ms,
+			paused: clock.started_at.is_none()
+		})
+	}
+
+	/// stops the current playback and starts playing the recording from
+	/// the beginning
+	pub async fn start(&self, name: String) -> Result<PlaybackStatus, Error> {
+		if !archive::valid_name(&name) {
+			return Err(Error::Request(format!("{name} does not exist")))
+		}
+
+		let path = archive::path(&name);
+		let archive = task::spawn_blocking(move || Archive::open(&path))
+			.await
+			.unwrap()
+			.map_err(|e| match e.kind() {
+				io::ErrorKind::NotFound => {
+					Error::Request(format!("{name} does not exist"))
+				},
+				_ => Error::Internal(e.to_string())
+			})?;
+
+		let _changing = self.changing.lock().await;
+		self.stop_running().await;
+
+		let clock = Arc::new(Mutex::new(Clock {
+			base_ms: 0,
+			started_at: Some(Instant::now()),
+			duration_ms: archive.duration_ms()
+		}));
+		let (tx, rx) = mpsc::channel();
+
+		let player = Player {
+			archive,
+			clock: clock.clone(),
+			virtual_display: self.virtual_display.clone(),
+			dcs_bios: self.dcs_bios.clone(),
+			next: 0,
+			outputs: ControlOutputs::new()
+		};
+
+		self.replaying.set(true);
+		let thread = thread::spawn(move || player.run(rx));
+
+		*self.inner.lock().unwrap() = Some(Running {
+			name,
+			clock,
+			commands: tx,
+			thread
+		});
+
+		Ok(self.status().unwrap())
+	}
+
+	fn send(&self, command: Command) -> Result<PlaybackStatus, Error> {
+		{
+			let inner = self.inner.lock().unwrap();
+			let running = inner.as_ref()
+				.ok_or_else(|| Error::Request("nothing is replayed".into()))?;
+			running.commands.send(command)
+				.map_err(|_| Error::Internal("playback failed".into()))?;
+		}
+
+		Ok(self.status().unwrap())
+	}
+
+	pub fn set_paused(&self, paused: bool) -> Result<PlaybackStatus, Error> {
+		self.send(Command::Pause(paused))
+	}
+
+	pub fn seek(&self, position_ms: u64) -> Result<PlaybackStatus, Error> {
+		self.send(Command::Seek(position_ms))
+	}
+
+	/// Stops replaying, the live data is used again.
+	///
+	/// The frames and outputs get cleared, until the driver sends the next
+	/// keyframe and dcs-bios the next update.
+	pub async fn stop(&self) {
+		let _changing = self.changing.lock().await;
+		self.stop_running().await;
+	}
+
+	async fn stop_running(&self) {
+		let running = match self.inner.lock().unwrap().take() {
+			Some(r) => r,
+			None => return
+		};
+
+		// the thread stops once the sender is dropped
+		drop(running.commands);
+		let r = task::spawn_blocking(move || running.thread.join())
+			.await
+			.unwrap();
+		if r.is_err() {
+			eprintln!("playback thread panicked");
+		}
+
+		self.replaying.set(false);
+	}
+}
+
+struct Player {
+	archive: Archive,
+	clock: Arc<Mutex<Clock>>,
+	virtual_display: VirtualDisplay,
+	dcs_bios: DcsBios,
+	/// the index of the next entry which needs to be applied
+	next: usize,
+	outputs: ControlOutputs
+}
+
+impl Player {
+	fn run(mut self, commands: mpsc::Receiver<Command>) {
+		let r = self.run_inner(commands);
+		if let Err(e) = r {
+			eprintln!("playback failed {e}");
+		}
+
+		// don't leave the replayed data behind
+		self.virtual_display.modify_frames(|frames| frames.clear());
+		self.dcs_bios.replace_outputs(ControlOutputs::new());
+	}
+
+	fn run_inner(
+		&mut self,
+		commands: mpsc::Receiver<Command>
+	) -> io::Result<()> {
+		self.seek(0)?;
+
+		loop {
+			let clock = *self.clock.lock().unwrap();
+			let next_at = self.archive.entries.get(self.next).map(|e| e.at_ms);
+
+			let command = match (clock.started_at, next_at) {
+				(Some(_), Some(at)) => {
+					let wait = at.saturating_sub(clock.position_ms());
+					match commands.recv_timeout(Duration::from_millis(wait)) {
+						Ok(c) => c,
+						Err(RecvTimeoutError::Timeout) => {
+							let position = self.clock.lock().unwrap()
+								.position_ms();
+							self.apply_until(position)?;
+							continue
+						},
+						Err(RecvTimeoutError::Disconnected) => return Ok(())
+					}
+				},
+				(Some(_), None) => {
+					// the end was reached
+					self.clock.lock().unwrap().set_paused(true);
+					continue
+				},
+				(None, _) => match commands.recv() {
+					Ok(c) => c,
+					Err(_) => return Ok(())
+				}
+			};
+
+			match command {
+				Command::Pause(paused) => {
+					self.clock.lock().unwrap().set_paused(paused);
+				},
+				Command::Seek(position) => self.seek(position)?
+			}
+		}
+	}
+
+	/// builds the state at the position from the last keyframe of every
+	/// display and all outputs before it
+	fn seek(&mut self, position_ms: u64) -> io::Result<()> {
+		let entries = &self.archive.entries;
+		let end = entries.partition_point(|e| e.at_ms <= position_ms);
+
+		let mut outputs = ControlOutputs::new();
+		let mut keyframes = HashMap::new();
+		for (i, entry) in entries[..end].iter().enumerate() {
+			match &entry.meta {
+				Meta::Keyframe { name, .. } => {
+					keyframes.insert(name.clone(), i);
+				},
+				Meta::Tile { .. } => {},
+				Meta::Outputs(changed) => {
+					for (name, new) in changed {
+						outputs.insert(name.clone(), new.clone());
+					}
+				}
+			}
+		}
+
+		let mut frames = DisplayFrames::new();
+		for (name, start) in keyframes {
+			for i in start..end {
+				if self.archive.entries[i].display() != Some(&name) {
+					continue
+				}
+
+				let data = self.archive.read_data(i)?;
+				apply_frame(&mut frames, &self.archive.entries[i].meta, data);
+			}
+		}
+
+		self.virtual_display.modify_frames(|f| *f = frames);
+		self.dcs_bios.replace_outputs(outputs.clone());
+		self.outputs = outputs;
+		self.next = end;
+		self.clock.lock().unwrap().seek(position_ms);
+
+		Ok(())
+	}
+
+	fn apply_until(&mut self, position_ms: u64) -> io::Result<()> {
+		let mut frames = vec![];
+		let mut outputs_changed = false;
+
+		while let Some(entry) = self.archive.entries.get(self.next) {
+			if entry.at_ms > position_ms {
+				break
+			}
+
+			match &entry.meta {
+				Meta::Outputs(changed) => {
+					for (name, new) in changed {
+						self.outputs.insert(name.clone(), new.clone());
+					}
+					outputs_changed = true;
+				},
+				meta => {
+					let meta = meta.clone();
+					frames.push((meta, self.archive.read_data(self.next)?));
+				}
+			}
+
+			self.next += 1;
+		}
+
+		if !frames.is_empty() {
+			self.virtual_display.modify_frames(|f| {
+				for (meta, data) in frames {
+					apply_frame(f, &meta, data);
+				}
+			});
+		}
+		if outputs_changed {
+			self.dcs_bios.replace_outputs(self.outputs.clone());
+		}
+
+		Ok(())
+	}
+}
+
+fn apply_frame(frames: &mut DisplayFrames, meta: &Meta, data: Vec<u8>) {
+	match meta {
+		Meta::Keyframe { name, format } => {
+			let frame = Frame::new(*format, data);
+			frames.insert(name.clone(), DisplayFrame::new(frame));
+		},
+		Meta::Tile { name, format, x, y } => {
+			if let Some(frame) = frames.get_mut(name) {
+				frame.apply_tiles(vec![Tile {
+					x: *x,
+					y: *y,
+					frame: Frame::new(*format, data)
+				}]);
+			}
+		},
+		Meta::Outputs(_) => {}
+	}
+}
@@ -0,0 +1,210 @@
+use super::archive::{self, Meta};
+use crate::api_error::Error;
+use crate::displays::DisplaySetup;
+use crate::dcs_bios::controls::{ControlOutputs, Outputs};
+use crate::{VirtualDisplay, DcsBios};
+
+use std::{io, fs};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use tokio::fs::File;
+use tokio::io::{BufWriter, AsyncWriteExt};
+use tokio::sync::oneshot;
+use tokio::time::Instant;
+
+/// Records the frames of all displays and the outputs of dcs-bios into an
+/// archive in the recordings directory, only one recording can run at a
+/// time.
+#[derive(Debug, Clone)]
+pub struct Recorder {
+	inner: Arc<Mutex<Option<Recording>>>,
+	virtual_display: VirtualDisplay,
+	dcs_bios: DcsBios,
+	display_setup: DisplaySetup
+}
+
+#[derive(Debug)]
+struct Recording {
+	name: String,
+	stop: oneshot::Sender<()>
+}
+
+impl Recorder {
+	pub fn new(
+		virtual_display: VirtualDisplay,
+		dcs_bios: DcsBios,
+		display_setup: DisplaySetup
+	) -> Self {
+		Self {
+			inner: Arc::new(Mutex::new(None)),
+			virtual_display,
+			dcs_bios,
+			display_setup
+		}
+	}
+
+	/// the name of the running recording
+	pub fn current(&self) -> Option<String> {
+		self.inner.lock().unwrap().as_ref().map(|r| r.name.clone())
+	}
+
+	pub fn start(&self, name: String) -> Result<(), Error> {
+		if !archive::valid_name(&name) {
+			return Err(Error::Request(
+				"only letters, digits, - and _ are allowed in the name".into()
+			))
+		}
+
+		let mut inner = self.inner.lock().unwrap();
+		if inner.is_some() {
+			return Err(Error::Request("already recording".into()))
+		}
+
+		let path = archive::path(&name);
+		if path.exists() {
+			return Err(Error::Request(format!("{name} already exists")))
+		}
+		fs::create_dir_all(archive::recordings_dir())
+			.map_err(|e| Error::Internal(e.to_string()))?;
+
+		let (tx, rx) = oneshot::channel();
+		*inner = Some(Recording { name: name.clone(), stop: tx });
+
+		let this = self.clone();
+		tokio::spawn(async move {
+			let r = record(
+				path,
+				this.virtual_display.clone(),
+				this.dcs_bios.clone(),
+				&this.display_setup,
+				rx
+			).await;
+			if let Err(e) = r {
+				eprintln!("recording {name} failed {e}");
+			}
+
+			let mut inner = this.inner.lock().unwrap();
+			if inner.as_ref().map(|r| r.name == name).unwrap_or(false) {
+				*inner = None;
+			}
+		});
+
+		Ok(())
+	}
+
+	/// returns false if there was no recording running
+	pub fn stop(&self) -> bool {
+		match self.inner.lock().unwrap().take() {
+			Some(recording) => {
+				let _ = recording.stop.send(());
+				true
+			},
+			None => false
+		}
+	}
+}
+
+async fn record(
+	path: PathBuf,
+	mut virtual_display: VirtualDisplay,
+	mut dcs_bios: DcsBios,
+	display_setup: &DisplaySetup,
+	mut stop: oneshot::Receiver<()>
+) -> io::Result<()> {
+	let mut writer = BufWriter::new(File::create(path).await?);
+	archive::write_header(&mut writer).await?;
+
+	let start = Instant::now();
+	// the latest frame id which was recorded of every display
+	let mut recorded = HashMap::new();
+	let mut outputs = ControlOutputs::new();
+
+	loop {
+		let at_ms = start.elapsed().as_millis() as u64;
+		record_frames(
+			&mut writer,
+			at_ms,
+			&virtual_display,
+			display_setup,
+			&mut recorded
+		).await?;
+		record_outputs(&mut writer, at_ms, &dcs_bios, &mut outputs).await?;
+
+		tokio::select! {
+			_ = virtual_display.changed() => {},
+			_ = dcs_bios.changed() => {},
+			_ = &mut stop => break
+		}
+	}
+
+	writer.flush().await
+}
+
+async fn record_frames(
+	writer: &mut BufWriter<File>,
+	at_ms: u64,
+	virtual_display: &VirtualDisplay,
+	display_setup: &DisplaySetup,
+	recorded: &mut HashMap<String, u64>
+) -> io::Result<()> {
+	let names = display_setup.get()
+		.map(|displays| displays.names())
+		.unwrap_or_default();
+
+	for name in names {
+		let frame = virtual_display.frame(&name)
+			.and_then(|f| f.since(recorded.get(&name).copied()));
+		let frame = match frame {
+			Some(f) => f,
+			None => continue
+		};
+		if let Some(id) = frame.latest_id() {
+			recorded.insert(name.clone(), id);
+		}
+
+		if let Some(keyframe) = frame.keyframe {
+			let meta = Meta::Keyframe {
+				name: name.clone(),
+				format: keyframe.format
+			};
+			archive::write_record(writer, at_ms, &meta, &keyframe.data).await?;
+		}
+
+		for tile in frame.tiles {
+			let meta = Meta::Tile {
+				name: name.clone(),
+				format: tile.frame.format,
+				x: tile.x,
+				y: tile.y
+			};
+			archive::write_record(writer, at_ms, &meta, &tile.frame.data)
+				.await?;
+		}
+	}
+
+	Ok(())
+}
+
+/// only records the outputs which changed since the last call
+async fn record_outputs(
+	writer: &mut BufWriter<File>,
+	at_ms: u64,
+	dcs_bios: &DcsBios,
+	outputs: &mut ControlOutputs
+) -> io::Result<()> {
+	let changed: Vec<(String, Outputs)> = dcs_bios.borrow().iter()
+		.filter(|(name, new)| outputs.get(name) != Some(*new))
+		.map(|(name, new)| (name.clone(), new.clone()))
+		.collect();
+	if changed.is_empty() {
+		return Ok(())
+	}
+
+	for (name, new) in &changed {
+		outputs.insert(name.clone(), new.clone());
+	}
+
+	archive::write_record(writer, at_ms, &Meta::Outputs(changed), &[]).await
+}
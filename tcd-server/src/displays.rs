@@ -1,25 +1,61 @@
 
-use std::mem;
+use crate::api_error::Error;
+use crate::auth::Tokens;
+use crate::config::data_dir;
+use crate::monitor_setup::MonitorSetup;
+
+use std::{fs, io, fmt};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::collections::{HashMap, hash_map};
+use std::time::SystemTime;
 
 use tokio::sync::watch;
 
-use serde::{Serialize, Deserialize};
+use serde::{Serialize, Deserialize, Deserializer};
+use serde::de::{MapAccess, Visitor, Error as _};
+
+use fire::FireBuilder;
+use fire::header::RequestHeader;
+use fire_api::{api, Request, Method};
+
+const DISPLAYS_FILE: &str = "displays.json";
+
+/// the largest resolution the virtual display driver supports, all displays
+/// need to be inside of it
+///
+/// The driver reports the mode windows actually uses and skips displays
+/// outside of it, see [`ScreenMode`].
+pub const SCREEN_WIDTH: u32 = 3840;
+pub const SCREEN_HEIGHT: u32 = 2160;
+/// the driver protocol sends the display count in a byte and reserves 255
+/// for the mode
+pub const MAX_DISPLAYS: usize = 254;
 
 #[derive(Debug, Clone)]
 pub struct DisplaySetup {
-	inner: Arc<watch::Sender<Option<Displays>>>
+	inner: Arc<watch::Sender<Option<Displays>>>,
+	/// the mode the connected driver reported
+	mode: Arc<watch::Sender<Option<ScreenMode>>>
 }
 
 impl DisplaySetup {
 	pub fn new() -> Self {
 		Self {
-			inner: Arc::new(watch::channel(None).0)
+			inner: Arc::new(watch::channel(None).0),
+			mode: Arc::new(watch::channel(None).0)
 		}
 	}
 
-	#[allow(dead_code)]
+	/// None if no driver is connected or it did not report a mode yet
+	pub fn mode(&self) -> Option<ScreenMode> {
+		*self.mode.borrow()
+	}
+
+	pub fn set_mode(&self, mode: Option<ScreenMode>) {
+		self.mode.send_replace(mode);
+	}
+
 	pub fn get(&self) -> Option<Displays> {
 		self.inner.borrow().clone()
 	}
@@ -49,61 +85,380 @@ impl DisplaySetupWatcher {
 	}
 }
 
+/// The displays the virtual display driver exports, the key is the name of
+/// the viewport in dcs (for example `LEFT_MFCD`)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 #[serde(transparent)]
 pub struct Displays {
-	inner: HashMap<DisplayKind, Display>
+	#[serde(deserialize_with = "unique_names")]
+	inner: HashMap<String, Display>
 }
 
 impl Displays {
+	pub fn new(inner: HashMap<String, Display>) -> Self {
+		Self { inner }
+	}
+
 	pub fn default() -> Self {
 		let mut map = HashMap::new();
-		map.insert(DisplayKind::LeftMfcd, Display {
+		map.insert("LEFT_MFCD".into(), Display {
 			x: 0,
 			y: 0,
 			width: 640,
-			height: 640
+			height: 640,
+			format: Format::default()
 		});
-		map.insert(DisplayKind::RightMfcd, Display {
+		map.insert("RIGHT_MFCD".into(), Display {
 			x: 640,
 			y: 0,
 			height: 640,
-			width: 640
+			width: 640,
+			format: Format::default()
 		});
 
 		Self { inner: map }
 	}
+
+	/// loads the displays which were saved, returns None if they were never
+	/// saved
+	pub fn load() -> io::Result<Option<Self>> {
+		match fs::read(data_dir().join(DISPLAYS_FILE)) {
+			Ok(v) => Self::from_json(&v).map(Some),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+			Err(e) => Err(e)
+		}
+	}
+
+	/// the file could have been edited by hand, so it needs to be validated
+	fn from_json(v: &[u8]) -> io::Result<Self> {
+		let displays: Self = serde_json::from_slice(v)
+			.map_err(io::Error::other)?;
+		displays.validate()
+			.map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+		Ok(displays)
+	}
+
+	pub fn save(&self) -> io::Result<()> {
+		let dir = data_dir();
+		fs::create_dir_all(&dir)?;
+
+		let v = serde_json::to_vec_pretty(self)?;
+		fs::write(dir.join(DISPLAYS_FILE), v)
+	}
+
+	pub fn get(&self, name: &str) -> Option<&Display> {
+		self.inner.get(name)
+	}
+
+	pub fn names(&self) -> Vec<String> {
+		self.inner.keys().cloned().collect()
+	}
+
+	pub fn iter(&self) -> hash_map::Iter<'_, String, Display> {
+		self.inner.iter()
+	}
+
+	/// checks that every display has a valid name and a size, is inside the
+	/// screen and does not overlap with another display
+	pub fn validate(&self) -> Result<(), String> {
+		if self.inner.len() > MAX_DISPLAYS {
+			return Err(format!("only {MAX_DISPLAYS} displays are supported"))
+		}
+
+		for (name, display) in &self.inner {
+			if !is_valid_name(name) {
+				return Err(format!(
+					"{name:?} is not a valid name, only letters, digits and \
+					_ are allowed"
+				))
+			}
+
+			if display.width == 0 || display.height == 0 {
+				return Err(format!("{name} has no size"))
+			}
+
+			if !display.inside(SCREEN_WIDTH, SCREEN_HEIGHT) {
+				return Err(format!(
+					"{name} is outside of the screen \
+					{SCREEN_WIDTH}x{SCREEN_HEIGHT}"
+				))
+			}
+
+			if let Format::Jpeg { quality } = display.format {
+				if !(1..=100).contains(&quality) {
+					return Err(format!(
+						"{name} needs a jpeg quality between 1 and 100"
+					))
+				}
+			}
+		}
+
+		let list: Vec<_> = self.inner.iter().collect();
+		for (i, (name_a, a)) in list.iter().enumerate() {
+			for (name_b, b) in &list[i + 1..] {
+				if a.overlaps(b) {
+					return Err(format!("{name_a} overlaps {name_b}"))
+				}
+			}
+		}
+
+		Ok(())
+	}
 }
 
+/// The resolution windows uses for the virtual screen.
+///
+/// Displays outside of it are not sent by the driver, they are still
+/// accepted since the mode can change at any time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ScreenMode {
+	pub width: u32,
+	pub height: u32
+}
+
+impl ScreenMode {
+	/// returns the names of the displays which are not inside of the mode
+	pub fn outside(&self, displays: &Displays) -> Vec<String> {
+		let mut names: Vec<_> = displays.iter()
+			.filter(|(_, d)| !d.inside(self.width, self.height))
+			.map(|(name, _)| name.clone())
+			.collect();
+		names.sort();
+		names
+	}
+
+	/// logs the displays which will not be sent by the driver
+	pub fn warn_outside(&self, displays: &Displays) {
+		let outside = self.outside(displays);
+		if !outside.is_empty() {
+			eprintln!(
+				"displays outside of the screen {}x{} will not be shown: {}",
+				self.width,
+				self.height,
+				outside.join(", ")
+			);
+		}
+	}
+}
+
+/// a map would keep only the last display with the same name
+fn unique_names<'de, D>(
+	deserializer: D
+) -> Result<HashMap<String, Display>, D::Error>
+where D: Deserializer<'de> {
+	struct UniqueNames;
+
+	impl<'de> Visitor<'de> for UniqueNames {
+		type Value = HashMap<String, Display>;
+
+		fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+			f.write_str("a map of displays")
+		}
+
+		fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+		where A: MapAccess<'de> {
+			let mut displays = HashMap::new();
+			while let Some((name, display)) = map.next_entry::<String, _>()? {
+				if displays.contains_key(&name) {
+					return Err(A::Error::custom(format!(
+						"the display {name} exists twice"
+					)))
+				}
+				displays.insert(name, display);
+			}
+
+			Ok(displays)
+		}
+	}
+
+	deserializer.deserialize_map(UniqueNames)
+}
+
+/// the name needs to be usable as a lua identifier since dcs references
+/// viewports by their global name
+fn is_valid_name(name: &str) -> bool {
+	let mut chars = name.chars();
+	let first_valid = chars.next()
+		.map(|c| c.is_ascii_alphabetic() || c == '_')
+		.unwrap_or(false);
+
+	first_valid && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Display {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	/// how the driver encodes the frames of this display
+	#[serde(default)]
+	pub format: Format
+}
+
+/// The image format the driver encodes a display with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Format {
+	Jpeg {
+		/// between 1 and 100
+		quality: u8
+	},
+	/// lossless, keeps thin lines sharp but is bigger
+	Png,
+	/// lossless
+	Webp
+}
+
+impl Default for Format {
+	fn default() -> Self {
+		Self::Jpeg { quality: 80 }
+	}
+}
+
+/// The format of a received frame, the driver sends it in the header of
+/// every frame
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
-pub enum DisplayKind {
-	LeftMfcd,
-	RightMfcd,
-	CenterMfd
+pub enum FrameFormat {
+	Jpeg,
+	Png,
+	Webp
 }
 
-impl DisplayKind {
+impl FrameFormat {
 	pub fn from_u8(num: u8) -> Option<Self> {
 		match num {
-			5 => Some(Self::LeftMfcd),
-			10 => Some(Self::RightMfcd),
-			15 => Some(Self::CenterMfd),
+			0 => Some(Self::Jpeg),
+			1 => Some(Self::Png),
+			2 => Some(Self::Webp),
 			_ => None
 		}
 	}
+
+	pub fn mime(&self) -> &'static str {
+		match self {
+			Self::Jpeg => "image/jpeg",
+			Self::Png => "image/png",
+			Self::Webp => "image/webp"
+		}
+	}
 }
 
-#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
-pub struct Display {
+static NEXT_FRAME_ID: AtomicU64 = AtomicU64::new(0);
+
+/// An encoded image
+#[derive(Debug, Clone)]
+pub struct Frame {
+	/// unique for every frame received from the driver
+	pub id: u64,
+	pub format: FrameFormat,
+	pub data: Vec<u8>,
+	/// when the frame was received from the driver
+	pub received_at: SystemTime
+}
+
+impl Frame {
+	pub fn new(format: FrameFormat, data: Vec<u8>) -> Self {
+		Self {
+			id: NEXT_FRAME_ID.fetch_add(1, Ordering::Relaxed),
+			format,
+			data,
+			received_at: SystemTime::now()
+		}
+	}
+}
+
+impl Display {
+	fn inside(&self, width: u32, height: u32) -> bool {
+		// use u64 so big values cannot overflow
+		self.x as u64 + self.width as u64 <= width as u64 &&
+			self.y as u64 + self.height as u64 <= height as u64
+	}
+
+	fn overlaps(&self, other: &Display) -> bool {
+		self.x < other.x + other.width &&
+			other.x < self.x + self.width &&
+			self.y < other.y + other.height &&
+			other.y < self.y + self.height
+	}
+}
+
+/// A part of a display which changed, the position is relative to the
+/// display
+#[derive(Debug, Clone)]
+pub struct Tile {
 	pub x: u32,
 	pub y: u32,
-	pub width: u32,
-	pub height: u32
+	pub frame: Frame
 }
 
+/// The content of a display, a keyframe and the tiles which changed since.
+///
+/// If the keyframe is None this only contains the tiles which changed since
+/// the client received a frame.
+#[derive(Debug, Clone)]
+pub struct DisplayFrame {
+	pub keyframe: Option<Frame>,
+	/// only the latest tile of every position, ordered by their id
+	pub tiles: Vec<Tile>
+}
+
+impl DisplayFrame {
+	pub fn new(keyframe: Frame) -> Self {
+		Self {
+			keyframe: Some(keyframe),
+			tiles: vec![]
+		}
+	}
+
+	/// replaces the tiles at the same position
+	pub fn apply_tiles(&mut self, tiles: Vec<Tile>) {
+		for tile in tiles {
+			self.tiles.retain(|t| t.x != tile.x || t.y != tile.y);
+			self.tiles.push(tile);
+		}
+	}
+
+	/// the newest keyframe or tile
+	pub fn latest(&self) -> Option<&Frame> {
+		self.tiles.last()
+			.map(|t| &t.frame)
+			.or(self.keyframe.as_ref())
+	}
+
+	/// the id of the newest keyframe or tile
+	pub fn latest_id(&self) -> Option<u64> {
+		self.latest().map(|f| f.id)
+	}
+
+	/// Returns what a client needs which received everything up to
+	/// `received`, None if nothing changed.
+	pub fn since(&self, received: Option<u64>) -> Option<Self> {
+		let keyframe = self.keyframe.as_ref()?;
+		let received = match received {
+			Some(r) if r >= keyframe.id => r,
+			_ => return Some(self.clone())
+		};
+
+		let tiles: Vec<_> = self.tiles.iter()
+			.filter(|t| t.frame.id > received)
+			.cloned()
+			.collect();
+		if tiles.is_empty() {
+			return None
+		}
+
+		Some(Self {
+			keyframe: None,
+			tiles
+		})
+	}
+}
+
+/// The latest frame of every display, by name
 #[derive(Debug, Clone)]
 pub struct DisplayFrames {
-	inner: HashMap<DisplayKind, Vec<u8>>
+	inner: HashMap<String, DisplayFrame>
 }
 
 impl DisplayFrames {
@@ -113,26 +468,238 @@ impl DisplayFrames {
 		}
 	}
 
-	pub fn get(&self, kind: &DisplayKind) -> Option<&Vec<u8>> {
-		self.inner.get(kind)
+	pub fn get(&self, name: &str) -> Option<&DisplayFrame> {
+		self.inner.get(name)
+	}
+
+	pub fn get_mut(&mut self, name: &str) -> Option<&mut DisplayFrame> {
+		self.inner.get_mut(name)
 	}
 
-	pub fn insert(&mut self, kind: DisplayKind, buf: Vec<u8>) {
-		self.inner.insert(kind, buf);
+	pub fn insert(&mut self, name: String, frame: DisplayFrame) {
+		self.inner.insert(name, frame);
 	}
 
-	pub fn keys(&self) -> hash_map::Keys<DisplayKind, Vec<u8>> {
+	pub fn keys(&self) -> hash_map::Keys<'_, String, DisplayFrame> {
 		self.inner.keys()
 	}
 
-	pub fn remove(&mut self, kind: &DisplayKind) -> Option<Vec<u8>> {
-		self.inner.remove(kind)
+	pub fn remove(&mut self, name: &str) -> Option<DisplayFrame> {
+		self.inner.remove(name)
 	}
 
-	pub fn clear_and_get_buffers(&mut self, buffers: &mut Vec<Vec<u8>>) {
-		for buf in self.inner.values_mut() {
-			buffers.push(mem::take(buf));
-		}
+	pub fn clear(&mut self) {
 		self.inner.clear();
 	}
-}
\ No newline at end of file
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DisplaysReq;
+
+impl Request for DisplaysReq {
+	type Response = Option<Displays>;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/displays";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(DisplaysReq)]
+fn displays_get(
+	_req: DisplaysReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	display_setup: &DisplaySetup
+) -> Result<Option<Displays>, Error> {
+	tokens.check_header(header)?;
+
+	Ok(display_setup.get())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SetDisplaysReq {
+	displays: Displays
+}
+
+impl Request for SetDisplaysReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/displays";
+	const METHOD: Method = Method::POST;
+}
+
+/// the new displays get sent to the connected driver and the installed
+/// MonitorSetup files get updated
+#[api(SetDisplaysReq)]
+fn displays_set(
+	req: SetDisplaysReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	display_setup: &DisplaySetup,
+	monitor_setup: &MonitorSetup
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	req.displays.validate()
+		.map_err(Error::Request)?;
+	monitor_setup.validate(&req.displays)
+		.map_err(Error::Request)?;
+
+	// write the lua files first, so if it fails nothing changed
+	monitor_setup.update_installed(&req.displays)
+		.map_err(|e| Error::Internal(e.to_string()))?;
+	req.displays.save()
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	if let Some(mode) = display_setup.mode() {
+		mode.warn_outside(&req.displays);
+	}
+	display_setup.set(Some(req.displays));
+
+	Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ModeReq;
+
+impl Request for ModeReq {
+	type Response = Option<ScreenMode>;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/displays/mode";
+	const METHOD: Method = Method::GET;
+}
+
+/// returns None if the driver is not connected
+#[api(ModeReq)]
+fn mode_get(
+	_req: ModeReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	display_setup: &DisplaySetup
+) -> Result<Option<ScreenMode>, Error> {
+	tokens.check_header(header)?;
+
+	Ok(display_setup.mode())
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(displays_get);
+	fire.add_route(displays_set);
+	fire.add_route(mode_get);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	fn display(x: u32, y: u32, width: u32, height: u32) -> Display {
+		Display { x, y, width, height, format: Format::Png }
+	}
+
+	fn displays(list: &[(&str, Display)]) -> Displays {
+		Displays::new(
+			list.iter().map(|(n, d)| (n.to_string(), *d)).collect()
+		)
+	}
+
+	#[test]
+	fn valid() {
+		assert!(Displays::default().validate().is_ok());
+
+		let d = displays(&[
+			("LEFT_MFCD", display(0, 0, 640, 640)),
+			("_2", display(3200, 1520, 640, 640))
+		]);
+		assert!(d.validate().is_ok());
+	}
+
+	#[test]
+	fn names() {
+		for name in ["", "1LEFT", "LEFT MFCD", "LEFT-MFCD", "ä"] {
+			let d = displays(&[(name, display(0, 0, 10, 10))]);
+			assert!(d.validate().is_err(), "{name:?}");
+		}
+	}
+
+	#[test]
+	fn duplicate_names() {
+		let json = br#"{
+			"A": { "x": 0, "y": 0, "width": 10, "height": 10 },
+			"A": { "x": 20, "y": 0, "width": 10, "height": 10 }
+		}"#;
+		let e = Displays::from_json(json).unwrap_err();
+		assert!(e.to_string().contains("twice"), "{e}");
+	}
+
+	#[test]
+	fn size() {
+		let d = displays(&[("A", display(0, 0, 0, 10))]);
+		assert!(d.validate().is_err());
+		let d = displays(&[("A", display(0, 0, 10, 0))]);
+		assert!(d.validate().is_err());
+	}
+
+	#[test]
+	fn bounds() {
+		let d = displays(&[("A", display(3200, 0, 640, 2160))]);
+		assert!(d.validate().is_ok());
+
+		let d = displays(&[("A", display(3201, 0, 640, 10))]);
+		assert!(d.validate().is_err());
+		let d = displays(&[("A", display(0, 1521, 10, 640))]);
+		assert!(d.validate().is_err());
+		// does not overflow
+		let d = displays(&[("A", display(u32::MAX, u32::MAX, 10, 10))]);
+		assert!(d.validate().is_err());
+	}
+
+	#[test]
+	fn jpeg_quality() {
+		let qualities = [(0, false), (1, true), (100, true), (101, false)];
+		for (quality, valid) in qualities {
+			let mut a = display(0, 0, 10, 10);
+			a.format = Format::Jpeg { quality };
+			let d = displays(&[("A", a)]);
+			assert_eq!(d.validate().is_ok(), valid, "{quality}");
+		}
+	}
+
+	#[test]
+	fn overlap() {
+		let d = displays(&[
+			("A", display(0, 0, 100, 100)),
+			("B", display(99, 99, 100, 100))
+		]);
+		assert!(d.validate().is_err());
+
+		// touching is fine
+		let d = displays(&[
+			("A", display(0, 0, 100, 100)),
+			("B", display(100, 0, 100, 100))
+		]);
+		assert!(d.validate().is_ok());
+	}
+
+	#[test]
+	fn max_displays() {
+		let list: HashMap<_, _> = (0..=MAX_DISPLAYS as u32)
+			.map(|i| (format!("D{i}"), display(i * 10, 0, 10, 10)))
+			.collect();
+		let mut d = Displays::new(list);
+		assert!(d.validate().is_err());
+
+		d.inner.remove("D0");
+		assert!(d.validate().is_ok());
+	}
+
+	#[test]
+	fn load_validates() {
+		let json = br#"{ "A": { "x": 0, "y": 0, "width": 0, "height": 10 } }"#;
+		assert!(Displays::from_json(json).is_err());
+
+		let json = br#"{ "A": { "x": 0, "y": 0, "width": 10, "height": 10 } }"#;
+		assert!(Displays::from_json(json).is_ok());
+	}
+}
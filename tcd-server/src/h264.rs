@@ -0,0 +1,388 @@
+use crate::api_error::Error;
+use crate::displays::{DisplaySetup, DisplayFrame};
+use crate::adaptive::Adaptive;
+use crate::handshake::{handshake, update_role, ServerHello};
+use crate::connections::{Connections, Liveness, ACK_TIMEOUT};
+use crate::auth::Tokens;
+use crate::config::Config;
+use crate::rescale::{self, Size};
+use crate::{VirtualDisplay, DcsBios};
+
+use std::collections::HashMap;
+
+use tokio::task;
+use tokio::time::{self, Instant};
+
+use serde::{Serialize, Deserialize};
+
+use openh264::OpenH264API;
+use openh264::encoder::{
+	Encoder, EncoderConfig, UsageType, RateControlMode, BitRate, FrameRate,
+	FrameType, Profile
+};
+use openh264::formats::{YUVBuffer, RgbaSliceU8};
+
+use fire::{FireBuilder, ws};
+use fire::ws::WebSocket;
+
+
+/// The same requests as `/api/mfds`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Request {
+	Subscribe(String),
+	Unsubscribe(String),
+	SetSize {
+		name: String,
+		size: Option<Size>
+	},
+	Aknowledge,
+	Pong
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct VideoFramesAnnouncement {
+	list: Vec<AnnouncedVideoFrame>
+}
+
+/// Each announced frame is followed by a binary message containing an
+/// access unit in the annex b format, which can be passed to a WebCodecs
+/// `VideoDecoder` as an `EncodedVideoChunk`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnnouncedVideoFrame {
+	name: String,
+	/// the decoder needs to be configured again if the size changed
+	width: u32,
+	height: u32,
+	/// if true the frame does not depend on previous frames
+	keyframe: bool,
+	/// microseconds since the stream started
+	timestamp: u64
+}
+
+struct VideoFrame {
+	name: String,
+	width: u32,
+	height: u32,
+	keyframe: bool,
+	data: Vec<u8>
+}
+
+/// The state of a subscribed display, every display has it's own encoder
+/// since the frames depend on the previous ones.
+struct Stream {
+	size: Option<Size>,
+	/// the id of the latest part of the frame which was encoded
+	sent: Option<u64>,
+	encoder: Option<VideoEncoder>
+}
+
+struct VideoEncoder {
+	encoder: Encoder,
+	width: u32,
+	height: u32
+}
+
+/// Streams the displays as h264, which needs a lot less bandwidth than
+/// sending every frame as a jpeg.
+///
+/// The frames are encoded for every client separately, since every client
+/// receives a different set of frames.
+#[ws("/api/mfds/h264")]
+async fn mfds_h264(
+	mut ws: WebSocket,
+	virtual_display: &VirtualDisplay,
+	dcs_bios: &DcsBios,
+	display_setup: &DisplaySetup,
+	connections: &Connections,
+	tokens: &Tokens,
+	config: &Config
+) -> Result<(), Error> {
+	let hello = ServerHello::new(dcs_bios, display_setup, config);
+	let session = match handshake(&mut ws, hello, tokens).await? {
+		Some((_, session)) => session,
+		None => return Ok(())
+	};
+
+	let mut connection = connections.register("mfds-h264", &session);
+	let mut liveness = Liveness::new();
+
+	let mut virtual_display = virtual_display.clone();
+	let mut streams: HashMap<String, Stream> = HashMap::new();
+	// sizes can be set before subscribing
+	let mut sizes = HashMap::new();
+	let started_at = Instant::now();
+	let mut was_aknowledged = true;
+	let mut sent_at = Instant::now();
+	let mut sent_bytes = 0;
+	let mut missed_frame = false;
+	let mut adaptive = Adaptive::new(&config.stream);
+
+	loop {
+		tokio::select! {
+			_ = virtual_display.changed(), if !streams.is_empty() => {
+				if !was_aknowledged {
+					connection.frame_dropped();
+					missed_frame = true;
+					continue
+				}
+
+				if !adaptive.may_send() {
+					missed_frame = true;
+					continue
+				}
+			},
+			_ = time::sleep_until(adaptive.next_frame_at()),
+				if was_aknowledged && missed_frame =>
+			{
+				missed_frame = false;
+			},
+			_ = time::sleep_until(sent_at + ACK_TIMEOUT), if !was_aknowledged => {
+				connection.ack_timed_out();
+				// if nothing get's encoded the timeout should not fire
+				// again
+				was_aknowledged = true;
+				sent_at = Instant::now();
+				missed_frame = false;
+				adaptive.timed_out();
+
+				// the client might have lost a frame, which the next frames
+				// depend on
+				for stream in streams.values_mut() {
+					stream.sent = None;
+					if let Some(encoder) = &mut stream.encoder {
+						encoder.encoder.force_intra_frame();
+					}
+				}
+			},
+			_ = liveness.tick() => {
+				if !liveness.ping(&mut ws).await? {
+					return Ok(())
+				}
+
+				continue
+			},
+			// every role can watch the displays
+			role = connection.role_changed() => {
+				if update_role(&mut ws, role).await?.is_none() {
+					return Ok(())
+				}
+
+				continue
+			},
+			req = ws.deserialize() => {
+				let maybe_req: Option<Request> = req
+					.map_err(|e| Error::Internal(e.to_string()))?;
+				let req = match maybe_req {
+					Some(r) => r,
+					// connection closed
+					None => return Ok(())
+				};
+
+				liveness.received();
+
+				match req {
+					Request::Subscribe(name) => {
+						let stream = Stream {
+							size: sizes.get(&name).copied(),
+							sent: None,
+							encoder: None
+						};
+						let _ = streams.insert(name, stream);
+						continue
+					},
+					Request::Unsubscribe(name) => {
+						let _ = streams.remove(&name);
+						let _ = sizes.remove(&name);
+						continue
+					},
+					Request::SetSize { name, size } => {
+						let size = size.filter(|s| s.width > 0 && s.height > 0);
+						// the encoder get's recreated with the new size
+						if let Some(stream) = streams.get_mut(&name) {
+							stream.size = size;
+							stream.sent = None;
+						}
+						match size {
+							Some(size) => sizes.insert(name, size),
+							None => sizes.remove(&name)
+						};
+						continue
+					},
+					Request::Aknowledge => {
+						was_aknowledged = true;
+						adaptive.aknowledged(sent_bytes);
+						connection.set_adaptive(adaptive.stats());
+
+						if !missed_frame || !adaptive.may_send() {
+							continue
+						}
+
+						missed_frame = false;
+					},
+					Request::Pong => continue
+				}
+			}
+		}
+
+		let mut video_frames = vec![];
+		for (name, stream) in streams.iter_mut() {
+			let frame = match virtual_display.frame(name) {
+				Some(f) if f.latest_id() != stream.sent => f,
+				_ => continue
+			};
+
+			stream.sent = frame.latest_id();
+			let video_frame = encode(name, stream, frame, config).await;
+			video_frames.extend(video_frame);
+		}
+
+		let timestamp = started_at.elapsed().as_micros() as u64;
+		let bytes = send_frames(&mut ws, video_frames, timestamp).await?;
+		if bytes > 0 {
+			was_aknowledged = false;
+			sent_at = Instant::now();
+			sent_bytes = bytes;
+			adaptive.sent();
+		}
+	}
+}
+
+/// encodes the frame with the encoder of the stream, returns None if the
+/// frame could not be encoded
+async fn encode(
+	name: &str,
+	stream: &mut Stream,
+	frame: DisplayFrame,
+	config: &Config
+) -> Option<VideoFrame> {
+	let size = stream.size;
+	let encoder = stream.encoder.take();
+	let bitrate = config.stream.h264_bitrate;
+	let max_fps = config.stream.max_fps;
+
+	let r = task::spawn_blocking(move || {
+		let mut encoder = encoder;
+		let r = encode_blocking(&mut encoder, &frame, size, bitrate, max_fps);
+		(encoder, r)
+	}).await.expect("h264 encoding panicked");
+
+	match r {
+		(encoder, Ok(Some((keyframe, data)))) => {
+			stream.encoder = encoder;
+			let encoder = stream.encoder.as_ref().unwrap();
+			Some(VideoFrame {
+				name: name.to_string(),
+				width: encoder.width,
+				height: encoder.height,
+				keyframe,
+				data
+			})
+		},
+		(encoder, Ok(None)) => {
+			stream.encoder = encoder;
+			None
+		},
+		(_, Err(e)) => {
+			// the next frame will use a new encoder
+			eprintln!("could not encode frame of {name} as h264 {e}");
+			None
+		}
+	}
+}
+
+/// Returns if the frame is a keyframe and the data, None if the encoder
+/// did not output anything.
+///
+/// The encoder get's created if the size of the frame changed.
+fn encode_blocking(
+	encoder: &mut Option<VideoEncoder>,
+	frame: &DisplayFrame,
+	size: Option<Size>,
+	bitrate: u32,
+	max_fps: u32
+) -> Result<Option<(bool, Vec<u8>)>, String> {
+	let image = rescale::render(frame, size)
+		.map_err(|e| e.to_string())?;
+
+	// yuv 4:2:0 needs an even width and height
+	let width = image.width() & !1;
+	let height = image.height() & !1;
+	if width == 0 || height == 0 {
+		return Ok(None)
+	}
+	let image = image.crop_imm(0, 0, width, height).into_rgba8();
+
+	let same_size = encoder.as_ref()
+		.map(|e| e.width == width && e.height == height)
+		.unwrap_or(false);
+	if !same_size {
+		let config = EncoderConfig::new()
+			.usage_type(UsageType::ScreenContentRealTime)
+			.profile(Profile::Baseline)
+			.rate_control_mode(RateControlMode::Bitrate)
+			.bitrate(BitRate::from_bps(bitrate))
+			.max_frame_rate(FrameRate::from_hz(max_fps.max(1) as f32))
+			// every frame needs to produce data, else the client would
+			// never aknowledge
+			.skip_frames(false);
+		let h264 = Encoder::with_api_config(OpenH264API::from_source(), config)
+			.map_err(|e| e.to_string())?;
+
+		*encoder = Some(VideoEncoder { encoder: h264, width, height });
+	}
+	let encoder = encoder.as_mut().unwrap();
+
+	let yuv = YUVBuffer::from_rgba8_source(RgbaSliceU8::new(
+		image.as_raw(),
+		(width as usize, height as usize)
+	));
+	let bitstream = encoder.encoder.encode(&yuv)
+		.map_err(|e| e.to_string())?;
+
+	let keyframe = matches!(
+		bitstream.frame_type(),
+		FrameType::IDR | FrameType::I
+	);
+	let data = bitstream.to_vec();
+
+	Ok((!data.is_empty()).then_some((keyframe, data)))
+}
+
+/// returns how many bytes were sent, 0 if there was nothing to send
+async fn send_frames(
+	ws: &mut WebSocket,
+	frames: Vec<VideoFrame>,
+	timestamp: u64
+) -> Result<usize, Error> {
+	if frames.is_empty() {
+		return Ok(0)
+	}
+
+	let announcement = VideoFramesAnnouncement {
+		list: frames.iter()
+			.map(|f| AnnouncedVideoFrame {
+				name: f.name.clone(),
+				width: f.width,
+				height: f.height,
+				keyframe: f.keyframe,
+				timestamp
+			})
+			.collect()
+	};
+
+	ws.serialize(&announcement).await
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	let mut bytes = 0;
+	for frame in frames {
+		bytes += frame.data.len();
+		ws.send(frame.data).await
+			.map_err(|e| Error::Internal(e.to_string()))?;
+	}
+
+	Ok(bytes)
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_raw_route(mfds_h264);
+}
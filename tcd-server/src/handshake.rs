@@ -0,0 +1,212 @@
+use crate::api_error::Error;
+use crate::auth::{Tokens, Session, Role};
+use crate::config::Config;
+use crate::connections::ACK_TIMEOUT;
+use crate::displays::DisplaySetup;
+use crate::DcsBios;
+
+use serde::{Serialize, Deserialize};
+
+use fire::ws::{WebSocket, CloseCode};
+
+use tokio::time;
+
+/// Needs to be increased every time a websocket api changes in a way which
+/// is not backwards compatible
+pub const PROTOCOL_VERSION: u32 = 10;
+
+/// the close code used if the client sent an invalid token
+pub const CLOSE_UNAUTHORIZED: u16 = 4001;
+
+/// How updates of the controls stream are sent to the client, every update
+/// is a single message containing a list of `Response`'s
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Encoding {
+	/// a text message
+	#[default]
+	Json,
+	/// a binary message, smaller and faster to parse
+	MessagePack
+}
+
+/// The video codecs the displays can be streamed with, instead of sending
+/// every frame as an image
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VideoCodec {
+	/// at `/api/mfds/h264`
+	H264
+}
+
+impl VideoCodec {
+	/// the codecs this server was built with
+	pub fn supported() -> Vec<Self> {
+		let mut list = vec![];
+		if cfg!(feature = "h264") {
+			list.push(Self::H264);
+		}
+		list
+	}
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+enum Message<T> {
+	Hello(T),
+	Welcome(Welcome)
+}
+
+/// Sent after the handshake was successful
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct Welcome {
+	role: Role
+}
+
+/// The first message the server sends on every websocket api
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ServerHello {
+	pub version: u32,
+	pub server_version: String,
+	/// the encodings supported by the controls stream
+	pub encodings: Vec<Encoding>,
+	/// if empty the displays can only be streamed as images
+	pub video_codecs: Vec<VideoCodec>,
+	/// the currently loaded aircraft
+	pub aircraft: Option<String>,
+	/// the names of the displays the virtual display driver exports
+	pub displays: Vec<String>,
+	/// controls which need to be armed before sending an input
+	pub guarded_controls: Vec<String>
+}
+
+impl ServerHello {
+	pub fn new(
+		dcs_bios: &DcsBios,
+		display_setup: &DisplaySetup,
+		config: &Config
+	) -> Self {
+		Self {
+			version: PROTOCOL_VERSION,
+			server_version: env!("CARGO_PKG_VERSION").into(),
+			encodings: vec![Encoding::Json, Encoding::MessagePack],
+			video_codecs: VideoCodec::supported(),
+			aircraft: dcs_bios.aircraft(),
+			displays: display_setup.get()
+				.map(|d| d.names())
+				.unwrap_or_default(),
+			guarded_controls: config.guarded_controls.clone()
+		}
+	}
+}
+
+/// The answer of the client to the `ServerHello`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ClientHello {
+	pub version: u32,
+	#[serde(default)]
+	pub encoding: Encoding,
+	/// the token received from pairing
+	#[serde(default)]
+	pub token: Option<String>
+}
+
+/// Sends the hello of the server and waits for the hello of the client.
+///
+/// Returns None if the connection was closed, the client did not answer in
+/// time or is not compatible, in which case the connection get's closed
+/// with a reason the client can show. Else returns the hello and the
+/// session of the client.
+pub async fn handshake(
+	ws: &mut WebSocket,
+	hello: ServerHello,
+	tokens: &Tokens
+) -> Result<Option<(ClientHello, Session)>, Error> {
+	ws.serialize(&Message::Hello(hello)).await
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	// don't keep connections open which never send a hello
+	let msg = match time::timeout(ACK_TIMEOUT, ws.receive()).await {
+		Ok(msg) => msg.map_err(|e| Error::Internal(e.to_string()))?,
+		Err(_) => {
+			ws.close(
+				CloseCode::Policy,
+				"expected hello, the client took too long".into()
+			).await;
+			return Ok(None)
+		}
+	};
+	let msg = match msg {
+		Some(m) => m,
+		None => return Ok(None)
+	};
+
+	// old clients don't know about the hello message
+	let hello = match serde_json::from_slice(&msg.into_data()) {
+		Ok(Message::Hello(hello)) => hello,
+		Ok(Message::Welcome(_)) | Err(_) => {
+			ws.close(
+				CloseCode::Protocol,
+				"expected hello, the page needs to be reloaded".into()
+			).await;
+			return Ok(None)
+		}
+	};
+
+	let ClientHello { version, .. } = hello;
+	if version != PROTOCOL_VERSION {
+		ws.close(
+			CloseCode::Policy,
+			format!(
+				"protocol version {version} not supported, expected \
+				{PROTOCOL_VERSION}, the page needs to be reloaded"
+			)
+		).await;
+		return Ok(None)
+	}
+
+	let session = hello.token.as_deref()
+		.and_then(|token| tokens.session(token));
+	let session = match session {
+		Some(s) => s,
+		None => {
+			ws.close(
+				CloseCode::Library(CLOSE_UNAUTHORIZED),
+				"invalid token, the device needs to be paired".into()
+			).await;
+			return Ok(None)
+		}
+	};
+
+	// let the client know what it is allowed to do
+	send_welcome(ws, &session.role).await?;
+
+	Ok(Some((hello, session)))
+}
+
+async fn send_welcome(ws: &mut WebSocket, role: &Role) -> Result<(), Error> {
+	let welcome = Welcome { role: role.clone() };
+	ws.serialize(&Message::<ServerHello>::Welcome(welcome)).await
+		.map_err(|e| Error::Internal(e.to_string()))
+}
+
+/// Tells the client about it's new role or closes the connection if the
+/// token was revoked.
+///
+/// Returns None if the connection was closed.
+pub async fn update_role(
+	ws: &mut WebSocket,
+	role: Option<Role>
+) -> Result<Option<Role>, Error> {
+	let role = match role {
+		Some(r) => r,
+		None => {
+			ws.close(
+				CloseCode::Library(CLOSE_UNAUTHORIZED),
+				"the token was revoked, the device needs to be paired".into()
+			).await;
+			return Ok(None)
+		}
+	};
+
+	send_welcome(ws, &role).await?;
+
+	Ok(Some(role))
+}
@@ -0,0 +1,1034 @@
+//! A parser for the subset of lua which is used in dcs MonitorSetup files.
+//!
+//! Supports global and local assignments, tables, numbers, strings,
+//! arithmetic, comparisons, `if` blocks and references to other globals.
+//! Function definitions are skipped and function calls evaluate to their
+//! first argument, which covers `_('name')`.
+
+use std::fmt;
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+	Nil,
+	Bool(bool),
+	Number(f64),
+	String(String),
+	Table(Table),
+	Function
+}
+
+impl Value {
+	pub fn as_number(&self) -> Option<f64> {
+		match self {
+			Self::Number(n) => Some(*n),
+			_ => None
+		}
+	}
+
+	pub fn as_table(&self) -> Option<&Table> {
+		match self {
+			Self::Table(t) => Some(t),
+			_ => None
+		}
+	}
+
+	fn is_truthy(&self) -> bool {
+		!matches!(self, Self::Nil | Self::Bool(false))
+	}
+
+	fn kind(&self) -> &'static str {
+		match self {
+			Self::Nil => "nil",
+			Self::Bool(_) => "boolean",
+			Self::Number(_) => "number",
+			Self::String(_) => "string",
+			Self::Table(_) => "table",
+			Self::Function => "function"
+		}
+	}
+}
+
+/// Only string keys are kept, array entries are ignored
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Table {
+	inner: HashMap<String, Value>
+}
+
+impl Table {
+	pub fn get(&self, key: &str) -> Option<&Value> {
+		self.inner.get(key)
+	}
+
+	fn set(&mut self, key: String, value: Value) {
+		self.inner.insert(key, value);
+	}
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+	pub line: usize,
+	pub msg: String
+}
+
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "line {}: {}", self.line, self.msg)
+	}
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+	Name(String),
+	Number(f64),
+	String(String),
+	Symbol(&'static str)
+}
+
+const SYMBOLS: &[&str] = &[
+	"..", "==", "~=", "<=", ">=",
+	"=", "{", "}", "[", "]", "(", ")", ",", ";", ".", ":",
+	"+", "-", "*", "/", "%", "^", "#", "<", ">"
+];
+
+fn tokenize(s: &str) -> Result<Vec<(Token, usize)>, Error> {
+	let chars: Vec<char> = s.chars().collect();
+	let mut tokens = vec![];
+	let mut line = 1;
+	let mut i = 0;
+
+	let err = |line, msg: &str| Error { line, msg: msg.into() };
+
+	while i < chars.len() {
+		let c = chars[i];
+
+		if c == '\n' {
+			line += 1;
+			i += 1;
+			continue
+		}
+
+		if c.is_whitespace() {
+			i += 1;
+			continue
+		}
+
+		// comments
+		if c == '-' && chars.get(i + 1) == Some(&'-') {
+			i += 2;
+			let block = chars.get(i) == Some(&'[') &&
+				chars.get(i + 1) == Some(&'[');
+			if block {
+				while i < chars.len() &&
+					!(chars[i] == ']' && chars.get(i + 1) == Some(&']'))
+				{
+					if chars[i] == '\n' {
+						line += 1;
+					}
+					i += 1;
+				}
+				i += 2;
+			} else {
+				while i < chars.len() && chars[i] != '\n' {
+					i += 1;
+				}
+			}
+			continue
+		}
+
+		if c.is_ascii_alphabetic() || c == '_' {
+			let start = i;
+			while i < chars.len() &&
+				(chars[i].is_ascii_alphanumeric() || chars[i] == '_')
+			{
+				i += 1;
+			}
+			let name: String = chars[start..i].iter().collect();
+			tokens.push((Token::Name(name), line));
+			continue
+		}
+
+		let starts_number = c.is_ascii_digit() || (
+			c == '.' &&
+			chars.get(i + 1).map(|c| c.is_ascii_digit()).unwrap_or(false)
+		);
+		if starts_number {
+			let start = i;
+			let hex = c == '0' && matches!(chars.get(i + 1), Some('x' | 'X'));
+			if hex {
+				i += 2;
+			}
+			while i < chars.len() {
+				// an exponent can have a sign 1e-5
+				let exponent = !hex && matches!(chars[i], 'e' | 'E') &&
+					matches!(chars.get(i + 1), Some('+' | '-'));
+				if exponent {
+					i += 2;
+				} else if chars[i].is_ascii_alphanumeric() || chars[i] == '.' {
+					i += 1;
+				} else {
+					break
+				}
+			}
+			let num: String = chars[start..i].iter().collect();
+			let parsed = if hex {
+				u64::from_str_radix(&num[2..], 16).ok().map(|n| n as f64)
+			} else {
+				num.parse().ok()
+			};
+			let num = parsed
+				.ok_or_else(|| err(line, &format!("invalid number {num}")))?;
+			tokens.push((Token::Number(num), line));
+			continue
+		}
+
+		if c == '\'' || c == '"' {
+			i += 1;
+			let mut string = String::new();
+			loop {
+				match chars.get(i) {
+					Some(&q) if q == c => break,
+					Some('\\') => {
+						let escaped = chars.get(i + 1)
+							.ok_or_else(|| err(line, "unfinished string"))?;
+						string.push(match escaped {
+							'n' => '\n',
+							't' => '\t',
+							c => *c
+						});
+						i += 2;
+					},
+					Some('\n') | None => {
+						return Err(err(line, "unfinished string"))
+					},
+					Some(c) => {
+						string.push(*c);
+						i += 1;
+					}
+				}
+			}
+			i += 1;
+			tokens.push((Token::String(string), line));
+			continue
+		}
+
+		let rest: String = chars[i..chars.len().min(i + 2)].iter().collect();
+		let symbol = SYMBOLS.iter()
+			.find(|sym| rest.starts_with(**sym))
+			.ok_or_else(|| err(line, &format!("unexpected character {c:?}")))?;
+		i += symbol.len();
+		tokens.push((Token::Symbol(symbol), line));
+	}
+
+	Ok(tokens)
+}
+
+/// keywords which start a block that is closed by `end`
+const BLOCK_KEYWORDS: &[&str] = &["function", "if", "do"];
+
+/// keywords which end the branch of an if block
+const BRANCH_END: &[&str] = &["elseif", "else", "end"];
+
+/// how deep expressions and blocks can be nested, this prevents a stack
+/// overflow on malicious files
+const MAX_DEPTH: usize = 200;
+
+struct Parser<'a> {
+	tokens: Vec<(Token, usize)>,
+	pos: usize,
+	depth: usize,
+	globals: HashMap<String, Value>,
+	locals: HashMap<String, Value>,
+	predefined: &'a HashMap<String, Value>
+}
+
+impl Parser<'_> {
+	fn line(&self) -> usize {
+		self.tokens.get(self.pos)
+			.or_else(|| self.tokens.last())
+			.map(|(_, line)| *line)
+			.unwrap_or(1)
+	}
+
+	fn err<T>(&self, msg: impl Into<String>) -> Result<T, Error> {
+		Err(Error { line: self.line(), msg: msg.into() })
+	}
+
+	fn peek(&self) -> Option<&Token> {
+		self.tokens.get(self.pos).map(|(t, _)| t)
+	}
+
+	fn next(&mut self) -> Option<Token> {
+		let token = self.tokens.get(self.pos).map(|(t, _)| t.clone());
+		self.pos += 1;
+		token
+	}
+
+	fn is_symbol(&self, symbol: &str) -> bool {
+		matches!(self.peek(), Some(Token::Symbol(s)) if *s == symbol)
+	}
+
+	fn is_name(&self, name: &str) -> bool {
+		matches!(self.peek(), Some(Token::Name(n)) if n == name)
+	}
+
+	fn expect_symbol(&mut self, symbol: &str) -> Result<(), Error> {
+		if self.is_symbol(symbol) {
+			self.pos += 1;
+			Ok(())
+		} else {
+			self.err(format!("expected {symbol}"))
+		}
+	}
+
+	fn expect_keyword(&mut self, keyword: &str) -> Result<(), Error> {
+		if self.is_name(keyword) {
+			self.pos += 1;
+			Ok(())
+		} else {
+			self.err(format!("expected {keyword}"))
+		}
+	}
+
+	fn is_branch_end(&self) -> bool {
+		matches!(
+			self.peek(),
+			Some(Token::Name(n)) if BRANCH_END.contains(&n.as_str())
+		)
+	}
+
+	/// calls f one level deeper
+	fn nested<T>(
+		&mut self,
+		f: impl FnOnce(&mut Self) -> Result<T, Error>
+	) -> Result<T, Error> {
+		if self.depth >= MAX_DEPTH {
+			return self.err("nested too deeply")
+		}
+
+		self.depth += 1;
+		let r = f(self);
+		self.depth -= 1;
+		r
+	}
+
+	fn expect_name(&mut self) -> Result<String, Error> {
+		match self.next() {
+			Some(Token::Name(n)) => Ok(n),
+			_ => {
+				self.pos -= 1;
+				self.err("expected a name")
+			}
+		}
+	}
+
+	fn parse(&mut self) -> Result<(), Error> {
+		while self.peek().is_some() {
+			self.statement()?;
+		}
+
+		Ok(())
+	}
+
+	fn statement(&mut self) -> Result<(), Error> {
+		if self.is_symbol(";") {
+			self.pos += 1;
+			return Ok(())
+		}
+
+		if self.is_name("function") {
+			return self.skip_block()
+		}
+
+		if self.is_name("if") {
+			self.pos += 1;
+			return self.nested(Self::if_block)
+		}
+
+		let local = self.is_name("local");
+		if local {
+			self.pos += 1;
+			if self.is_name("function") {
+				return self.skip_block()
+			}
+		}
+
+		// name.field.field = value
+		let mut path = vec![self.expect_name()?];
+		while self.is_symbol(".") {
+			self.pos += 1;
+			path.push(self.expect_name()?);
+		}
+
+		// a function call as a statement
+		if self.is_symbol("(") {
+			self.call_args()?;
+			return Ok(())
+		}
+
+		self.expect_symbol("=")?;
+		let value = self.expression()?;
+
+		self.assign(local, path, value)
+	}
+
+	fn assign(
+		&mut self,
+		local: bool,
+		path: Vec<String>,
+		value: Value
+	) -> Result<(), Error> {
+		let (first, fields) = path.split_first().unwrap();
+
+		if fields.is_empty() {
+			if local || self.locals.contains_key(first) {
+				self.locals.insert(first.clone(), value);
+			} else {
+				self.globals.insert(first.clone(), value);
+			}
+			return Ok(())
+		}
+
+		let line = self.line();
+		let table = match self.locals.get_mut(first) {
+			Some(v) => v,
+			None => self.globals.get_mut(first)
+				.ok_or_else(|| Error {
+					line,
+					msg: format!("{first} is not defined")
+				})?
+		};
+
+		let (last, fields) = fields.split_last().unwrap();
+		let mut table = match table {
+			Value::Table(t) => t,
+			v => return Err(Error {
+				line,
+				msg: format!("{first} is a {}", v.kind())
+			})
+		};
+		for field in fields {
+			table = match table.inner.get_mut(field) {
+				Some(Value::Table(t)) => t,
+				_ => return Err(Error {
+					line,
+					msg: format!("{field} is not a table")
+				})
+			};
+		}
+
+		table.set(last.clone(), value);
+
+		Ok(())
+	}
+
+	/// runs the branch which matches, the if keyword was already consumed
+	fn if_block(&mut self) -> Result<(), Error> {
+		loop {
+			let condition = self.expression()?;
+			self.expect_keyword("then")?;
+
+			if condition.is_truthy() {
+				self.block()?;
+				// skip the remaining branches
+				while !self.is_name("end") {
+					self.pos += 1;
+					self.skip_branch()?;
+				}
+				self.pos += 1;
+				return Ok(())
+			}
+
+			self.skip_branch()?;
+			match self.next() {
+				Some(Token::Name(n)) if n == "elseif" => {},
+				Some(Token::Name(n)) if n == "else" => {
+					self.block()?;
+					return self.expect_keyword("end")
+				},
+				// skip_branch only stops at elseif, else or end
+				_ => return Ok(())
+			}
+		}
+	}
+
+	/// runs statements until the end of the branch
+	fn block(&mut self) -> Result<(), Error> {
+		while !self.is_branch_end() {
+			if self.peek().is_none() {
+				return self.err("missing end")
+			}
+			self.statement()?;
+		}
+
+		Ok(())
+	}
+
+	/// skips tokens until the end of the branch
+	fn skip_branch(&mut self) -> Result<(), Error> {
+		let mut depth = 0;
+		loop {
+			if depth == 0 && self.is_branch_end() {
+				return Ok(())
+			}
+
+			match self.next() {
+				Some(Token::Name(n)) if BLOCK_KEYWORDS.contains(&n.as_str()) => {
+					depth += 1;
+				},
+				Some(Token::Name(n)) if n == "end" => {
+					depth -= 1;
+				},
+				Some(_) => {},
+				None => return self.err("missing end")
+			}
+		}
+	}
+
+	/// skips a function definition
+	fn skip_block(&mut self) -> Result<(), Error> {
+		let mut depth = 0;
+		loop {
+			match self.next() {
+				Some(Token::Name(n)) if BLOCK_KEYWORDS.contains(&n.as_str()) => {
+					depth += 1;
+				},
+				// for and while loops use do
+				Some(Token::Name(n)) if n == "end" => {
+					depth -= 1;
+					if depth == 0 {
+						return Ok(())
+					}
+				},
+				Some(_) => {},
+				None => return self.err("missing end")
+			}
+		}
+	}
+
+	/// returns the first argument
+	fn call_args(&mut self) -> Result<Value, Error> {
+		// _'name' is a valid call
+		if let Some(Token::String(s)) = self.peek() {
+			let s = s.clone();
+			self.pos += 1;
+			return Ok(Value::String(s))
+		}
+
+		self.expect_symbol("(")?;
+		let mut first = None;
+		while !self.is_symbol(")") {
+			let value = self.expression()?;
+			first.get_or_insert(value);
+			if !self.is_symbol(")") {
+				self.expect_symbol(",")?;
+			}
+		}
+		self.pos += 1;
+
+		Ok(first.unwrap_or(Value::Nil))
+	}
+
+	fn expression(&mut self) -> Result<Value, Error> {
+		self.nested(Self::or)
+	}
+
+	/// both sides are always evaluated
+	fn or(&mut self) -> Result<Value, Error> {
+		let mut value = self.and()?;
+
+		while self.is_name("or") {
+			self.pos += 1;
+			let right = self.and()?;
+			if !value.is_truthy() {
+				value = right;
+			}
+		}
+
+		Ok(value)
+	}
+
+	fn and(&mut self) -> Result<Value, Error> {
+		let mut value = self.comparison()?;
+
+		while self.is_name("and") {
+			self.pos += 1;
+			let right = self.comparison()?;
+			if value.is_truthy() {
+				value = right;
+			}
+		}
+
+		Ok(value)
+	}
+
+	fn comparison(&mut self) -> Result<Value, Error> {
+		let mut value = self.sum()?;
+
+		loop {
+			let op = match self.peek() {
+				Some(Token::Symbol(s))
+					if ["==", "~=", "<", ">", "<=", ">="].contains(s) => *s,
+				_ => return Ok(value)
+			};
+			self.pos += 1;
+			let right = self.sum()?;
+
+			value = Value::Bool(self.compare(op, &value, &right)?);
+		}
+	}
+
+	fn compare(
+		&self,
+		op: &str,
+		left: &Value,
+		right: &Value
+	) -> Result<bool, Error> {
+		let ord = match (left, right) {
+			_ if op == "==" => return Ok(left == right),
+			_ if op == "~=" => return Ok(left != right),
+			(Value::Number(a), Value::Number(b)) => a.partial_cmp(b),
+			(Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+			_ => return self.err(format!(
+				"cannot compare {} with {}",
+				left.kind(),
+				right.kind()
+			))
+		};
+
+		Ok(match (op, ord) {
+			(_, None) => false,
+			("<", Some(o)) => o.is_lt(),
+			(">", Some(o)) => o.is_gt(),
+			("<=", Some(o)) => o.is_le(),
+			(">=", Some(o)) => o.is_ge(),
+			_ => unreachable!()
+		})
+	}
+
+	fn sum(&mut self) -> Result<Value, Error> {
+		let mut value = self.term()?;
+
+		loop {
+			let op = match self.peek() {
+				Some(Token::Symbol(s)) if ["+", "-", ".."].contains(s) => *s,
+				_ => return Ok(value)
+			};
+			self.pos += 1;
+			let right = self.term()?;
+
+			value = self.binary(op, value, right)?;
+		}
+	}
+
+	fn term(&mut self) -> Result<Value, Error> {
+		let mut value = self.unary()?;
+
+		loop {
+			let op = match self.peek() {
+				Some(Token::Symbol(s)) if ["*", "/", "%"].contains(s) => *s,
+				_ => return Ok(value)
+			};
+			self.pos += 1;
+			let right = self.unary()?;
+
+			value = self.binary(op, value, right)?;
+		}
+	}
+
+	fn binary(
+		&self,
+		op: &str,
+		left: Value,
+		right: Value
+	) -> Result<Value, Error> {
+		if op == ".." {
+			return match (left, right) {
+				(Value::String(a), Value::String(b)) => {
+					Ok(Value::String(a + &b))
+				},
+				_ => self.err("can only concatenate strings")
+			}
+		}
+
+		let (a, b) = match (left.as_number(), right.as_number()) {
+			(Some(a), Some(b)) => (a, b),
+			_ => return self.err(format!(
+				"cannot use {op} on {} and {}",
+				left.kind(),
+				right.kind()
+			))
+		};
+
+		Ok(Value::Number(match op {
+			"+" => a + b,
+			"-" => a - b,
+			"*" => a * b,
+			"/" => a / b,
+			// lua rounds towards minus infinity
+			"%" => a - (a / b).floor() * b,
+			_ => unreachable!()
+		}))
+	}
+
+	fn unary(&mut self) -> Result<Value, Error> {
+		if self.is_symbol("-") {
+			self.pos += 1;
+			let value = self.nested(Self::unary)?;
+			return match value {
+				Value::Number(n) => Ok(Value::Number(-n)),
+				v => self.err(format!("cannot negate {}", v.kind()))
+			}
+		}
+
+		if self.is_name("not") {
+			self.pos += 1;
+			let value = self.nested(Self::unary)?;
+			return Ok(Value::Bool(!value.is_truthy()))
+		}
+
+		self.primary()
+	}
+
+	fn primary(&mut self) -> Result<Value, Error> {
+		match self.next() {
+			Some(Token::Number(n)) => Ok(Value::Number(n)),
+			Some(Token::String(s)) => Ok(Value::String(s)),
+			Some(Token::Symbol("{")) => self.nested(Self::table),
+			Some(Token::Symbol("(")) => {
+				let value = self.expression()?;
+				self.expect_symbol(")")?;
+				Ok(value)
+			},
+			Some(Token::Name(n)) => match n.as_str() {
+				"nil" => Ok(Value::Nil),
+				"true" => Ok(Value::Bool(true)),
+				"false" => Ok(Value::Bool(false)),
+				"function" => {
+					// the function keyword was already consumed
+					self.pos -= 1;
+					self.skip_block()?;
+					Ok(Value::Function)
+				},
+				_ => self.reference(n)
+			},
+			_ => {
+				self.pos -= 1;
+				self.err("expected a value")
+			}
+		}
+	}
+
+	/// a variable, it's fields or a call
+	fn reference(&mut self, name: String) -> Result<Value, Error> {
+		let mut value = self.locals.get(&name)
+			.or_else(|| self.globals.get(&name))
+			.or_else(|| self.predefined.get(&name))
+			.cloned()
+			.unwrap_or(Value::Nil);
+
+		loop {
+			if self.is_symbol(".") {
+				self.pos += 1;
+				let field = self.expect_name()?;
+				value = self.field(value, &field)?;
+			} else if self.is_symbol("[") {
+				self.pos += 1;
+				let key = self.expression()?;
+				self.expect_symbol("]")?;
+				value = match key {
+					Value::String(s) => self.field(value, &s)?,
+					_ => Value::Nil
+				};
+			} else if self.is_symbol("(") ||
+				matches!(self.peek(), Some(Token::String(_)))
+			{
+				value = self.call_args()?;
+			} else {
+				return Ok(value)
+			}
+		}
+	}
+
+	fn field(&self, value: Value, field: &str) -> Result<Value, Error> {
+		match value {
+			Value::Table(t) => Ok(t.get(field).cloned().unwrap_or(Value::Nil)),
+			v => self.err(format!("cannot index a {} with {field}", v.kind()))
+		}
+	}
+
+	fn table(&mut self) -> Result<Value, Error> {
+		let mut table = Table::default();
+
+		while !self.is_symbol("}") {
+			// [key] = value
+			if self.is_symbol("[") {
+				self.pos += 1;
+				let key = self.expression()?;
+				self.expect_symbol("]")?;
+				self.expect_symbol("=")?;
+				let value = self.expression()?;
+				if let Value::String(key) = key {
+					table.set(key, value);
+				}
+			// key = value
+			} else if matches!(self.peek(), Some(Token::Name(_))) &&
+				matches!(
+					self.tokens.get(self.pos + 1),
+					Some((Token::Symbol("="), _))
+				)
+			{
+				let key = self.expect_name()?;
+				self.pos += 1;
+				let value = self.expression()?;
+				table.set(key, value);
+			// array entries are not needed
+			} else {
+				self.expression()?;
+			}
+
+			if self.is_symbol(",") || self.is_symbol(";") {
+				self.pos += 1;
+			} else if !self.is_symbol("}") {
+				return self.err("expected , or }")
+			}
+		}
+		self.pos += 1;
+
+		Ok(Value::Table(table))
+	}
+}
+
+/// Runs the lua code and returns all global variables.
+///
+/// `predefined` contains variables which can be read but are not part of
+/// the result (for example `screen`).
+pub fn parse_globals(
+	s: &str,
+	predefined: &HashMap<String, Value>
+) -> Result<HashMap<String, Value>, Error> {
+	let mut parser = Parser {
+		tokens: tokenize(s)?,
+		pos: 0,
+		depth: 0,
+		globals: HashMap::new(),
+		locals: HashMap::new(),
+		predefined
+	};
+
+	parser.parse()?;
+
+	Ok(parser.globals)
+}
+
+/// creates a table with the given number fields
+pub fn number_table(fields: &[(&str, f64)]) -> Value {
+	let mut table = Table::default();
+	for (key, value) in fields {
+		table.set(key.to_string(), Value::Number(*value));
+	}
+
+	Value::Table(table)
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// like 1 Camera + 2 MFCD which ships with dcs
+	const TWO_MFCDS: &str = r#"
+_  = function(p) return p; end;
+name = _('1 Camera + 2 MFCD');
+Description = 'Two MFCDs below the main view'
+Viewports =
+{
+     Center =
+     {
+          x = 0;
+          y = 0;
+          width = screen.width;
+          height = screen.height - 400;
+          viewDx = 0;
+          viewDy = 0;
+          aspect = screen.width / (screen.height - 400);
+     }
+}
+
+local mfcd_size = 400
+LEFT_MFCD =
+{
+     x = 0;
+     y = screen.height - mfcd_size;
+     width = mfcd_size;
+     height = mfcd_size;
+}
+
+RIGHT_MFCD =
+{
+     x = screen.width - mfcd_size;
+     y = screen.height - mfcd_size;
+     width = mfcd_size;
+     height = mfcd_size;
+}
+
+UIMainView = Viewports.Center
+GU_MAIN_VIEWPORT = Viewports.Center
+"#;
+
+	/// like the wide setups which ship with dcs
+	const WIDE: &str = r#"
+_  = function(p) return p; end;
+name = _('3 Camera');
+Description = 'Three screens, one camera each'
+
+function reconfigure_for_unit(unit_type)
+	if unit_type == "A-10C" then
+		return
+	end
+end
+
+if screen.aspect >= 3 then
+	local w = screen.width / 3
+	Viewports = {
+		Left = { x = 0, y = 0, width = w, height = screen.height },
+		Center = { x = w, y = 0, width = w, height = screen.height },
+		Right = { x = 2 * w, y = 0, width = w, height = screen.height }
+	}
+elseif screen.aspect >= 2 then
+	Viewports = { Center = { x = 1, y = 1, width = 1, height = 1 } }
+else
+	Viewports = {
+		Center = {
+			x = 0; y = 0;
+			width = screen.width; height = screen.height
+		}
+	}
+end
+
+UIMainView = Viewports.Center
+"#;
+
+	fn screen(width: f64, height: f64) -> HashMap<String, Value> {
+		let mut predefined = HashMap::new();
+		predefined.insert("screen".to_string(), number_table(&[
+			("width", width),
+			("height", height),
+			("aspect", width / height)
+		]));
+		predefined
+	}
+
+	fn number(globals: &HashMap<String, Value>, path: &[&str]) -> f64 {
+		let (last, tables) = path.split_last().unwrap();
+		let mut table = globals[tables[0]].as_table().unwrap();
+		for name in &tables[1..] {
+			table = table.get(name).unwrap().as_table().unwrap();
+		}
+		table.get(last).unwrap().as_number().unwrap()
+	}
+
+	#[test]
+	fn two_mfcds() {
+		let globals = parse_globals(TWO_MFCDS, &screen(2560., 1840.))
+			.unwrap();
+
+		assert_eq!(
+			globals["name"],
+			Value::String("1 Camera + 2 MFCD".into())
+		);
+		assert!(!globals.contains_key("mfcd_size"));
+		assert_eq!(number(&globals, &["Viewports", "Center", "height"]), 1440.);
+		assert_eq!(number(&globals, &["LEFT_MFCD", "y"]), 1440.);
+		assert_eq!(number(&globals, &["RIGHT_MFCD", "x"]), 2160.);
+		assert_eq!(globals["UIMainView"], globals["Viewports"]
+			.as_table().unwrap().get("Center").unwrap().clone());
+	}
+
+	#[test]
+	fn if_blocks() {
+		let globals = parse_globals(WIDE, &screen(5760., 1080.)).unwrap();
+		assert_eq!(number(&globals, &["Viewports", "Center", "x"]), 1920.);
+		assert_eq!(number(&globals, &["Viewports", "Right", "x"]), 3840.);
+		assert!(!globals.contains_key("w"));
+
+		let globals = parse_globals(WIDE, &screen(2560., 1080.)).unwrap();
+		assert_eq!(number(&globals, &["Viewports", "Center", "x"]), 1.);
+
+		let globals = parse_globals(WIDE, &screen(1920., 1080.)).unwrap();
+		assert_eq!(number(&globals, &["UIMainView", "width"]), 1920.);
+	}
+
+	#[test]
+	fn operators() {
+		let globals = parse_globals(
+			"a = not nil\nb = 1 < 2 and 3 ~= 3\nc = nil or 'c'\n\
+			d = 'a' .. 'b' == 'ab'\ne = -(2 + 3) * 2 % 7",
+			&HashMap::new()
+		).unwrap();
+
+		assert_eq!(globals["a"], Value::Bool(true));
+		assert_eq!(globals["b"], Value::Bool(false));
+		assert_eq!(globals["c"], Value::String("c".into()));
+		assert_eq!(globals["d"], Value::Bool(true));
+		assert_eq!(globals["e"], Value::Number(4.));
+	}
+
+	#[test]
+	fn numbers() {
+		let globals = parse_globals(
+			"a = 1e-5\nb = 0x10\nc = 2.5E+2\nd = .5\ne = 0XfF",
+			&HashMap::new()
+		).unwrap();
+
+		assert_eq!(globals["a"], Value::Number(1e-5));
+		assert_eq!(globals["b"], Value::Number(16.));
+		assert_eq!(globals["c"], Value::Number(250.));
+		assert_eq!(globals["d"], Value::Number(0.5));
+		assert_eq!(globals["e"], Value::Number(255.));
+	}
+
+	#[test]
+	fn malformed() {
+		let cases = [
+			("a = ", 1),
+			("a = {\n x = 1", 2),
+			("a = 'unfinished", 1),
+			("a = 1x", 1),
+			("a = 0x", 1),
+			("\n\nb.c = 1", 3),
+			("a = 1 + {}", 1),
+			("a = 1 < 'b'", 1),
+			("if true then\na = 1", 2),
+			("if true a = 1 end", 1),
+			("function f() return", 1),
+			("a = $", 1)
+		];
+
+		for (lua, line) in cases {
+			let e = parse_globals(lua, &HashMap::new()).unwrap_err();
+			assert_eq!(e.line, line, "{lua}: {e}");
+		}
+	}
+
+	#[test]
+	fn deep_nesting() {
+		let inputs = [
+			format!("a = {}", "{".repeat(100_000)),
+			format!("a = {}1", "(".repeat(100_000)),
+			// -- would be a comment
+			format!("a = {}1", "- ".repeat(100_000)),
+			format!("a = {}1", "not ".repeat(100_000)),
+			"if true then ".repeat(100_000)
+		];
+
+		for lua in inputs {
+			let e = parse_globals(&lua, &HashMap::new()).unwrap_err();
+			assert_eq!(e.msg, "nested too deeply");
+		}
+
+		// reasonable nesting still works
+		let lua = format!("a = {}1{}", "(".repeat(50), ")".repeat(50));
+		let globals = parse_globals(&lua, &HashMap::new()).unwrap();
+		assert_eq!(globals["a"], Value::Number(1.));
+	}
+}
@@ -7,6 +7,32 @@ use dcs_bios::DcsBios;
 use dcs_bios::control_definitions::ControlDefinitions;
 mod displays;
 use displays::{DisplaySetup, Displays};
+mod handshake;
+mod connections;
+use connections::Connections;
+mod config;
+use config::Config;
+mod auth;
+use auth::Tokens;
+mod net;
+mod tls;
+use tls::{Certificate, TlsInfo};
+mod mdns;
+mod monitor_setup;
+mod lua;
+mod rescale;
+mod adaptive;
+mod debrief;
+use debrief::{Replaying, Recorder, Playback};
+mod mjpeg;
+mod snapshot;
+#[cfg(feature = "h264")]
+mod h264;
+use monitor_setup::MonitorSetup;
+
+use std::net::SocketAddr;
+
+const PORT: u16 = 3511;
 #[cfg(feature = "self-host")]
 mod web_api;
 
@@ -14,39 +40,104 @@ mod web_api;
 #[tokio::main]
 async fn main() {
 	let display_setup = DisplaySetup::new();
-	// for the moment let's just hard code these values
-	display_setup.set(Some(Displays::default()));
+	let replaying = Replaying::new();
+	let displays = Displays::load()
+		.expect("failed to load displays")
+		.unwrap_or_else(Displays::default);
+	display_setup.set(Some(displays));
 
 	let (
 		virtual_display,
 		virtual_display_task
-	) = VirtualDisplay::new(display_setup.clone());
+	) = VirtualDisplay::new(display_setup.clone(), replaying.clone());
 
 	let control_defs = ControlDefinitions::new().await
 		.expect("failed to open control definitions");
 
-	let (dcs_bios, dcs_bios_task) = DcsBios::new(control_defs.clone());
+	let (dcs_bios, dcs_bios_task) = DcsBios::new(
+		control_defs.clone(),
+		replaying.clone()
+	);
+
+	let recorder = Recorder::new(
+		virtual_display.clone(),
+		dcs_bios.clone(),
+		display_setup.clone()
+	);
+	let playback = Playback::new(
+		replaying,
+		virtual_display.clone(),
+		dcs_bios.clone()
+	);
+
+	let config = Config::load()
+		.expect("failed to load config");
+
+	let monitor_setup = MonitorSetup::load()
+		.expect("failed to load monitor setup settings");
+
+	let certificate = config.tls.then(|| {
+		let cert = Certificate::load_or_generate()
+			.expect("failed to load certificate");
+		eprintln!("certificate fingerprint (sha256) {}", cert.fingerprint());
+		cert
+	});
+	let scheme = if certificate.is_some() { "https" } else { "http" };
+
+	let tokens = Tokens::load(scheme, PORT)
+		.expect("failed to load tokens");
+
+	// needs to be kept alive for the service to stay advertised
+	let _mdns = if config.mdns {
+		mdns::advertise(PORT, certificate.is_some())
+			.map_err(|e| eprintln!("could not advertise via mdns {e}"))
+			.ok()
+	} else {
+		None
+	};
 
-	let mut server = fire::build("0.0.0.0:3511").await.unwrap();
+	let addr = SocketAddr::from(([0, 0, 0, 0], PORT));
+	let mut server = fire::build(addr).await.unwrap();
 
 	server.add_data(virtual_display);
 	server.add_data(display_setup);
 	server.add_data(control_defs);
 	server.add_data(dcs_bios);
+	server.add_data(Connections::new());
+	server.add_data(tokens);
+	server.add_data(config);
+	server.add_data(TlsInfo { certificate: certificate.clone() });
+	server.add_data(monitor_setup);
+	server.add_data(recorder);
+	server.add_data(playback);
 
 	mfds::handle(&mut server);
+	mjpeg::handle(&mut server);
+	snapshot::handle(&mut server);
+	#[cfg(feature = "h264")]
+	h264::handle(&mut server);
 	dcs_bios::api::handle(&mut server);
+	connections::handle(&mut server);
+	auth::handle(&mut server);
+	tls::handle(&mut server);
+	displays::handle(&mut server);
+	monitor_setup::handle(&mut server);
+	debrief::api::handle(&mut server);
 	#[cfg(feature = "self-host")]
 	web_api::handle(&mut server);
 
-	eprintln!("!! Open http://127.0.0.1:3511 !!");
+	eprintln!("!! Open {scheme}://127.0.0.1:{PORT} !!");
 
 	tokio::try_join!(
 		virtual_display_task,
 		dcs_bios_task,
 		tokio::spawn(async move {
-			server.ignite().await
-				.expect("server paniced");
+			match certificate {
+				Some(cert) => tls::serve(server, addr, &cert).await
+					.expect("tls server failed"),
+				None => server.ignite().await
+					.expect("server paniced")
+			}
 		})
 	).expect("one task failed");
 }
\ No newline at end of file
@@ -0,0 +1,47 @@
+use std::env;
+
+use mdns_sd::{ServiceDaemon, ServiceInfo, Error};
+
+/// the service type clients can browse for
+pub const SERVICE_TYPE: &str = "_tcd._tcp.local.";
+
+/// Advertises the server on the local network via multicast dns.
+///
+/// The service is advertised as long as the returned daemon is not dropped.
+/// The txt record contains the server version, the protocol version and if
+/// tls is used.
+pub fn advertise(port: u16, tls: bool) -> Result<ServiceDaemon, Error> {
+	let daemon = ServiceDaemon::new()?;
+
+	let name = host_name();
+	let properties = [
+		("version", env!("CARGO_PKG_VERSION").to_string()),
+		("protocol", crate::handshake::PROTOCOL_VERSION.to_string()),
+		("tls", tls.to_string())
+	];
+
+	// the addresses get filled in by the daemon and are updated if the
+	// network changes
+	let service = ServiceInfo::new(
+		SERVICE_TYPE,
+		&name,
+		&format!("{name}.local."),
+		(),
+		port,
+		&properties[..]
+	)?.enable_addr_auto();
+
+	daemon.register(service)?;
+
+	Ok(daemon)
+}
+
+/// returns the name of this computer or tcd if it is not known
+fn host_name() -> String {
+	// windows uses COMPUTERNAME
+	env::var("COMPUTERNAME")
+		.or_else(|_| env::var("HOSTNAME"))
+		.ok()
+		.filter(|name| !name.is_empty())
+		.unwrap_or_else(|| "tcd".into())
+}
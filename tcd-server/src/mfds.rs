@@ -1,64 +1,152 @@
 use crate::api_error::Error;
-use crate::displays::DisplayKind;
-use crate::VirtualDisplay;
+use crate::displays::{DisplaySetup, DisplayFrames, Displays};
+use crate::virtual_display::Subscription;
+use crate::adaptive::Adaptive;
+use crate::handshake::{handshake, update_role, ServerHello};
+use crate::connections::{Connections, Liveness, ACK_TIMEOUT};
+use crate::auth::Tokens;
+use crate::config::Config;
+use crate::rescale::Size;
+use crate::{VirtualDisplay, DcsBios};
 
-use std::collections::HashSet;
+use std::collections::HashMap;
+
+use tokio::time::{self, Instant};
 
 use serde::{Serialize, Deserialize};
 
 use fire::{FireBuilder, ws};
 use fire::ws::WebSocket;
+use fire::header::RequestHeader;
 
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 enum Request {
-	Subscribe(DisplayKind),
-	Unsubscribe(DisplayKind),
-	Aknowledge
+	/// the name of the display
+	Subscribe(String),
+	Unsubscribe(String),
+	/// the server rescales the frames of the display to fit inside of the
+	/// size, None sends the original size
+	SetSize {
+		name: String,
+		size: Option<Size>
+	},
+	Aknowledge,
+	Pong
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct DisplayFramesAnnouncement {
-	list: Vec<DisplayKind>
+	list: Vec<AnnouncedFrame>
+}
+
+/// Each announced frame is followed by a binary message with the keyframe
+/// (if there is one) and a binary message for every tile.
+///
+/// The client needs to draw the tiles on top of the previous frame.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct AnnouncedFrame {
+	name: String,
+	/// needed so browsers decode the image correctly
+	mime: String,
+	keyframe: bool,
+	tiles: Vec<TilePosition>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TilePosition {
+	x: u32,
+	y: u32
 }
 
 #[ws("/api/mfds")]
 async fn mfds(
 	mut ws: WebSocket,
-	virtual_display: &VirtualDisplay
+	virtual_display: &VirtualDisplay,
+	dcs_bios: &DcsBios,
+	display_setup: &DisplaySetup,
+	connections: &Connections,
+	tokens: &Tokens,
+	config: &Config
 ) -> Result<(), Error> {
+	let hello = ServerHello::new(dcs_bios, display_setup, config);
+	let session = match handshake(&mut ws, hello, tokens).await? {
+		Some((_, session)) => session,
+		None => return Ok(())
+	};
+
+	let mut connection = connections.register("mfds", &session);
+	let mut liveness = Liveness::new();
+
 	let mut virtual_display = virtual_display.clone();
-	let mut subscribed = HashSet::new();
+	let mut subscribed: HashMap<String, Subscription> = HashMap::new();
+	// sizes can be set before subscribing
+	let mut sizes = HashMap::new();
 	let mut was_aknowledged = true;
+	let mut sent_at = Instant::now();
+	let mut sent_bytes = 0;
+	// if a new frame arrived while waiting for the aknowledgment or the
+	// frame rate did not allow to send it
+	let mut missed_frame = false;
+	let mut adaptive = Adaptive::new(&config.stream);
 
 	loop {
-		tokio::select! {
-			mut monitors = virtual_display.recv(&subscribed),
-				if !subscribed.is_empty() && was_aknowledged
-			=> {
-				// send them
-				let list: Vec<_> = monitors.keys().map(|k| *k).collect();
-				if list.is_empty() {
+		let monitors = tokio::select! {
+			_ = virtual_display.changed(), if !subscribed.is_empty() => {
+				// the frame will be sent once the client aknowledges
+				if !was_aknowledged {
+					connection.frame_dropped();
+					missed_frame = true;
 					continue
 				}
 
-				let announcement = DisplayFramesAnnouncement {
-					list: list.clone()
-				};
+				// the frame will be sent once the frame rate allows it
+				if !adaptive.may_send() {
+					missed_frame = true;
+					continue
+				}
 
-				ws.serialize(&announcement).await
-					.map_err(|e| Error::Internal(e.to_string()))?;
+				virtual_display.frames(&subscribed)
+			},
+			_ = time::sleep_until(adaptive.next_frame_at()),
+				if was_aknowledged && missed_frame =>
+			{
+				missed_frame = false;
 
-				for kind in list {
-					let image = monitors.remove(&kind)
-						// we always need to send data
-						.unwrap_or_else(Vec::new);
+				virtual_display.frames(&subscribed)
+			},
+			// the client is stalled, let's send the latest frames again
+			_ = time::sleep_until(sent_at + ACK_TIMEOUT), if !was_aknowledged => {
+				connection.ack_timed_out();
+				// if there is nothing to send the timeout should not fire
+				// again
+				was_aknowledged = true;
+				sent_at = Instant::now();
+				missed_frame = false;
+				adaptive.timed_out();
+				set_qualities(&mut subscribed, &adaptive, display_setup.get());
+
+				// start with a keyframe in case the client lost something
+				for sub in subscribed.values_mut() {
+					sub.received = None;
+				}
 
-					ws.send(image).await
-						.map_err(|e| Error::Internal(e.to_string()))?;
+				virtual_display.frames(&subscribed)
+			},
+			_ = liveness.tick() => {
+				if !liveness.ping(&mut ws).await? {
+					return Ok(())
+				}
+
+				continue
+			},
+			// every role can watch the displays
+			role = connection.role_changed() => {
+				if update_role(&mut ws, role).await?.is_none() {
+					return Ok(())
 				}
 
-				was_aknowledged = false;
+				continue
 			},
 			req = ws.deserialize() => {
 				let maybe_req: Option<Request> = req
@@ -69,22 +157,168 @@ async fn mfds(
 					None => return Ok(())
 				};
 
+				liveness.received();
+
 				match req {
-					Request::Subscribe(kind) => {
-						let _ = subscribed.insert(kind);
+					Request::Subscribe(name) => {
+						let sub = Subscription {
+							received: None,
+							size: sizes.get(&name).copied(),
+							quality: None
+						};
+						let _ = subscribed.insert(name, sub);
+						set_qualities(
+							&mut subscribed,
+							&adaptive,
+							display_setup.get()
+						);
+						continue
+					},
+					Request::Unsubscribe(name) => {
+						let _ = subscribed.remove(&name);
+						let _ = sizes.remove(&name);
+						continue
 					},
-					Request::Unsubscribe(kind) => {
-						let _ = subscribed.remove(&kind);
+					Request::SetSize { name, size } => {
+						let size = size.filter(|s| s.width > 0 && s.height > 0);
+						// the new size needs a new keyframe
+						if let Some(sub) = subscribed.get_mut(&name) {
+							sub.size = size;
+							sub.received = None;
+						}
+						match size {
+							Some(size) => sizes.insert(name, size),
+							None => sizes.remove(&name)
+						};
+						continue
 					},
 					Request::Aknowledge => {
 						was_aknowledged = true;
-					}
+						adaptive.aknowledged(sent_bytes);
+						connection.set_adaptive(adaptive.stats());
+						set_qualities(
+							&mut subscribed,
+							&adaptive,
+							display_setup.get()
+						);
+
+						if !missed_frame || !adaptive.may_send() {
+							continue
+						}
+
+						missed_frame = false;
+						virtual_display.frames(&subscribed)
+					},
+					Request::Pong => continue
 				}
 			}
+		};
+
+		let monitors = virtual_display.rescaler().rescale_frames(
+			monitors,
+			&subscribed,
+			display_setup.get()
+		).await;
+
+		for name in monitors.keys() {
+			if let Some(sub) = subscribed.get_mut(name) {
+				sub.received = monitors.get(name).unwrap().latest_id();
+			}
+		}
+
+		let bytes = send_frames(&mut ws, monitors).await?;
+		if bytes > 0 {
+			was_aknowledged = false;
+			sent_at = Instant::now();
+			sent_bytes = bytes;
+			adaptive.sent();
+		}
+	}
+}
+
+/// lowers the jpeg quality of the subscribed displays if the client is slow
+fn set_qualities(
+	subscribed: &mut HashMap<String, Subscription>,
+	adaptive: &Adaptive,
+	displays: Option<Displays>
+) {
+	for (name, sub) in subscribed.iter_mut() {
+		sub.quality = displays.as_ref()
+			.and_then(|d| d.get(name))
+			.and_then(|d| adaptive.quality(d.format));
+	}
+}
+
+/// returns how many bytes were sent, 0 if there was nothing to send
+async fn send_frames(
+	ws: &mut WebSocket,
+	mut monitors: DisplayFrames
+) -> Result<usize, Error> {
+	let list: Vec<_> = monitors.keys().cloned().collect();
+	if list.is_empty() {
+		return Ok(0)
+	}
+
+	let mut bytes = 0;
+
+	let announcement = DisplayFramesAnnouncement {
+		list: list.iter()
+			.map(|name| {
+				let frame = monitors.get(name).unwrap();
+				// all parts of a frame have the same format
+				let format = frame.keyframe.as_ref()
+					.or_else(|| frame.tiles.first().map(|t| &t.frame))
+					.map(|f| f.format.mime())
+					.unwrap_or_default();
+
+				AnnouncedFrame {
+					name: name.clone(),
+					mime: format.into(),
+					keyframe: frame.keyframe.is_some(),
+					tiles: frame.tiles.iter()
+						.map(|t| TilePosition { x: t.x, y: t.y })
+						.collect()
+				}
+			})
+			.collect()
+	};
+
+	ws.serialize(&announcement).await
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	for name in list {
+		let frame = monitors.remove(&name).unwrap();
+
+		if let Some(keyframe) = frame.keyframe {
+			bytes += keyframe.data.len();
+			ws.send(keyframe.data).await
+				.map_err(|e| Error::Internal(e.to_string()))?;
+		}
+
+		for tile in frame.tiles {
+			bytes += tile.frame.data.len();
+			ws.send(tile.frame.data).await
+				.map_err(|e| Error::Internal(e.to_string()))?;
 		}
 	}
+
+	Ok(bytes)
+}
+
+/// Returns the name of the display if the path is `/api/mfds/{name}.{ext}`,
+/// used by the routes which serve a display as a plain image or stream.
+pub(crate) fn display_from_path<'a>(
+	header: &'a RequestHeader,
+	extension: &str
+) -> Option<&'a str> {
+	let (name, ext) = header.uri().path()
+		.strip_prefix("/api/mfds/")?
+		.rsplit_once('.')?;
+
+	(ext == extension && !name.is_empty() && !name.contains('/'))
+		.then_some(name)
 }
 
 pub(crate) fn handle(fire: &mut FireBuilder) {
 	fire.add_raw_route(mfds);
-}
\ No newline at end of file
+}
@@ -0,0 +1,189 @@
+use crate::api_error::Error;
+use crate::auth::Tokens;
+use crate::config::Config;
+use crate::connections::{Connections, Connection};
+use crate::displays::{DisplaySetup, FrameFormat};
+use crate::mfds::display_from_path;
+use crate::rescale::Target;
+use crate::VirtualDisplay;
+
+use std::io;
+
+use tokio::io::{AsyncWriteExt, DuplexStream};
+use tokio::time::{self, Duration, Instant};
+
+use fire::{FireBuilder, Request, Response, Body, Data};
+use fire::header::{RequestHeader, Method};
+use fire::routes::Route;
+use fire::util::PinnedFuture;
+use fire::into::IntoResponse;
+
+const EXTENSION: &str = "mjpeg";
+const BOUNDARY: &str = "frame";
+/// how much of the stream get's buffered if the client reads slowly
+const BUFFER_SIZE: usize = 256 * 1024;
+
+/// Serves a display as a multipart mjpeg stream at `/api/mfds/{name}.mjpeg`
+/// so it can be shown without the mfds protocol, for example in OBS or VLC.
+///
+/// Those can't send the token header, so the token can also be passed as
+/// `?token=`.
+struct MjpegRoute;
+
+impl Route for MjpegRoute {
+	fn check(&self, header: &RequestHeader) -> bool {
+		header.method() == Method::GET &&
+			display_from_path(header, EXTENSION).is_some()
+	}
+
+	fn validate_data(&self, data: &Data) {
+		assert!(data.exists::<VirtualDisplay>());
+		assert!(data.exists::<DisplaySetup>());
+		assert!(data.exists::<Tokens>());
+		assert!(data.exists::<Config>());
+		assert!(data.exists::<Connections>());
+	}
+
+	fn call<'a>(
+		&'a self,
+		req: &'a mut Request,
+		data: &'a Data
+	) -> PinnedFuture<'a, fire::Result<Response>> {
+		PinnedFuture::new(async move {
+			Ok(match start_stream(req.header(), data) {
+				Ok(resp) => resp,
+				Err(e) => e.into_response()
+			})
+		})
+	}
+}
+
+fn start_stream(
+	header: &RequestHeader,
+	data: &Data
+) -> Result<Response, Error> {
+	// the route checked that the data exists
+	let virtual_display = data.get::<VirtualDisplay>().unwrap();
+	let display_setup = data.get::<DisplaySetup>().unwrap();
+	let tokens = data.get::<Tokens>().unwrap();
+	let config = data.get::<Config>().unwrap();
+	let connections = data.get::<Connections>().unwrap();
+
+	let session = tokens.check_header_or_query(header)?;
+
+	let name = display_from_path(header, EXTENSION).unwrap().to_string();
+	let exists = display_setup.get()
+		.map(|displays| displays.get(&name).is_some())
+		.unwrap_or(false);
+	if !exists {
+		return Err(Error::Request(format!("display {name} does not exist")))
+	}
+
+	let (writer, reader) = tokio::io::duplex(BUFFER_SIZE);
+	let stream = Stream {
+		name,
+		virtual_display: virtual_display.clone(),
+		display_setup: display_setup.clone(),
+		interval: Duration::from_secs_f64(
+			1. / config.stream.max_fps.max(1) as f64
+		),
+		connection: connections.register("mjpeg", &session),
+		writer,
+		sent: None
+	};
+	tokio::spawn(async move {
+		// fails once the client disconnects
+		let _ = stream.run().await;
+	});
+
+	let content_type = format!("multipart/x-mixed-replace; boundary={BOUNDARY}");
+	Ok(Response::builder()
+		.content_type(content_type)
+		.header("cache-control", "no-cache")
+		.body(Body::from_async_reader(reader))
+		.build())
+}
+
+struct Stream {
+	name: String,
+	virtual_display: VirtualDisplay,
+	display_setup: DisplaySetup,
+	/// the minimum time between two frames
+	interval: Duration,
+	// keeps the connection listed while streaming
+	connection: Connection,
+	writer: DuplexStream,
+	/// the id of the last frame which was sent
+	sent: Option<u64>
+}
+
+impl Stream {
+	async fn run(mut self) -> io::Result<()> {
+		loop {
+			let next_frame_at = Instant::now() + self.interval;
+			self.send_frame().await?;
+
+			tokio::select! {
+				_ = self.virtual_display.changed() => {},
+				role = self.connection.role_changed() => {
+					// the token was revoked
+					if role.is_none() {
+						return Ok(())
+					}
+
+					continue
+				}
+			}
+			time::sleep_until(next_frame_at).await;
+		}
+	}
+
+	async fn send_frame(&mut self) -> io::Result<()> {
+		let frame = match self.virtual_display.frame(&self.name) {
+			Some(f) => f,
+			None => return Ok(())
+		};
+		if frame.latest_id() == self.sent {
+			return Ok(())
+		}
+
+		let display = self.display_setup.get()
+			.and_then(|displays| displays.get(&self.name).cloned());
+		let display = match display {
+			Some(d) => d,
+			None => return Ok(())
+		};
+
+		// the driver might send png or webp and only the changed tiles
+		let target = Target {
+			size: None,
+			quality: None,
+			format: Some(FrameFormat::Jpeg)
+		};
+		let frame = self.virtual_display.rescaler()
+			.rescale(&self.name, frame, &display, target).await;
+		let id = frame.latest_id();
+		let keyframe = match frame.keyframe {
+			Some(k) if k.format == FrameFormat::Jpeg => k,
+			// the frame could not be converted
+			_ => return Ok(())
+		};
+
+		let part_header = format!(
+			"--{BOUNDARY}\r\n\
+			Content-Type: image/jpeg\r\n\
+			Content-Length: {}\r\n\r\n",
+			keyframe.data.len()
+		);
+		self.writer.write_all(part_header.as_bytes()).await?;
+		self.writer.write_all(&keyframe.data).await?;
+		self.writer.write_all(b"\r\n").await?;
+		self.sent = id;
+
+		Ok(())
+	}
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(MjpegRoute);
+}
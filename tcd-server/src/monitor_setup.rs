@@ -0,0 +1,683 @@
+use crate::api_error::Error;
+use crate::auth::Tokens;
+use crate::config::data_dir;
+use crate::displays::{DisplaySetup, Displays, Display, Format};
+use crate::lua::{self, Value, Table};
+
+use std::{env, fs, io};
+use std::fmt::Write;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+
+use fire::FireBuilder;
+use fire::header::RequestHeader;
+use fire_api::{api, Request, Method};
+
+const SETTINGS_FILE: &str = "monitor_setup.json";
+/// the name of the file in the MonitorSetup folder of dcs
+const LUA_FILE: &str = "tcd.lua";
+/// the name shown in the dcs settings
+const SETUP_NAME: &str = "Tcd Virtual Display";
+
+/// Everything besides the displays which is needed to generate the
+/// MonitorSetup lua file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MonitorSettings {
+	/// the viewport where dcs renders the 3d view
+	pub main: Viewport,
+	/// where the virtual display is placed in the area dcs renders to,
+	/// the display rectangles are relative to it
+	pub display_x: u32,
+	pub display_y: u32
+}
+
+impl Default for MonitorSettings {
+	fn default() -> Self {
+		Self {
+			main: Viewport {
+				x: 0,
+				y: 0,
+				width: 2560,
+				height: 1440
+			},
+			display_x: 0,
+			display_y: 1440
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Viewport {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32
+}
+
+impl MonitorSettings {
+	fn load() -> io::Result<Self> {
+		match fs::read(data_dir().join(SETTINGS_FILE)) {
+			Ok(v) => serde_json::from_slice(&v).map_err(io::Error::other),
+			Err(e) if e.kind() == io::ErrorKind::NotFound => {
+				Ok(Self::default())
+			},
+			Err(e) => Err(e)
+		}
+	}
+
+	fn save(&self) -> io::Result<()> {
+		let dir = data_dir();
+		fs::create_dir_all(&dir)?;
+
+		let v = serde_json::to_vec_pretty(self)?;
+		fs::write(dir.join(SETTINGS_FILE), v)
+	}
+
+	/// the displays are checked if they fit at `display_x` and `display_y`
+	fn validate(&self, displays: Option<&Displays>) -> Result<(), String> {
+		if self.main.width == 0 || self.main.height == 0 {
+			return Err("the main viewport has no size".into())
+		}
+
+		let fits = self.main.x.checked_add(self.main.width).is_some() &&
+			self.main.y.checked_add(self.main.height).is_some();
+		if !fits {
+			return Err("the main viewport is too big".into())
+		}
+
+		for (name, display) in displays.into_iter().flat_map(|d| d.iter()) {
+			self.display_viewport(name, display)?;
+		}
+
+		Ok(())
+	}
+
+	/// where the display is placed in the area dcs renders to
+	fn display_viewport(
+		&self,
+		name: &str,
+		display: &Display
+	) -> Result<Viewport, String> {
+		let viewport = self.display_x.checked_add(display.x)
+			.zip(self.display_y.checked_add(display.y))
+			.map(|(x, y)| Viewport {
+				x,
+				y,
+				width: display.width,
+				height: display.height
+			})
+			.filter(|v| {
+				v.x.checked_add(v.width).is_some() &&
+					v.y.checked_add(v.height).is_some()
+			});
+
+		viewport.ok_or_else(|| format!(
+			"{name} does not fit at {}, {}",
+			self.display_x,
+			self.display_y
+		))
+	}
+}
+
+/// Generates the MonitorSetup lua file which contains the main viewport and
+/// a global table for every display.
+///
+/// Returns an error if a display does not fit at the position of the
+/// virtual display.
+pub fn generate(
+	settings: &MonitorSettings,
+	displays: &Displays
+) -> Result<String, String> {
+	let main = &settings.main;
+	let aspect = main.width as f64 / main.height as f64;
+
+	let mut s = String::new();
+	// writing to a string cannot fail
+	writeln!(s, "-- generated by tcd-server, changes will be overwritten")
+		.unwrap();
+	writeln!(s, "_  = function(p) return p; end;").unwrap();
+	writeln!(s, "name = _('{SETUP_NAME}');").unwrap();
+	writeln!(s, "Description = '{SETUP_NAME}'").unwrap();
+	writeln!(s, "Viewports =\n{{\n     Center =\n     {{").unwrap();
+	write_fields(&mut s, main, "          ");
+	writeln!(s, "          viewDx = 0;").unwrap();
+	writeln!(s, "          viewDy = 0;").unwrap();
+	writeln!(s, "          aspect = {aspect:.13};").unwrap();
+	writeln!(s, "     }}\n}}").unwrap();
+
+	// sort them so the file does not change if nothing changed
+	let mut list: Vec<_> = displays.iter().collect();
+	list.sort_by(|a, b| a.0.cmp(b.0));
+
+	for (name, display) in list {
+		let viewport = settings.display_viewport(name, display)?;
+
+		writeln!(s, "\n{name} =\n{{").unwrap();
+		write_fields(&mut s, &viewport, "     ");
+		writeln!(s, "}}").unwrap();
+	}
+
+	writeln!(s, "\nUIMainView = Viewports.Center").unwrap();
+	writeln!(s, "GU_MAIN_VIEWPORT = Viewports.Center").unwrap();
+
+	Ok(s)
+}
+
+fn write_fields(s: &mut String, viewport: &Viewport, indent: &str) {
+	writeln!(s, "{indent}x = {};", viewport.x).unwrap();
+	writeln!(s, "{indent}y = {};", viewport.y).unwrap();
+	writeln!(s, "{indent}width = {};", viewport.width).unwrap();
+	writeln!(s, "{indent}height = {};", viewport.height).unwrap();
+}
+
+/// globals which reference the main viewport and are not displays
+const MAIN_ALIASES: &[&str] = &["UIMainView", "GU_MAIN_VIEWPORT"];
+
+/// The size of the screen, some MonitorSetup files calculate the viewports
+/// from `screen.width` and `screen.height`.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct ScreenSize {
+	pub width: u32,
+	pub height: u32
+}
+
+/// Reads an existing MonitorSetup file and converts it into settings and
+/// displays.
+///
+/// The center viewport becomes the main viewport and every other global
+/// table with a rectangle becomes a display. The virtual display is placed
+/// at the top left corner of all displays.
+pub fn import(
+	lua: &str,
+	screen: Option<ScreenSize>
+) -> Result<(MonitorSettings, Displays), String> {
+	let mut predefined = HashMap::new();
+	if let Some(screen) = screen {
+		predefined.insert("screen".to_string(), lua::number_table(&[
+			("width", screen.width as f64),
+			("height", screen.height as f64),
+			("aspect", screen.width as f64 / screen.height as f64)
+		]));
+	}
+
+	let globals = lua::parse_globals(lua, &predefined)
+		.map_err(|e| format!("could not parse the file, {e}"))?;
+
+	let main = globals.get("Viewports")
+		.and_then(Value::as_table)
+		.and_then(|t| t.get("Center"))
+		.and_then(Value::as_table)
+		.ok_or("the file does not contain Viewports.Center")?;
+	let main = viewport("Viewports.Center", main)?
+		.ok_or("Viewports.Center is not a rectangle")?;
+
+	let mut viewports = vec![];
+	for (name, value) in &globals {
+		if name == "Viewports" || MAIN_ALIASES.contains(&name.as_str()) {
+			continue
+		}
+
+		let table = match value.as_table() {
+			Some(t) => t,
+			None => continue
+		};
+		if let Some(viewport) = viewport(name, table)? {
+			viewports.push((name.clone(), viewport));
+		}
+	}
+
+	let display_x = viewports.iter().map(|(_, v)| v.x).min()
+		.ok_or("the file does not contain any displays")?;
+	let display_y = viewports.iter().map(|(_, v)| v.y).min().unwrap();
+
+	let displays = viewports.into_iter()
+		.map(|(name, v)| (name, Display {
+			x: v.x - display_x,
+			y: v.y - display_y,
+			width: v.width,
+			height: v.height,
+			format: Format::default()
+		}))
+		.collect();
+	let displays = Displays::new(displays);
+	displays.validate()?;
+
+	let settings = MonitorSettings { main, display_x, display_y };
+	settings.validate(Some(&displays))?;
+
+	Ok((settings, displays))
+}
+
+/// returns None if the table does not contain a rectangle
+fn viewport(name: &str, table: &Table) -> Result<Option<Viewport>, String> {
+	let fields = ["x", "y", "width", "height"]
+		.map(|key| table.get(key).and_then(Value::as_number));
+	let [x, y, width, height] = match fields {
+		[Some(x), Some(y), Some(w), Some(h)] => [x, y, w, h],
+		_ => return Ok(None)
+	};
+
+	let to_u32 = |v: f64| {
+		if v.is_finite() && v >= 0. && v <= u32::MAX as f64 {
+			Ok(v.round() as u32)
+		} else {
+			Err(format!("{name} has an invalid value {v}"))
+		}
+	};
+
+	Ok(Some(Viewport {
+		x: to_u32(x)?,
+		y: to_u32(y)?,
+		width: to_u32(width)?,
+		height: to_u32(height)?
+	}))
+}
+
+/// returns the MonitorSetup folders of all dcs installations in the saved
+/// games folder
+fn monitor_setup_dirs() -> Vec<PathBuf> {
+	let saved_games = match env::var("USERPROFILE") {
+		Ok(p) => PathBuf::from(p).join("Saved Games"),
+		Err(_) => return vec![]
+	};
+
+	["DCS", "DCS.openbeta"].into_iter()
+		.map(|name| saved_games.join(name))
+		.filter(|path| path.is_dir())
+		.map(|path| path.join("Config").join("MonitorSetup"))
+		.collect()
+}
+
+/// Holds the settings and keeps the installed lua files up to date
+#[derive(Debug, Clone)]
+pub struct MonitorSetup {
+	inner: Arc<Mutex<MonitorSettings>>
+}
+
+impl MonitorSetup {
+	pub fn load() -> io::Result<Self> {
+		Ok(Self {
+			inner: Arc::new(Mutex::new(MonitorSettings::load()?))
+		})
+	}
+
+	pub fn settings(&self) -> MonitorSettings {
+		self.inner.lock().unwrap().clone()
+	}
+
+	/// writes the lua file to every dcs installation, returns the written
+	/// paths
+	fn install(&self, displays: &Displays) -> io::Result<Vec<PathBuf>> {
+		let dirs = monitor_setup_dirs();
+		if dirs.is_empty() {
+			return Err(io::Error::other("no dcs saved games folder found"))
+		}
+
+		let lua = generate(&self.settings(), displays)
+			.map_err(io::Error::other)?;
+		dirs.into_iter()
+			.map(|dir| {
+				fs::create_dir_all(&dir)?;
+				let path = dir.join(LUA_FILE);
+				fs::write(&path, &lua)?;
+				Ok(path)
+			})
+			.collect()
+	}
+
+	/// checks if the displays fit with the current settings
+	pub fn validate(&self, displays: &Displays) -> Result<(), String> {
+		self.settings().validate(Some(displays))
+	}
+
+	/// Needs to be called before the displays change, rewrites the lua
+	/// files which were installed before.
+	///
+	/// The lua files are written before anything is applied, so if this
+	/// fails nothing changed.
+	pub fn update_installed(&self, displays: &Displays) -> io::Result<()> {
+		update_installed(&self.settings(), displays)
+	}
+}
+
+fn update_installed(
+	settings: &MonitorSettings,
+	displays: &Displays
+) -> io::Result<()> {
+	let lua = generate(settings, displays)
+		.map_err(io::Error::other)?;
+
+	for dir in monitor_setup_dirs() {
+		let path = dir.join(LUA_FILE);
+		if path.is_file() {
+			fs::write(path, &lua)?;
+		}
+	}
+
+	Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SettingsReq;
+
+impl Request for SettingsReq {
+	type Response = MonitorSettings;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/monitor-setup/settings";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(SettingsReq)]
+fn settings_get(
+	_req: SettingsReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	monitor_setup: &MonitorSetup
+) -> Result<MonitorSettings, Error> {
+	tokens.check_header(header)?;
+
+	Ok(monitor_setup.settings())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct SetSettingsReq {
+	settings: MonitorSettings
+}
+
+impl Request for SetSettingsReq {
+	type Response = ();
+	type Error = Error;
+
+	const PATH: &'static str = "/api/monitor-setup/settings";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(SetSettingsReq)]
+fn settings_set(
+	req: SetSettingsReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	monitor_setup: &MonitorSetup,
+	display_setup: &DisplaySetup
+) -> Result<(), Error> {
+	tokens.check_admin(header)?;
+
+	let displays = display_setup.get();
+	req.settings.validate(displays.as_ref())
+		.map_err(Error::Request)?;
+
+	if let Some(displays) = &displays {
+		update_installed(&req.settings, displays)
+			.map_err(|e| Error::Internal(e.to_string()))?;
+	}
+
+	req.settings.save()
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	*monitor_setup.inner.lock().unwrap() = req.settings;
+
+	Ok(())
+}
+
+/// Returns the generated lua file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LuaReq;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct LuaResp {
+	/// the name the file should have
+	file_name: String,
+	lua: String
+}
+
+impl Request for LuaReq {
+	type Response = LuaResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/monitor-setup";
+	const METHOD: Method = Method::GET;
+}
+
+#[api(LuaReq)]
+fn lua_get(
+	_req: LuaReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	monitor_setup: &MonitorSetup,
+	display_setup: &DisplaySetup
+) -> Result<LuaResp, Error> {
+	tokens.check_header(header)?;
+
+	let displays = display_setup.get()
+		.ok_or_else(|| Error::Request("no displays configured".into()))?;
+
+	let lua = generate(&monitor_setup.settings(), &displays)
+		.map_err(Error::Request)?;
+
+	Ok(LuaResp {
+		file_name: LUA_FILE.into(),
+		lua
+	})
+}
+
+/// Writes the lua file to the dcs saved games folder
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstallReq;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct InstallResp {
+	paths: Vec<String>
+}
+
+impl Request for InstallReq {
+	type Response = InstallResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/monitor-setup/install";
+	const METHOD: Method = Method::POST;
+}
+
+#[api(InstallReq)]
+fn install(
+	_req: InstallReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	monitor_setup: &MonitorSetup,
+	display_setup: &DisplaySetup
+) -> Result<InstallResp, Error> {
+	tokens.check_admin(header)?;
+
+	let displays = display_setup.get()
+		.ok_or_else(|| Error::Request("no displays configured".into()))?;
+	monitor_setup.settings().validate(Some(&displays))
+		.map_err(Error::Request)?;
+
+	let paths = monitor_setup.install(&displays)
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	Ok(InstallResp {
+		paths: paths.into_iter()
+			.map(|p| p.display().to_string())
+			.collect()
+	})
+}
+
+/// Imports an existing MonitorSetup file, replacing the settings and the
+/// displays
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportReq {
+	lua: String,
+	/// needed if the file uses `screen`
+	#[serde(default)]
+	screen: Option<ScreenSize>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ImportResp {
+	settings: MonitorSettings,
+	displays: Displays
+}
+
+impl Request for ImportReq {
+	type Response = ImportResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/monitor-setup/import";
+	const METHOD: Method = Method::POST;
+	/// MonitorSetup files are often bigger than the default of 4KB
+	const SIZE_LIMIT: usize = 256 * 1024;
+}
+
+#[api(ImportReq)]
+fn import_lua(
+	req: ImportReq,
+	header: &RequestHeader,
+	tokens: &Tokens,
+	monitor_setup: &MonitorSetup,
+	display_setup: &DisplaySetup
+) -> Result<ImportResp, Error> {
+	tokens.check_admin(header)?;
+
+	let (settings, displays) = import(&req.lua, req.screen)
+		.map_err(Error::Request)?;
+
+	// write the lua files first, so if it fails nothing changed
+	update_installed(&settings, &displays)
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	settings.save()
+		.map_err(|e| Error::Internal(e.to_string()))?;
+	displays.save()
+		.map_err(|e| Error::Internal(e.to_string()))?;
+
+	*monitor_setup.inner.lock().unwrap() = settings.clone();
+	if let Some(mode) = display_setup.mode() {
+		mode.warn_outside(&displays);
+	}
+	display_setup.set(Some(displays.clone()));
+
+	Ok(ImportResp { settings, displays })
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(settings_get);
+	fire.add_route(settings_set);
+	fire.add_route(lua_get);
+	fire.add_route(install);
+	fire.add_route(import_lua);
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	/// like 1 Camera + 2 MFCD which ships with dcs
+	const TWO_MFCDS: &str = r#"
+_  = function(p) return p; end;
+name = _('1 Camera + 2 MFCD');
+Description = 'Two MFCDs below the main view'
+Viewports =
+{
+     Center =
+     {
+          x = 0;
+          y = 0;
+          width = screen.width;
+          height = screen.height - 400;
+          viewDx = 0;
+          viewDy = 0;
+          aspect = screen.width / (screen.height - 400);
+     }
+}
+
+local mfcd_size = 400
+LEFT_MFCD =
+{
+     x = 0;
+     y = screen.height - mfcd_size;
+     width = mfcd_size;
+     height = mfcd_size;
+}
+
+RIGHT_MFCD =
+{
+     x = screen.width - mfcd_size;
+     y = screen.height - mfcd_size;
+     width = mfcd_size;
+     height = mfcd_size;
+}
+
+UIMainView = Viewports.Center
+GU_MAIN_VIEWPORT = Viewports.Center
+"#;
+
+	fn display(x: u32, y: u32, width: u32, height: u32) -> Display {
+		Display { x, y, width, height, format: Format::default() }
+	}
+
+	fn rect(d: &Display) -> (u32, u32, u32, u32) {
+		(d.x, d.y, d.width, d.height)
+	}
+
+	fn main_rect(s: &MonitorSettings) -> (u32, u32, u32, u32) {
+		(s.main.x, s.main.y, s.main.width, s.main.height)
+	}
+
+	#[test]
+	fn generate_and_import() {
+		let settings = MonitorSettings {
+			main: Viewport { x: 0, y: 0, width: 1920, height: 1080 },
+			display_x: 100,
+			display_y: 1080
+		};
+		let mut map = HashMap::new();
+		map.insert("LEFT_MFCD".to_string(), display(0, 40, 600, 600));
+		map.insert("RIGHT_MFCD".to_string(), display(1220, 40, 600, 600));
+		map.insert("CDU".to_string(), display(650, 0, 500, 700));
+		let displays = Displays::new(map);
+
+		let lua = generate(&settings, &displays).unwrap();
+		let (imported, imported_displays) = import(&lua, None).unwrap();
+
+		assert_eq!(main_rect(&imported), (0, 0, 1920, 1080));
+		assert_eq!(imported.display_x, 100);
+		assert_eq!(imported.display_y, 1080);
+
+		assert_eq!(imported_displays.iter().count(), 3);
+		for (name, display) in displays.iter() {
+			let imported = imported_displays.get(name).unwrap();
+			assert_eq!(rect(imported), rect(display), "{name}");
+			assert_eq!(imported.format, display.format);
+		}
+
+		// importing the generated file again gives the same file
+		assert_eq!(generate(&imported, &imported_displays).unwrap(), lua);
+	}
+
+	#[test]
+	fn import_two_mfcds() {
+		let screen = ScreenSize { width: 2560, height: 1840 };
+		let (settings, displays) = import(TWO_MFCDS, Some(screen)).unwrap();
+
+		assert_eq!(main_rect(&settings), (0, 0, 2560, 1440));
+		assert_eq!(settings.display_x, 0);
+		assert_eq!(settings.display_y, 1440);
+
+		// the aliases of the main viewport are not displays
+		assert_eq!(displays.iter().count(), 2);
+		let left = displays.get("LEFT_MFCD").unwrap();
+		assert_eq!(rect(left), (0, 0, 400, 400));
+		let right = displays.get("RIGHT_MFCD").unwrap();
+		assert_eq!(rect(right), (2160, 0, 400, 400));
+		assert_eq!(right.format, Format::default());
+	}
+
+	#[test]
+	fn import_needs_the_screen() {
+		assert!(import(TWO_MFCDS, None).is_err());
+	}
+}
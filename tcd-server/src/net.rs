@@ -0,0 +1,10 @@
+use std::net::{IpAddr, UdpSocket};
+
+/// returns the ip address of the interface which would be used to reach
+/// other devices
+pub fn local_ip() -> Option<IpAddr> {
+	// connecting an udp socket does not send any packets
+	let socket = UdpSocket::bind("0.0.0.0:0").ok()?;
+	socket.connect("8.8.8.8:80").ok()?;
+	socket.local_addr().ok().map(|addr| addr.ip())
+}
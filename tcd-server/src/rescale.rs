@@ -0,0 +1,381 @@
+use crate::displays::{
+	Displays, Display, DisplayFrames, DisplayFrame, Frame, FrameFormat, Format
+};
+use crate::virtual_display::Subscription;
+
+use std::io::Cursor;
+use std::sync::{Arc, Mutex};
+use std::collections::HashMap;
+
+use tokio::task;
+use tokio::time::{Duration, Instant};
+
+use image::{
+	ImageFormat, ImageOutputFormat, ImageResult, ColorType, RgbaImage,
+	DynamicImage
+};
+use image::imageops::{self, FilterType};
+use image::codecs::webp::WebPEncoder;
+
+use serde::{Serialize, Deserialize};
+
+/// sizes which were not requested in this time get removed from the cache
+const CACHE_TIMEOUT: Duration = Duration::from_secs(30);
+/// used if the display is not encoded as jpeg anymore
+const DEFAULT_JPEG_QUALITY: u8 = 80;
+
+/// The size a client wan't's to receive the frames of a display in
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct Size {
+	pub width: u32,
+	pub height: u32
+}
+
+/// What a frame should be converted to
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Target {
+	pub size: Option<Size>,
+	/// only used for jpeg
+	pub quality: Option<u8>,
+	/// if None the format of the keyframe is kept
+	pub format: Option<FrameFormat>
+}
+
+type Entry = Arc<tokio::sync::Mutex<Option<Frame>>>;
+/// the instant is when the target was last requested
+type Cache = HashMap<(String, Target), (Instant, Entry)>;
+
+/// the reencoded keyframe and tiles of a display by their id
+type PartsEntry = Arc<tokio::sync::Mutex<HashMap<u64, Frame>>>;
+/// by the name of the display and the jpeg quality
+type PartsCache = HashMap<(String, u8), (Instant, PartsEntry)>;
+
+/// Rescales or reencodes frames and caches the result so every target only
+/// needs to be calculated once per frame, no matter how many clients
+/// requested it.
+#[derive(Debug, Clone)]
+pub struct Rescaler {
+	inner: Arc<Mutex<Cache>>,
+	parts: Arc<Mutex<PartsCache>>
+}
+
+impl Rescaler {
+	pub fn new() -> Self {
+		Self {
+			inner: Arc::new(Mutex::new(HashMap::new())),
+			parts: Arc::new(Mutex::new(HashMap::new()))
+		}
+	}
+
+	fn parts_entry(&self, name: &str, quality: u8) -> PartsEntry {
+		let mut parts = self.parts.lock().unwrap();
+		parts.retain(|_, (used, _)| used.elapsed() < CACHE_TIMEOUT);
+
+		let (used, entry) = parts.entry((name.to_string(), quality))
+			.or_insert_with(|| (Instant::now(), PartsEntry::default()));
+		*used = Instant::now();
+
+		entry.clone()
+	}
+
+	fn entry(&self, name: &str, target: Target) -> Entry {
+		let mut inner = self.inner.lock().unwrap();
+		inner.retain(|_, (used, _)| used.elapsed() < CACHE_TIMEOUT);
+
+		let (used, entry) = inner.entry((name.to_string(), target))
+			.or_insert_with(|| (Instant::now(), Entry::default()));
+		*used = Instant::now();
+
+		entry.clone()
+	}
+
+	/// Returns the frame in the requested target as a keyframe, frames are
+	/// never upscaled.
+	///
+	/// The frame needs to contain a keyframe.
+	pub async fn rescale(
+		&self,
+		name: &str,
+		frame: DisplayFrame,
+		display: &Display,
+		target: Target
+	) -> DisplayFrame {
+		let fits = target.size
+			.map(|s| s.width >= display.width && s.height >= display.height)
+			.unwrap_or(true);
+		let same_format = match (target.format, &frame.keyframe) {
+			(Some(format), Some(keyframe)) => format == keyframe.format,
+			_ => true
+		};
+		if fits && same_format && target.quality.is_none() &&
+			frame.tiles.is_empty()
+		{
+			return frame
+		}
+
+		let id = match frame.latest_id() {
+			Some(id) => id,
+			None => return frame
+		};
+
+		let entry = self.entry(name, target);
+		// if another client is rescaling the same frame we wait for it
+		let mut cached = entry.lock().await;
+		if let Some(cached) = cached.as_ref().filter(|c| c.id == id) {
+			return DisplayFrame::new(cached.clone())
+		}
+
+		let quality = target.quality.unwrap_or(match display.format {
+			Format::Jpeg { quality } => quality,
+			_ => DEFAULT_JPEG_QUALITY
+		});
+
+		let n_frame = frame.clone();
+		let r = task::spawn_blocking(move || resize(&n_frame, target, quality))
+			.await
+			.expect("resize panicked");
+
+		match r {
+			Ok(resized) => {
+				*cached = Some(resized.clone());
+				DisplayFrame::new(resized)
+			},
+			Err(e) => {
+				eprintln!("could not rescale frame of {name} {e}");
+				frame
+			}
+		}
+	}
+
+	/// Reencodes the keyframe and every tile with the jpeg quality, so a
+	/// client only receives what changed. The ids are kept.
+	///
+	/// Parts which are not jpeg are not changed.
+	pub async fn reencode(
+		&self,
+		name: &str,
+		mut frame: DisplayFrame,
+		quality: u8
+	) -> DisplayFrame {
+		let entry = self.parts_entry(name, quality);
+		let mut cached = entry.lock().await;
+
+		// older parts are not needed anymore, if a client still needs them
+		// they get reencoded again
+		let oldest = frame.keyframe.iter()
+			.chain(frame.tiles.iter().map(|t| &t.frame))
+			.map(|f| f.id)
+			.min();
+		cached.retain(|id, _| Some(*id) >= oldest);
+
+		let parts = frame.keyframe.iter_mut()
+			.chain(frame.tiles.iter_mut().map(|t| &mut t.frame))
+			.filter(|f| f.format == FrameFormat::Jpeg);
+		for part in parts {
+			if let Some(reencoded) = cached.get(&part.id) {
+				*part = reencoded.clone();
+				continue
+			}
+
+			let n_part = part.clone();
+			let r = task::spawn_blocking(move || {
+				reencode_jpeg(&n_part, quality)
+			}).await.expect("reencode panicked");
+
+			match r {
+				Ok(reencoded) => {
+					cached.insert(part.id, reencoded.clone());
+					*part = reencoded;
+				},
+				Err(e) => {
+					eprintln!("could not reencode frame of {name} {e}");
+				}
+			}
+		}
+
+		frame
+	}
+
+	/// rescales every frame which has a requested size or quality
+	pub async fn rescale_frames(
+		&self,
+		mut frames: DisplayFrames,
+		subscribed: &HashMap<String, Subscription>,
+		displays: Option<Displays>
+	) -> DisplayFrames {
+		let displays = match displays {
+			Some(d) => d,
+			None => return frames
+		};
+
+		for (name, sub) in subscribed {
+			let target = Target {
+				size: sub.size,
+				quality: sub.quality,
+				format: None
+			};
+			if target.size.is_none() && target.quality.is_none() {
+				continue
+			}
+			let display = match displays.get(name) {
+				Some(d) => d,
+				None => continue
+			};
+			let frame = match frames.remove(name) {
+				Some(f) => f,
+				None => continue
+			};
+
+			// if only the quality changes the tiles can be kept
+			let frame = match target {
+				Target { size: None, quality: Some(quality), .. } => {
+					self.reencode(name, frame, quality).await
+				},
+				_ => self.rescale(name, frame, display, target).await
+			};
+			frames.insert(name.clone(), frame);
+		}
+
+		frames
+	}
+}
+
+fn decode(frame: &Frame) -> ImageResult<DynamicImage> {
+	let format = match frame.format {
+		FrameFormat::Jpeg => ImageFormat::Jpeg,
+		FrameFormat::Png => ImageFormat::Png,
+		FrameFormat::Webp => ImageFormat::WebP
+	};
+
+	image::load_from_memory_with_format(&frame.data, format)
+}
+
+/// Draws the tiles onto the keyframe and resizes the result, the aspect
+/// ratio is kept, so the result fits inside of the size.
+///
+/// The frame needs to contain a keyframe.
+pub fn render(
+	frame: &DisplayFrame,
+	size: Option<Size>
+) -> ImageResult<DynamicImage> {
+	let keyframe = frame.keyframe.as_ref().unwrap();
+
+	let mut image: RgbaImage = decode(keyframe)?.into_rgba8();
+	for tile in &frame.tiles {
+		let tile_image = decode(&tile.frame)?.into_rgba8();
+		imageops::replace(
+			&mut image,
+			&tile_image,
+			tile.x as i64,
+			tile.y as i64
+		);
+	}
+
+	let image = DynamicImage::ImageRgba8(image);
+	Ok(match size {
+		Some(size) if image.width() > size.width ||
+			image.height() > size.height =>
+		{
+			image.resize(size.width, size.height, FilterType::Triangle)
+		},
+		_ => image
+	})
+}
+
+fn reencode_jpeg(frame: &Frame, quality: u8) -> ImageResult<Frame> {
+	let image = decode(frame)?.into_rgb8();
+
+	let mut data = Cursor::new(vec![]);
+	DynamicImage::ImageRgb8(image)
+		.write_to(&mut data, ImageOutputFormat::Jpeg(quality))?;
+
+	Ok(Frame {
+		id: frame.id,
+		format: frame.format,
+		data: data.into_inner(),
+		received_at: frame.received_at
+	})
+}
+
+fn resize(
+	frame: &DisplayFrame,
+	target: Target,
+	quality: u8
+) -> ImageResult<Frame> {
+	// the caller checked that there is a keyframe
+	let keyframe = frame.keyframe.as_ref().unwrap();
+	let image = render(frame, target.size)?;
+
+	let format = target.format.unwrap_or(keyframe.format);
+	let mut data = Cursor::new(vec![]);
+	match format {
+		FrameFormat::Jpeg => {
+			// jpeg does not support an alpha channel
+			DynamicImage::ImageRgb8(image.to_rgb8())
+				.write_to(&mut data, ImageOutputFormat::Jpeg(quality))?
+		},
+		FrameFormat::Png => image.write_to(&mut data, ImageOutputFormat::Png)?,
+		FrameFormat::Webp => {
+			let image = image.to_rgba8();
+			WebPEncoder::new_lossless(&mut data).encode(
+				&image,
+				image.width(),
+				image.height(),
+				ColorType::Rgba8
+			)?
+		}
+	}
+
+	// the caller checked that there is a keyframe
+	let latest = frame.latest().unwrap();
+	Ok(Frame {
+		// the id of the newest part, so the cache knows what it contains
+		id: latest.id,
+		format,
+		data: data.into_inner(),
+		received_at: latest.received_at
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use crate::displays::Tile;
+
+	fn jpeg(width: u32, height: u32) -> Frame {
+		let image = RgbaImage::from_fn(width, height, |x, y| {
+			image::Rgba([(x * 7) as u8, (y * 13) as u8, (x ^ y) as u8, 255])
+		});
+		let mut data = Cursor::new(vec![]);
+		DynamicImage::ImageRgba8(image).to_rgb8()
+			.write_to(&mut data, ImageOutputFormat::Jpeg(100))
+			.unwrap();
+
+		Frame::new(FrameFormat::Jpeg, data.into_inner())
+	}
+
+	#[tokio::test]
+	async fn reencode_keeps_the_tiles() {
+		let rescaler = Rescaler::new();
+		let keyframe = jpeg(64, 64);
+		let mut frame = DisplayFrame::new(keyframe.clone());
+		frame.apply_tiles(vec![Tile { x: 16, y: 16, frame: jpeg(16, 16) }]);
+		let tile = frame.tiles[0].frame.clone();
+
+		let full = rescaler.reencode("LEFT", frame.clone(), 20).await;
+		let reencoded = full.keyframe.as_ref().unwrap();
+		assert_eq!(reencoded.id, keyframe.id);
+		assert!(reencoded.data.len() < keyframe.data.len());
+		assert_eq!(full.tiles.len(), 1);
+		assert_eq!(full.tiles[0].frame.id, tile.id);
+		assert!(full.tiles[0].frame.data.len() < tile.data.len());
+
+		// a client which already has the keyframe only get's the tile
+		let since = frame.since(Some(keyframe.id)).unwrap();
+		let since = rescaler.reencode("LEFT", since, 20).await;
+		assert!(since.keyframe.is_none());
+		assert_eq!(since.tiles.len(), 1);
+		assert_eq!(since.tiles[0].frame.data, full.tiles[0].frame.data);
+	}
+}
@@ -0,0 +1,144 @@
+use crate::api_error::Error;
+use crate::auth::Tokens;
+use crate::displays::{DisplaySetup, FrameFormat};
+use crate::mfds::display_from_path;
+use crate::rescale::Target;
+use crate::VirtualDisplay;
+
+use std::sync::OnceLock;
+use std::time::{SystemTime, UNIX_EPOCH, Duration};
+
+use fire::{FireBuilder, Request, Response, Data};
+use fire::header::{
+	RequestHeader, Method, StatusCode, ETAG, LAST_MODIFIED, IF_NONE_MATCH,
+	IF_MODIFIED_SINCE, CACHE_CONTROL
+};
+use fire::routes::Route;
+use fire::util::PinnedFuture;
+use fire::into::IntoResponse;
+
+/// Serves the latest frame of a display at `/api/mfds/{name}.jpg` or
+/// `/api/mfds/{name}.png`.
+///
+/// Like the mjpeg stream the token can also be passed as `?token=`.
+struct SnapshotRoute;
+
+const EXTENSIONS: &[(&str, FrameFormat)] = &[
+	("jpg", FrameFormat::Jpeg),
+	("png", FrameFormat::Png)
+];
+
+/// returns the name of the display, the extension and the format
+fn requested(
+	header: &RequestHeader
+) -> Option<(&str, &'static str, FrameFormat)> {
+	EXTENSIONS.iter().find_map(|(ext, format)| {
+		display_from_path(header, ext).map(|name| (name, *ext, *format))
+	})
+}
+
+impl Route for SnapshotRoute {
+	fn check(&self, header: &RequestHeader) -> bool {
+		header.method() == Method::GET && requested(header).is_some()
+	}
+
+	fn validate_data(&self, data: &Data) {
+		assert!(data.exists::<VirtualDisplay>());
+		assert!(data.exists::<DisplaySetup>());
+		assert!(data.exists::<Tokens>());
+	}
+
+	fn call<'a>(
+		&'a self,
+		req: &'a mut Request,
+		data: &'a Data
+	) -> PinnedFuture<'a, fire::Result<Response>> {
+		PinnedFuture::new(async move {
+			Ok(match snapshot(req.header(), data).await {
+				Ok(resp) => resp,
+				Err(e) => e.into_response()
+			})
+		})
+	}
+}
+
+/// http dates only have a precision of seconds
+fn truncate_to_secs(time: SystemTime) -> SystemTime {
+	let secs = time.duration_since(UNIX_EPOCH)
+		.map(|d| d.as_secs())
+		.unwrap_or(0);
+	UNIX_EPOCH + Duration::from_secs(secs)
+}
+
+/// frame ids start at 0 every time the server starts, so the etag also
+/// contains a random id of this process
+fn boot_id() -> u64 {
+	static BOOT_ID: OnceLock<u64> = OnceLock::new();
+	*BOOT_ID.get_or_init(rand::random)
+}
+
+async fn snapshot(
+	header: &RequestHeader,
+	data: &Data
+) -> Result<Response, Error> {
+	// the route checked that the data exists
+	let virtual_display = data.get::<VirtualDisplay>().unwrap();
+	let display_setup = data.get::<DisplaySetup>().unwrap();
+	let tokens = data.get::<Tokens>().unwrap();
+
+	tokens.check_header_or_query(header)?;
+
+	let (name, ext, format) = requested(header).unwrap();
+	let display = display_setup.get()
+		.and_then(|displays| displays.get(name).cloned())
+		.ok_or_else(|| {
+			Error::NotFound(format!("display {name} does not exist"))
+		})?;
+
+	// the driver might not be connected yet
+	let frame = virtual_display.frame(name)
+		.ok_or_else(|| Error::Unavailable("no frame received yet".into()))?;
+	// frames from the driver always start with a keyframe
+	let latest = frame.latest().unwrap();
+	let etag = format!("\"{:x}-{}-{ext}\"", boot_id(), latest.id);
+	let modified = truncate_to_secs(latest.received_at);
+
+	let not_modified = match header.value(IF_NONE_MATCH) {
+		Some(none_match) => none_match.split(',')
+			.any(|tag| tag.trim() == etag),
+		None => header.value(IF_MODIFIED_SINCE)
+			.and_then(|since| httpdate::parse_http_date(since).ok())
+			.map(|since| modified <= since)
+			.unwrap_or(false)
+	};
+
+	let builder = Response::builder()
+		.header(ETAG, etag.clone())
+		.header(LAST_MODIFIED, httpdate::fmt_http_date(modified))
+		// the frame changes all the time
+		.header(CACHE_CONTROL, "no-cache");
+
+	if not_modified {
+		return Ok(builder.status_code(StatusCode::NOT_MODIFIED).build())
+	}
+
+	let target = Target {
+		size: None,
+		quality: None,
+		format: Some(format)
+	};
+	let frame = virtual_display.rescaler()
+		.rescale(name, frame, &display, target).await;
+	let keyframe = frame.keyframe
+		.filter(|k| k.format == format)
+		.ok_or_else(|| Error::Internal("could not convert frame".into()))?;
+
+	Ok(builder
+		.content_type(format.mime())
+		.body(keyframe.data)
+		.build())
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(SnapshotRoute);
+}
@@ -0,0 +1,216 @@
+use crate::api_error::Error;
+use crate::config::data_dir;
+use crate::net::local_ip;
+
+use std::{fs, io};
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use tokio::net::TcpListener;
+use tokio::time;
+use tokio_rustls::TlsAcceptor;
+use tokio_rustls::rustls::{self, ServerConfig, PrivateKey};
+
+use hyper::server::conn::Http;
+
+use sha2::{Sha256, Digest};
+
+use serde::{Serialize, Deserialize};
+
+use fire::FireBuilder;
+use fire_api::{api, Request, Method};
+
+const CERT_FILE: &str = "cert.pem";
+const KEY_FILE: &str = "key.pem";
+/// a client which stalls the handshake does not get to keep the connection
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// how long to wait after accepting failed, for example if there are no
+/// file descriptors left
+const ACCEPT_RETRY: Duration = Duration::from_millis(100);
+
+/// The self signed certificate of the server
+#[derive(Debug, Clone)]
+pub struct Certificate {
+	cert_pem: String,
+	cert_der: Vec<u8>,
+	key_der: Vec<u8>,
+	fingerprint: String
+}
+
+impl Certificate {
+	/// Loads the certificate from the data directory, generates a new one if
+	/// it does not exist yet.
+	pub fn load_or_generate() -> io::Result<Self> {
+		let dir = data_dir();
+		let cert_path = dir.join(CERT_FILE);
+		let key_path = dir.join(KEY_FILE);
+
+		if !cert_path.is_file() || !key_path.is_file() {
+			let (cert, key) = generate()?;
+			fs::create_dir_all(&dir)?;
+			fs::write(&cert_path, cert)?;
+			fs::write(&key_path, key)?;
+			eprintln!("generated a new certificate {:?}", cert_path);
+		}
+
+		let cert_pem = fs::read_to_string(cert_path)?;
+		let key_pem = fs::read_to_string(key_path)?;
+
+		let cert_der = rustls_pemfile::certs(&mut cert_pem.as_bytes())?
+			.into_iter()
+			.next()
+			.ok_or_else(|| io::Error::other("no certificate found"))?;
+		let mut key_reader = key_pem.as_bytes();
+		let key_der = rustls_pemfile::pkcs8_private_keys(&mut key_reader)?
+			.into_iter()
+			.next()
+			.ok_or_else(|| io::Error::other("no private key found"))?;
+
+		let fingerprint = fingerprint(&cert_der);
+
+		Ok(Self { cert_pem, cert_der, key_der, fingerprint })
+	}
+
+	/// the sha256 fingerprint formatted like browsers show it
+	pub fn fingerprint(&self) -> &str {
+		&self.fingerprint
+	}
+
+	fn server_config(&self) -> io::Result<ServerConfig> {
+		let mut config = ServerConfig::builder()
+			.with_safe_defaults()
+			.with_no_client_auth()
+			.with_single_cert(
+				vec![rustls::Certificate(self.cert_der.clone())],
+				PrivateKey(self.key_der.clone())
+			)
+			.map_err(io::Error::other)?;
+		// fire needs the host header and websockets only work over http1
+		config.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+		Ok(config)
+	}
+}
+
+/// returns the certificate and the private key pem encoded
+fn generate() -> io::Result<(String, String)> {
+	let mut names = vec!["localhost".to_string(), "127.0.0.1".to_string()];
+	if let Some(ip) = local_ip() {
+		names.push(ip.to_string());
+	}
+
+	let cert = rcgen::generate_simple_self_signed(names)
+		.map_err(io::Error::other)?;
+	let cert_pem = cert.serialize_pem()
+		.map_err(io::Error::other)?;
+
+	Ok((cert_pem, cert.serialize_private_key_pem()))
+}
+
+fn fingerprint(der: &[u8]) -> String {
+	let hash = Sha256::digest(der);
+
+	let mut s = String::with_capacity(hash.len() * 3);
+	for (i, b) in hash.iter().enumerate() {
+		if i > 0 {
+			s.push(':');
+		}
+		write!(s, "{b:02X}").unwrap();
+	}
+
+	s
+}
+
+/// Serves the fire over tls, this runs forever.
+pub async fn serve(
+	fire: FireBuilder,
+	addr: SocketAddr,
+	cert: &Certificate
+) -> io::Result<()> {
+	let acceptor = TlsAcceptor::from(Arc::new(cert.server_config()?));
+	let listener = TcpListener::bind(addr).await?;
+	let make_service = fire.into_make_fire_service();
+
+	eprintln!("Running server with tls on addr: {addr}");
+
+	loop {
+		let (stream, remote_addr) = match listener.accept().await {
+			Ok(s) => s,
+			Err(e) => {
+				eprintln!("failed to accept tls connection {e}");
+				time::sleep(ACCEPT_RETRY).await;
+				continue
+			}
+		};
+		let acceptor = acceptor.clone();
+		let service = make_service.make(remote_addr);
+
+		tokio::spawn(async move {
+			// a client which does not trust the certificate aborts here
+			let accept = acceptor.accept(stream);
+			let stream = match time::timeout(HANDSHAKE_TIMEOUT, accept).await {
+				Ok(Ok(s)) => s,
+				Ok(Err(e)) => {
+					eprintln!("tls handshake with {remote_addr} failed {e}");
+					return
+				},
+				Err(_) => {
+					eprintln!("tls handshake with {remote_addr} timed out");
+					return
+				}
+			};
+
+			let r = Http::new()
+				.serve_connection(stream, service)
+				.with_upgrades()
+				.await;
+			if let Err(e) = r {
+				eprintln!("connection error {e}");
+			}
+		});
+	}
+}
+
+/// Information about tls, added as data so the api can expose it
+#[derive(Debug, Clone)]
+pub struct TlsInfo {
+	pub certificate: Option<Certificate>
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TlsReq;
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct TlsResp {
+	enabled: bool,
+	fingerprint: Option<String>,
+	/// the certificate pem encoded so it can be installed on a device
+	certificate: Option<String>
+}
+
+impl Request for TlsReq {
+	type Response = TlsResp;
+	type Error = Error;
+
+	const PATH: &'static str = "/api/tls";
+	const METHOD: Method = Method::GET;
+}
+
+/// does not need a token since the fingerprint needs to be checked before
+/// pairing
+#[api(TlsReq)]
+fn tls_info(_req: TlsReq, tls: &TlsInfo) -> Result<TlsResp, Error> {
+	let cert = tls.certificate.as_ref();
+
+	Ok(TlsResp {
+		enabled: cert.is_some(),
+		fingerprint: cert.map(|c| c.fingerprint.clone()),
+		certificate: cert.map(|c| c.cert_pem.clone())
+	})
+}
+
+pub(crate) fn handle(fire: &mut FireBuilder) {
+	fire.add_route(tls_info);
+}
@@ -1,18 +1,26 @@
 
 use crate::displays::{
-	DisplaySetup, DisplaySetupWatcher, DisplayFrames, DisplayKind
+	DisplaySetup, DisplayFrames, DisplayFrame, Displays, Format, FrameFormat,
+	Frame, Tile, ScreenMode
 };
+use crate::rescale::{Rescaler, Size};
+use crate::debrief::Replaying;
 
-use std::{io, mem};
+use std::io;
 use std::sync::Arc;
-use std::collections::HashSet;
+use std::collections::HashMap;
 
 use tokio::net::{TcpListener, TcpStream};
 use tokio::io::{BufReader, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::watch;
 use tokio::task::JoinHandle;
 
-const ADDR: &str = "127.0.0.1:5476";
+use serde::{Serialize, Deserialize};
+
+use virtual_display_core::protocol::{
+	self, DisplayHeader, Mode, ADDR, HEADER_LEN, MODE_LEN, MODE_MARKER,
+	KIND_KEYFRAME, KIND_DELTA
+};
 
 macro_rules! io_other {
 	($err:expr) => {
@@ -24,33 +32,78 @@ macro_rules! io_other {
 
 #[derive(Debug, Clone)]
 pub struct VirtualDisplay {
-	inner: watch::Receiver<DisplayFrames>
+	inner: watch::Receiver<DisplayFrames>,
+	/// used to replay recordings
+	tx: Arc<watch::Sender<DisplayFrames>>,
+	/// shared between all clients
+	rescaler: Rescaler
 }
 
 impl VirtualDisplay {
-	pub fn new(display_setup: DisplaySetup) -> (Self, JoinHandle<()>) {
+	/// while replaying the frames from the driver are ignored
+	pub fn new(
+		display_setup: DisplaySetup,
+		replaying: Replaying
+	) -> (Self, JoinHandle<()>) {
 		let (tx, rx) = watch::channel(DisplayFrames::new());
+		let tx = Arc::new(tx);
 		let this = Self {
-			inner: rx
+			inner: rx,
+			tx: tx.clone(),
+			rescaler: Rescaler::new()
 		};
 
 		let handle = tokio::spawn(async move {
-			listener_task(tx, display_setup).await
+			listener_task(tx, display_setup, replaying).await
 		});
 
 		(this, handle)
 	}
 
-	pub async fn recv(
-		&mut self,
-		subscribed: &HashSet<DisplayKind>
-	) -> DisplayFrames {
+	/// replaces the frames of all displays, used to replay recordings
+	pub fn modify_frames(&self, f: impl FnOnce(&mut DisplayFrames)) {
+		self.tx.send_modify(f);
+	}
+
+	/// completes when new frames are available
+	pub async fn changed(&mut self) {
 		self.inner.changed().await.expect("virtual display task failed");
+	}
+
+	pub fn rescaler(&self) -> &Rescaler {
+		&self.rescaler
+	}
+
+	/// returns the keyframe of the display with all tiles received since
+	pub fn frame(&self, name: &str) -> Option<DisplayFrame> {
+		self.inner.borrow().get(name).cloned()
+	}
+
+	/// Returns what changed in the subscribed displays since the client
+	/// received the last frame.
+	pub fn frames(
+		&self,
+		subscribed: &HashMap<String, Subscription>
+	) -> DisplayFrames {
 		let data = self.inner.borrow();
 		let mut n_data = DisplayFrames::new();
-		for kind in subscribed {
-			if let Some(image) = data.get(kind) {
-				n_data.insert(*kind, image.clone());
+		for (name, sub) in subscribed {
+			let frame = match data.get(name) {
+				Some(f) => f,
+				None => continue
+			};
+
+			// rescaling needs the full frame, a different quality only
+			// needs what changed
+			let frame = if sub.size.is_some() {
+				Some(frame.clone())
+					.filter(|f| f.latest_id() != sub.received)
+			} else {
+				frame.since(sub.received)
+			};
+
+			if let Some(frame) = frame {
+				n_data.insert(name.clone(), frame);
 			}
 		}
 
@@ -58,31 +111,116 @@ impl VirtualDisplay {
 	}
 }
 
+/// The state of a display a client subscribed to
+#[derive(Debug, Clone, Default)]
+pub struct Subscription {
+	/// the id of the latest keyframe or tile the client received
+	pub received: Option<u64>,
+	/// the server rescales the frames to fit inside of this size
+	pub size: Option<Size>,
+	/// the server reencodes the frames with this jpeg quality
+	pub quality: Option<u8>
+}
+
+/// A display like the driver receives it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct DriverDisplay {
+	/// the driver uses the id in the header of every frame
+	id: u8,
+	name: String,
+	x: u32,
+	y: u32,
+	width: u32,
+	height: u32,
+	format: Format
+}
+
+/// Assigns every display name an id for the driver protocol.
+///
+/// Ids are never reused during a connection, so frames which the driver
+/// encoded before it received a new layout still belong to the right
+/// display.
+#[derive(Debug)]
+struct DisplayIds {
+	// the index is the id
+	names: Vec<String>
+}
+
+impl DisplayIds {
+	fn new() -> Self {
+		Self { names: vec![] }
+	}
+
+	fn id(&mut self, name: &str) -> io::Result<u8> {
+		let pos = self.names.iter().position(|n| n == name);
+		let id = match pos {
+			Some(id) => id,
+			None => {
+				self.names.push(name.to_string());
+				self.names.len() - 1
+			}
+		};
+
+		// the connection needs to be restarted to get new ids
+		u8::try_from(id).ok()
+			.filter(|id| *id < MODE_MARKER)
+			.ok_or_else(|| io_other!("too many displays for one connection"))
+	}
+
+	fn name(&self, id: u8) -> Option<&str> {
+		self.names.get(id as usize).map(|n| n.as_str())
+	}
+
+	fn driver_displays(
+		&mut self,
+		displays: &Displays
+	) -> io::Result<Vec<DriverDisplay>> {
+		displays.iter()
+			.map(|(name, display)| Ok(DriverDisplay {
+				id: self.id(name)?,
+				name: name.clone(),
+				x: display.x,
+				y: display.y,
+				width: display.width,
+				height: display.height,
+				format: display.format
+			}))
+			.collect()
+	}
+}
+
 async fn listener_task(
-	tx: watch::Sender<DisplayFrames>,
-	display_setup: DisplaySetup
+	tx: Arc<watch::Sender<DisplayFrames>>,
+	display_setup: DisplaySetup,
+	replaying: Replaying
 ) {
 	let listener = TcpListener::bind(ADDR).await
 		.expect("failed to bind listener");
 
 	eprintln!("virtual display driver listening on {}", ADDR);
 
-	let tx = Arc::new(tx);
-
 	loop {
 
 		let stream = listener.accept().await;
 		match stream {
 			Ok((stream, addr)) => {
 				let tx = tx.clone();
-				let display_setup = display_setup.subscribe();
+				let display_setup = display_setup.clone();
+				let replaying = replaying.clone();
 				tokio::spawn(async move {
 					eprintln!("virtual display connected from {}", addr);
 
-					let r = handle_stream(stream, tx, display_setup).await;
+					let r = handle_stream(
+						stream,
+						tx,
+						&display_setup,
+						replaying
+					).await;
 					if let Err(e) = r {
 						eprintln!("stream error {:?}", e);
 					}
+					// the next driver reports it's own mode
+					display_setup.set_mode(None);
 				});
 			},
 			Err(e) => {
@@ -96,65 +234,122 @@ async fn listener_task(
 async fn handle_stream(
 	stream: TcpStream,
 	tx: Arc<watch::Sender<DisplayFrames>>,
-	mut display_setup: DisplaySetupWatcher
+	setup: &DisplaySetup,
+	replaying: Replaying
 ) -> io::Result<()> {
 	let mut reader = BufReader::new(stream);
+	let mut display_setup = setup.subscribe();
 
 	let mut sent_displays = false;
-	let mut buffers = vec![];
+	let mut ids = DisplayIds::new();
 	let mut read_buf = vec![];
-	let mut prev_data = tx.borrow().clone();
+
+	// the driver starts with keyframes
+	if !replaying.is_active() {
+		tx.send_modify(|frames| frames.clear());
+	}
 
 	loop {
 		let has_changed = display_setup.has_changed() || !sent_displays;
 		if has_changed {
-			let maybe_displays = display_setup.clone();
+			let maybe_displays = display_setup.clone()
+				.map(|displays| ids.driver_displays(&displays))
+				.transpose()?;
 			let v = serde_json::to_vec(&maybe_displays).unwrap();
 			reader.write_u32(v.len() as u32).await?;
 			reader.write_all(&v).await?;
 
 			sent_displays = true;
+			// the driver sends keyframes after it received new displays
+			if !replaying.is_active() {
+				tx.send_modify(|frames| frames.clear());
+			}
 		}
 
-		// ┌────────────┐
-		// │Displays Len│
-		// ├────────────┤
-		// │     8      │
-		// └────────────┘
-		//
-		// ┌────┬───┬──────┐
-		// │Kind│Len│ Data │
-		// ├────┼───┼──────┤
-		// │ 8  │32 │$Len*8│
-		// └────┴───┴──────┘
-		// reserved 5bytes
+		// see virtual_display_core::protocol for the format
 		let displays_len = reader.read_u8().await?;
 
 		if displays_len == 0 {
 			continue
 		}
 
-		prev_data.clear_and_get_buffers(&mut buffers);
+		if displays_len == MODE_MARKER {
+			let mut bytes = [0u8; MODE_LEN];
+			reader.read_exact(&mut bytes).await?;
+			let Mode { width, height } = Mode::from_bytes(bytes);
+			let mode = ScreenMode { width, height };
+			eprintln!("virtual display mode {}x{}", mode.width, mode.height);
+			if let Some(displays) = setup.get() {
+				mode.warn_outside(&displays);
+			}
+			setup.set_mode(Some(mode));
+			continue
+		}
+
+		let mut received = Vec::with_capacity(displays_len as usize);
 
 		for _ in 0..displays_len {
-			let kind = reader.read_u8().await?;
-			let kind = DisplayKind::from_u8(kind)
-				.ok_or_else(|| io_other!(format!("invalid kind {}", kind)))?;
-			let len = reader.read_u32().await? as usize;
-
+			let mut header = [0u8; HEADER_LEN];
+			reader.read_exact(&mut header).await?;
+			let DisplayHeader { id, format, kind, len } =
+				DisplayHeader::from_bytes(header);
+
+			let name = ids.name(id)
+				.ok_or_else(|| io_other!(format!("invalid display id {}", id)))?
+				.to_string();
+			let format = FrameFormat::from_u8(format)
+				.ok_or_else(|| io_other!(format!("invalid format {}", format)))?;
+
+			let len = len as usize;
 			read_buf.resize(len, 0);
-
 			reader.read_exact(&mut read_buf[..len]).await?;
 
-			// to save on allocations
-			let image = match buffers.pop() {
-				Some(buf) => mem::replace(&mut read_buf, buf),
-				None => read_buf.clone()
+			let update = match kind {
+				KIND_KEYFRAME => {
+					Update::Keyframe(Frame::new(format, read_buf.clone()))
+				},
+				KIND_DELTA => {
+					let tiles = protocol::parse_tiles(&read_buf[..len])?
+						.into_iter()
+						.map(|tile| Tile {
+							x: tile.x as u32,
+							y: tile.y as u32,
+							frame: Frame::new(format, tile.data.to_vec())
+						})
+						.collect();
+					Update::Tiles(tiles)
+				},
+				k => return Err(io_other!(format!("invalid kind {}", k)))
 			};
-			prev_data.insert(kind, image);
+			received.push((name, update));
+		}
+
+		// the frames are still read while replaying so the driver does not
+		// stall
+		if replaying.is_active() {
+			continue
 		}
 
-		buffers.clear();
-		prev_data = tx.send_replace(prev_data);
+		tx.send_modify(|frames| {
+			for (name, update) in received {
+				match update {
+					Update::Keyframe(frame) => {
+						frames.insert(name, DisplayFrame::new(frame));
+					},
+					// tiles without a keyframe belong to an old layout
+					Update::Tiles(tiles) => {
+						if let Some(frame) = frames.get_mut(&name) {
+							frame.apply_tiles(tiles);
+						}
+					}
+				}
+			}
+		});
 	}
 }
+
+enum Update {
+	Keyframe(Frame),
+	Tiles(Vec<Tile>)
+}
+
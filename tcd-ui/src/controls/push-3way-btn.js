@@ -1,4 +1,4 @@
-import { subscribe, send, Input } from './../lib/controlsapi.js';
+import { subscribe, send, release, Input } from './../lib/controlsapi.js';
 import { timeout } from 'fire/util.js';
 
 export const DOWN = 0;
@@ -49,15 +49,17 @@ export default class Push3WayBtn {
 	}
 
 	async up() {
-		send(Input.integer(this.name, 2));
+		if (!send(Input.integer(this.name, 2)))
+			return;
 		await this.onChange(2);
-		send(Input.integer(this.name, 1));
+		release(Input.integer(this.name, 1));
 	}
 
 	async down() {
-		send(Input.integer(this.name, 0));
+		if (!send(Input.integer(this.name, 0)))
+			return;
 		await this.onChange(0);
-		send(Input.integer(this.name, 1));
+		release(Input.integer(this.name, 1));
 	}
 
 	destroy() {
@@ -1,4 +1,4 @@
-import { subscribe, send, Input } from './../lib/controlsapi.js';
+import { subscribe, send, release, Input } from './../lib/controlsapi.js';
 import { timeout } from 'fire/util.js';
 
 export default class PushBtn {
@@ -43,9 +43,11 @@ export default class PushBtn {
 	}
 
 	async click() {
-		send(Input.integer(this.name, 1));
+		// a guarded button is only armed by the first click
+		if (!send(Input.integer(this.name, 1)))
+			return;
 		await this.onChange(1);
-		send(Input.integer(this.name, 0));
+		release(Input.integer(this.name, 0));
 	}
 
 	destroy() {
@@ -1,9 +1,81 @@
+import ApiError from 'fire/api/error.js';
+import { getToken } from './auth.js';
 
 /// returns a url object with the api + added path
+///
+/// in debug the dev server forwards /api to the tcd-server
 export function getUrl(path) {
-	const host = window.location.host;
-	const url = new URL('http://' + host + '/api' + path);
-	if (IN_DEBUG)
-		url.port = 3511;
+	return new URL('/api' + path, window.location.origin);
+}
+
+/// returns a url for a websocket api, uses wss if the page uses https
+export function getWsUrl(path) {
+	const url = getUrl(path);
+	url.protocol = url.protocol === 'https:' ? 'wss:' : 'ws:';
 	return url;
-}
\ No newline at end of file
+}
+
+/// calls an http api of the server with the token of this device
+export async function request(method, path, data = null) {
+	const headers = { 'content-type': 'application/json' };
+	const token = getToken();
+	if (token)
+		headers['tcd-token'] = token;
+
+	const resp = await fetch(getUrl(path), {
+		method,
+		headers,
+		body: data !== null ? JSON.stringify(data) : null
+	});
+
+	const d = await resp.json();
+	if (!resp.ok) {
+		// errors are serialized as { Kind: "message" }
+		const [kind, msg] = Object.entries(d)[0] ?? ['Unknown', ''];
+		throw new ApiError(kind, msg);
+	}
+
+	return d;
+}
+/// needs to match the protocol version of the server
+export const PROTOCOL_VERSION = 10;
+
+/// parses the hello message the server sends at the start of every websocket
+/// connection
+///
+/// throws if the message is not a hello or the versions don't match
+export function parseHello(data) {
+	const d = JSON.parse(data);
+	if (typeof d !== 'object' || !('Hello' in d))
+		throw new Error('expected hello message');
+
+	const hello = d.Hello;
+	if (hello.version !== PROTOCOL_VERSION) {
+		throw new Error(
+			'server protocol version ' + hello.version + ' not supported, ' +
+			'expected ' + PROTOCOL_VERSION
+		);
+	}
+
+	return hello;
+}
+
+/// returns the hello message the client needs to send after the hello of
+/// the server was received
+export function clientHello(encoding = 'Json') {
+	return JSON.stringify({
+		Hello: {
+			version: PROTOCOL_VERSION,
+			encoding,
+			token: getToken()
+		}
+	});
+}
+
+/// the close code the server uses if the token is invalid
+export const CLOSE_UNAUTHORIZED = 4001;
+
+/// returns { enabled, fingerprint, certificate } doesn't need a token
+export async function tlsInfo() {
+	return await request('GET', '/tls');
+}
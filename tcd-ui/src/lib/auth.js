@@ -0,0 +1,68 @@
+import { request } from './api.js';
+
+const TOKEN_KEY = 'tcd-token';
+
+/// returns the token of this device or null if it is not paired
+export function getToken() {
+	return localStorage.getItem(TOKEN_KEY);
+}
+
+export function removeToken() {
+	localStorage.removeItem(TOKEN_KEY);
+}
+
+/// redeems a pairing code which is shown by the server and stores the token
+export async function pair(code, name) {
+	const resp = await request('POST', '/pairing/redeem', { code, name });
+	localStorage.setItem(TOKEN_KEY, resp.token);
+}
+
+/// generates a new pairing code for another device
+///
+/// role: 'Observer' or { Operator: { categories: null | [str] } }
+export async function newPairingCode(role) {
+	const resp = await request('POST', '/pairing/code', { role });
+	return resp.code;
+}
+
+/// returns all paired devices [{ id, name, role, created_at }]
+export async function devices() {
+	return await request('GET', '/tokens');
+}
+
+export async function revoke(id) {
+	await request('POST', '/tokens/revoke', { id });
+}
+
+/// the new role is applied once the device reconnects
+export async function setRole(id, role) {
+	await request('POST', '/tokens/role', { id, role });
+}
+
+/// converts a role to a string which can be shown to the user
+export function roleName(role) {
+	if (role === 'Observer')
+		return 'Observer';
+
+	const cats = role.Operator.categories;
+	if (!cats)
+		return 'Operator';
+	return 'Operator (' + cats.join(', ') + ')';
+}
+
+/// creates a role from a kind ('Observer' or 'Operator') and a comma
+/// separated list of categories, an empty list means no restriction
+export function newRole(kind, categories = '') {
+	if (kind === 'Observer')
+		return 'Observer';
+
+	const cats = categories.split(',')
+		.map(c => c.trim())
+		.filter(c => c.length > 0);
+
+	return {
+		Operator: {
+			categories: cats.length > 0 ? cats : null
+		}
+	};
+}
@@ -1,11 +1,25 @@
 
 import { newError } from './errors.js';
-import { getUrl } from './api.js';
+import {
+	getWsUrl, parseHello, clientHello, CLOSE_UNAUTHORIZED
+} from './api.js';
+import { removeToken } from './auth.js';
+import { decode } from './msgpack.js';
 import Data from 'fire/data/data.js';
 
 let failed = false;
 let ws = null;
+// the hello from the server, is set once the handshake is done
+let hello = null;
+// the role of this device, is set once the server accepted the token
+let role = null;
+// the guarded control which the server armed { name, until }
+let armed = null;
 let listeners = new Map;// Map<Kind, Set>
+let options = new Map;// Map<Kind, Object>
+// the server only sends outputs which changed so we need to keep the last
+// response for new listeners
+let currentResponses = new Map;// Map<Kind, Response>
 
 export class Response extends Data {
 	constructor(d) {
@@ -105,7 +119,11 @@ export class InputValue extends Data {
 }
 
 // fn (null | Response)
-export function subscribe(name, fn) {
+//
+// opts: { minChange: int, maxRate: float } (only used by the first listener)
+// minChange: integers which change less are not sent (until they settle)
+// maxRate: the maximum updates per second
+export function subscribe(name, fn, opts = null) {
 	if (failed)
 		throw new Error('cannot subscribe websocket connection failed');
 
@@ -115,16 +133,18 @@ export function subscribe(name, fn) {
 		const set = new Set;
 		set.add(fn);
 		listeners.set(name, set);
+		if (opts)
+			options.set(name, opts);
 
 		// need to send subscribe
-		if (ws && ws.readyState === 1)
+		if (hello)
 			sendSubscribe(name);
-		// if the connection is not ready
+		// if the handshake is not done
 		// initWs will automatically subscribe to all kinds that are in
 		// listeners
 	}
 
-	fn(null);
+	fn(currentResponses.get(name) ?? null);
 
 	if (!ws)
 		initWs();
@@ -135,7 +155,10 @@ export function subscribe(name, fn) {
 
 		if (set.size === 0) {
 			listeners.delete(name);
-			ws.send(JSON.stringify({ 'Unsubscribe': name }));
+			options.delete(name);
+			currentResponses.delete(name);
+			if (hello)
+				ws.send(JSON.stringify({ 'Unsubscribe': name }));
 		}
 
 		// if (listeners.size === 0)
@@ -144,18 +167,79 @@ export function subscribe(name, fn) {
 }
 
 // send needs to be of Input type
+//
+// guarded controls need to be sent twice, the first time only arms the
+// control
+//
+// returns true if the input was sent, false if it was dropped or only armed
+// the control
 export function send(input) {
 	if (failed)
 		throw new Error('websocket connection failed');
 
+	// inputs before the handshake is done are dropped
+	if (!hello)
+		return false;
+
+	const guarded = hello.guarded_controls.includes(input.name);
+	const isArmed = armed && armed.name === input.name &&
+		armed.until > Date.now();
+	if (guarded && !isArmed) {
+		ws.send(JSON.stringify({ Arm: input.name }));
+		return false;
+	}
+
+	// the server consumes the arming with this input
+	if (guarded)
+		armed = null;
+
 	ws.send(JSON.stringify({
 		Input: input
 	}));
+	return true;
+}
+
+// puts a momentary control back after send returned true
+//
+// a release is never held back, the value needs to put the control back
+// to where it was before the press
+export function release(input) {
+	if (failed)
+		throw new Error('websocket connection failed');
+
+	if (!hello)
+		return;
+
+	ws.send(JSON.stringify({
+		Release: input
+	}));
 }
 
 // you need to make sure that connection is active
 function sendSubscribe(kind) {
-	ws.send(JSON.stringify({ 'Subscribe': kind }));
+	const opts = options.get(kind);
+	if (!opts) {
+		ws.send(JSON.stringify({ 'Subscribe': kind }));
+		return;
+	}
+
+	ws.send(JSON.stringify({
+		'SubscribeWithOptions': {
+			name: kind,
+			options: {
+				min_change: opts.minChange ?? null,
+				max_rate: opts.maxRate ?? null
+			}
+		}
+	}));
+}
+
+/// returns the role of this device or null if the connection is not
+/// established yet
+///
+/// 'Observer' or { Operator: { categories: null | [str] } }
+export function getRole() {
+	return role;
 }
 
 function notify(kind, value) {
@@ -166,47 +250,110 @@ function notify(kind, value) {
 }
 
 function initWs() {
-	let url = getUrl('/controls/stream');
-	url.protocol = 'ws:';
-	ws = new WebSocket(url);
-
-	ws.addEventListener('open', e => {
-		// the connection was opened
-		// we now may need to call subscribe
-		for (const kind of listeners.keys()) {
-			sendSubscribe(kind);
-		}
-	});
+	ws = new WebSocket(getWsUrl('/controls/stream'));
+	ws.binaryType = 'arraybuffer';
 
 	ws.addEventListener('close', e => {
 		ws = null;
+		hello = null;
+		role = null;
+		armed = null;
 		failed = true;
 
-		newError('Controls stream closed');
+		// the token is not valid anymore, we need to pair again
+		if (e.code === CLOSE_UNAUTHORIZED) {
+			removeToken();
+			window.location.reload();
+			return;
+		}
+
+		newError('Controls stream closed' + (e.reason ? ': ' + e.reason : ''));
 	});
 
-	let len = 0;
 	ws.addEventListener('message', wsMsg => {
+		// the first message is always the hello of the server
+		if (!hello) {
+			try {
+				hello = parseHello(wsMsg.data);
+			} catch (e) {
+				ws.close();
+				newError('Controls stream: ' + e.message);
+				return;
+			}
+
+			// all updates should be sent in a single binary message
+			ws.send(clientHello('MessagePack'));
+
+			// we now may need to call subscribe
+			for (const kind of listeners.keys()) {
+				sendSubscribe(kind);
+			}
+			return;
+		}
+
+		// a binary message contains all responses
+		if (wsMsg.data instanceof ArrayBuffer) {
+			const list = decode(wsMsg.data);
+			ws.send(JSON.stringify('Aknowledge'));
+
+			list.forEach(d => handleResponse(new Response(d)));
+			return;
+		}
+
 		const d = JSON.parse(wsMsg.data);
 
-		// if the len is zero we expect an announce message
-		if (len == 0) {
-			len = d.len;
+		// the server checks if we're still alive
+		if (d === 'Ping') {
+			ws.send(JSON.stringify('Pong'));
 			return;
 		}
 
-		len--;
-		if (len == 0)
-			ws.send(JSON.stringify('Aknowledge'));
+		// the server accepted our token
+		if (typeof d === 'object' && 'Welcome' in d) {
+			role = d.Welcome.role;
+			return;
+		}
 
-		const resp = new Response(d);
+		if (typeof d === 'object' && 'InputDenied' in d) {
+			newError('Not allowed to change ' + d.InputDenied);
+			return;
+		}
 
-		const set = listeners.get(resp.name);
-		if (set)
-			set.forEach(fn => fn(resp));
+		if (typeof d === 'object' && 'Armed' in d) {
+			const { name, window_ms } = d.Armed;
+			armed = { name, until: Date.now() + window_ms };
+			newError(name + ' is armed, press again to confirm');
+			return;
+		}
+
+		if (typeof d === 'object' && 'NotArmed' in d) {
+			newError(d.NotArmed + ' was not confirmed in time');
+			return;
+		}
+
+		if (typeof d === 'object' && 'Replaying' in d) {
+			newError('Cannot change ' + d.Replaying + ' during a debrief');
+			return;
+		}
+
+		// a text message contains all responses if we did not get
+		// MessagePack
+		if (Array.isArray(d)) {
+			ws.send(JSON.stringify('Aknowledge'));
+
+			d.forEach(d => handleResponse(new Response(d)));
+		}
 	});
 }
 
+function handleResponse(resp) {
+	currentResponses.set(resp.name, resp);
+
+	const set = listeners.get(resp.name);
+	if (set)
+		set.forEach(fn => fn(resp));
+}
+
 function closeWs() {
 	if (!ws)
 		return;
@@ -0,0 +1,31 @@
+import { request, getUrl } from './api.js';
+import { getToken } from './auth.js';
+
+/// returns { [name]: { x, y, width, height } } or null
+///
+/// the name is the name of the viewport in dcs for example LEFT_MFCD
+export async function getDisplays() {
+	return await request('GET', '/displays');
+}
+
+/// the server validates the displays and sends them to the driver
+export async function setDisplays(displays) {
+	await request('POST', '/displays', { displays });
+}
+
+/// returns { width, height } or null if the driver is not connected
+///
+/// displays outside of the mode are not sent by the driver
+export async function getMode() {
+	return await request('GET', '/displays/mode');
+}
+
+/// returns the url of the latest frame of a display, can be used directly in
+/// an img tag
+///
+/// format is jpg or png
+export function snapshotUrl(name, format = 'jpg') {
+	const url = getUrl(`/mfds/${encodeURIComponent(name)}.${format}`);
+	url.searchParams.set('token', getToken() ?? '');
+	return url;
+}
@@ -0,0 +1,276 @@
+import {
+	getWsUrl, parseHello, clientHello, CLOSE_UNAUTHORIZED
+} from './api.js';
+import { removeToken } from './auth.js';
+
+const VIDEO_KEY = 'tcd-video';
+// constrained baseline, the server never uses anything else
+const CODEC = 'avc1.42e01f';
+
+/// returns true if the browser can decode the h264 stream
+export function videoSupported() {
+	return 'VideoDecoder' in window;
+}
+
+/// returns true if the displays should be streamed as h264 instead of
+/// jpeg on this device
+export function videoEnabled() {
+	return videoSupported() && localStorage.getItem(VIDEO_KEY) === 'h264';
+}
+
+/// returns true if video is enabled and the server, which sent the hello,
+/// was built with h264 support
+export function videoAvailable(hello) {
+	return videoEnabled() && hello.video_codecs.includes('H264');
+}
+
+/// the page needs to be reloaded for this to take effect
+export function setVideoEnabled(enabled) {
+	if (enabled)
+		localStorage.setItem(VIDEO_KEY, 'h264');
+	else
+		localStorage.removeItem(VIDEO_KEY);
+}
+
+let failed = false;
+// called once the stream failed
+let fallback = null;
+let ws = null;
+// the hello from the server, is set once the handshake is done
+let hello = null;
+let listeners = new Map;// Map<Kind, Set>
+let sizes = new Map;// Map<Kind, { width, height }>
+let decoders = new Map;// Map<Kind, { decoder, width, height }>
+let currentFrames = new Map;// Map<Kind, Canvas>
+
+/// fn get's called once the stream closed or could not be decoded, the
+/// subscriptions are then dropped and the displays should be streamed as
+/// jpeg
+export function setFallback(fn) {
+	fallback = fn;
+}
+
+/// works the same as subscribe in mfdsapi.js
+export function subscribe(kind, fn, size = null) {
+	if (failed)
+		throw new Error('cannot subscribe websocket connection failed');
+
+	if (size)
+		setSize(kind, size);
+
+	if (listeners.has(kind)) {
+		listeners.get(kind).add(fn);
+	} else {
+		const set = new Set;
+		set.add(fn);
+		listeners.set(kind, set);
+
+		if (hello)
+			sendSubscribe(kind);
+	}
+
+	fn(currentFrames.get(kind) ?? null);
+
+	if (!ws)
+		initWs();
+
+	return () => {
+		let set = listeners.get(kind);
+		set.delete(fn);
+
+		if (set.size === 0) {
+			listeners.delete(kind);
+			sizes.delete(kind);
+			currentFrames.delete(kind);
+			closeDecoder(kind);
+			if (hello)
+				ws.send(JSON.stringify({ 'Unsubscribe': kind }));
+		}
+	};
+}
+
+export function setSize(kind, size) {
+	const prev = sizes.get(kind);
+	if (prev && prev.width >= size.width && prev.height >= size.height)
+		return;
+
+	size = {
+		width: Math.max(prev?.width ?? 0, Math.round(size.width)),
+		height: Math.max(prev?.height ?? 0, Math.round(size.height))
+	};
+	sizes.set(kind, size);
+
+	if (hello)
+		ws.send(JSON.stringify({ 'SetSize': { name: kind, size } }));
+}
+
+function sendSubscribe(kind) {
+	const size = sizes.get(kind);
+	if (size)
+		ws.send(JSON.stringify({ 'SetSize': { name: kind, size } }));
+
+	ws.send(JSON.stringify({ 'Subscribe': kind }));
+}
+
+function notify(kind) {
+	const set = listeners.get(kind);
+	if (!set)
+		return;
+	set.forEach(fn => fn(currentFrames.get(kind) ?? null));
+}
+
+function closeDecoder(kind) {
+	const d = decoders.get(kind);
+	if (d && d.decoder.state !== 'closed')
+		d.decoder.close();
+	decoders.delete(kind);
+}
+
+// the decoder needs to be recreated if the size changes
+function getDecoder(kind, width, height) {
+	const prev = decoders.get(kind);
+	if (
+		prev && prev.width === width && prev.height === height &&
+		prev.decoder.state === 'configured'
+	)
+		return prev.decoder;
+
+	closeDecoder(kind);
+
+	const decoder = new VideoDecoder({
+		output: frame => drawFrame(kind, frame),
+		error: e => {
+			console.log('could not decode frame', e);
+			ws?.close();
+		}
+	});
+	// no description means the frames are in the annex b format
+	decoder.configure({
+		codec: CODEC,
+		codedWidth: width,
+		codedHeight: height,
+		optimizeForLatency: true
+	});
+	decoders.set(kind, { decoder, width, height });
+
+	return decoder;
+}
+
+function drawFrame(kind, frame) {
+	let canvas = currentFrames.get(kind);
+	if (!canvas) {
+		canvas = document.createElement('canvas');
+		currentFrames.set(kind, canvas);
+	}
+
+	if (
+		canvas.width !== frame.displayWidth ||
+		canvas.height !== frame.displayHeight
+	) {
+		canvas.width = frame.displayWidth;
+		canvas.height = frame.displayHeight;
+	}
+
+	canvas.getContext('2d').drawImage(frame, 0, 0);
+	frame.close();
+
+	notify(kind);
+}
+
+function initWs() {
+	ws = new WebSocket(getWsUrl('/mfds/h264'));
+	ws.binaryType = 'arraybuffer';
+
+	ws.addEventListener('close', e => {
+		ws = null;
+		hello = null;
+		failed = true;
+
+		if (e.code === CLOSE_UNAUTHORIZED) {
+			removeToken();
+			window.location.reload();
+			return;
+		}
+
+		console.log(
+			'H.264 stream closed' + (e.reason ? ': ' + e.reason : '') +
+			', falling back to jpeg'
+		);
+
+		for (const kind of decoders.keys()) {
+			closeDecoder(kind);
+		}
+		listeners.clear();
+		sizes.clear();
+		currentFrames.clear();
+
+		if (fallback)
+			fallback();
+	});
+
+	// the frames we expect after an announcement
+	// [{ name, width, height, keyframe, timestamp }]
+	let missingFrames = [];
+	ws.addEventListener('message', wsMsg => {
+		if (!hello) {
+			try {
+				hello = parseHello(wsMsg.data);
+			} catch (e) {
+				ws.close();
+				console.log('H.264 stream: ' + e.message);
+				return;
+			}
+
+			ws.send(clientHello());
+
+			for (const kind of listeners.keys()) {
+				sendSubscribe(kind);
+			}
+			return;
+		}
+
+		if (missingFrames.length === 0) {
+			const d = JSON.parse(wsMsg.data);
+
+			if (d === 'Ping') {
+				ws.send(JSON.stringify('Pong'));
+				return;
+			}
+
+			if (typeof d === 'object' && 'Welcome' in d)
+				return;
+
+			if (typeof d !== 'object' || !('list' in d)) {
+				console.log('received unexpected message', d);
+				throw new Error('invalid message');
+			}
+
+			// reverse it so we can just call pop
+			missingFrames = d.list.slice().reverse();
+			return;
+		}
+
+		const info = missingFrames.pop();
+
+		if (missingFrames.length === 0)
+			ws.send(JSON.stringify('Aknowledge'));
+
+		// might have been unsubscribed in the meantime
+		if (!listeners.has(info.name))
+			return;
+
+		const decoder = getDecoder(info.name, info.width, info.height);
+		// a new decoder needs to start with a keyframe, the server sends
+		// one if the size changed or the client was resynced
+		const state = decoders.get(info.name);
+		if (!state.started && !info.keyframe)
+			return;
+		state.started = true;
+
+		decoder.decode(new EncodedVideoChunk({
+			type: info.keyframe ? 'key' : 'delta',
+			timestamp: info.timestamp,
+			data: wsMsg.data
+		}));
+	});
+}
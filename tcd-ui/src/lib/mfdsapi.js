@@ -1,16 +1,42 @@
 
 import { newError } from './errors.js';
-import { getUrl } from './api.js';
+import {
+	getWsUrl, parseHello, clientHello, CLOSE_UNAUTHORIZED
+} from './api.js';
+import { removeToken } from './auth.js';
+import {
+	videoAvailable, setFallback, subscribe as subscribeVideo,
+	setSize as setVideoSize
+} from './h264api.js';
 
 let failed = false;
 let ws = null;
+// the hello from the server, is set once the handshake is done
+let hello = null;
+// if the frames are streamed as h264, decided once the hello was received
+let video = false;
+// the unsubscribe functions of h264api
+let videoSubs = new Map;// Map<Kind, fn>
 let listeners = new Map;// Map<Kind, Set>
-let currentFrames = new Map;
+// the size the server should rescale the frames to
+let sizes = new Map;// Map<Kind, { width, height }>
+let currentFrames = new Map;// Map<Kind, Canvas>
+// the images of a kind are drawn one after another
+let drawing = new Map;// Map<Kind, Promise>
 
-export function subscribe(kind, fn) {
+/// size ({ width, height }) is optional, the server then only sends frames
+/// which fit inside of it, if multiple listeners request a size the
+/// biggest one is used
+///
+/// if enabled on this device and supported by the server the frames are
+/// streamed as h264 instead
+export function subscribe(kind, fn, size = null) {
 	if (failed)
 		throw new Error('cannot subscribe websocket connection failed');
 
+	if (size)
+		setSize(kind, size);
+
 	if (listeners.has(kind)) {
 		listeners.get(kind).add(fn);
 	} else {
@@ -19,9 +45,9 @@ export function subscribe(kind, fn) {
 		listeners.set(kind, set);
 
 		// need to send subscribe
-		if (ws && ws.readyState === 1)
+		if (hello)
 			sendSubscribe(kind);
-		// if the connection is not ready
+		// if the handshake is not done
 		// initWs will automatically subscribe to all kinds that are in
 		// listeners
 	}
@@ -37,7 +63,11 @@ export function subscribe(kind, fn) {
 
 		if (set.size === 0) {
 			listeners.delete(kind);
-			ws.send(JSON.stringify({ 'Unsubscribe': kind }));
+			sizes.delete(kind);
+			// the server sends a keyframe if we subscribe again
+			currentFrames.delete(kind);
+			if (hello)
+				sendUnsubscribe(kind);
 		}
 
 		// if (listeners.size === 0)
@@ -45,11 +75,70 @@ export function subscribe(kind, fn) {
 	};
 }
 
+function setSize(kind, size) {
+	const prev = sizes.get(kind);
+	if (prev && prev.width >= size.width && prev.height >= size.height)
+		return;
+
+	size = {
+		width: Math.max(prev?.width ?? 0, Math.round(size.width)),
+		height: Math.max(prev?.height ?? 0, Math.round(size.height))
+	};
+	sizes.set(kind, size);
+
+	if (hello && video)
+		setVideoSize(kind, size);
+	else if (hello)
+		ws.send(JSON.stringify({ 'SetSize': { name: kind, size } }));
+}
+
 // you need to make sure that connection is active
 function sendSubscribe(kind) {
+	// send the size first so the first frame already has the correct size
+	const size = sizes.get(kind);
+
+	if (video) {
+		const unsubscribe = subscribeVideo(kind, canvas => {
+			if (!canvas)
+				return;
+			currentFrames.set(kind, canvas);
+			notify(kind);
+		}, size);
+		videoSubs.set(kind, unsubscribe);
+		return;
+	}
+
+	if (size)
+		ws.send(JSON.stringify({ 'SetSize': { name: kind, size } }));
+
 	ws.send(JSON.stringify({ 'Subscribe': kind }));
 }
 
+function sendUnsubscribe(kind) {
+	if (video) {
+		videoSubs.get(kind)();
+		videoSubs.delete(kind);
+		return;
+	}
+
+	ws.send(JSON.stringify({ 'Unsubscribe': kind }));
+}
+
+// the h264 stream failed, subscribe to the jpeg frames instead
+setFallback(() => {
+	if (!video)
+		return;
+
+	video = false;
+	videoSubs.clear();
+	if (!hello)
+		return;
+
+	for (const kind of listeners.keys()) {
+		sendSubscribe(kind);
+	}
+});
+
 function notify(kind) {
 	const set = listeners.get(kind);
 	if (!set)
@@ -58,70 +147,141 @@ function notify(kind) {
 }
 
 function initWs() {
-	let url = getUrl('/mfds');
-	url.protocol = 'ws:';
-	ws = new WebSocket(url);
-
-	ws.addEventListener('open', e => {
-		// the connection was opened
-		// we now may need to call subscribe
-		for (const kind of listeners.keys()) {
-			sendSubscribe(kind);
-		}
-	});
+	ws = new WebSocket(getWsUrl('/mfds'));
 
 	ws.addEventListener('close', e => {
 		ws = null;
+		hello = null;
 		failed = true;
 
-		newError('Mfds stream closed');
+		// the token is not valid anymore, we need to pair again
+		if (e.code === CLOSE_UNAUTHORIZED) {
+			removeToken();
+			window.location.reload();
+			return;
+		}
+
+		newError('Mfds stream closed' + (e.reason ? ': ' + e.reason : ''));
 	});
 
-	let missingKinds = [];
+	// the images we expect after an announcement
+	// [{ kind, mime, keyframe, x, y, last }]
+	let missingImages = [];
 	ws.addEventListener('message', wsMsg => {
+		// the first message is always the hello of the server
+		if (!hello) {
+			try {
+				hello = parseHello(wsMsg.data);
+			} catch (e) {
+				ws.close();
+				newError('Mfds stream: ' + e.message);
+				return;
+			}
+
+			ws.send(clientHello());
+
+			video = videoAvailable(hello);
+
+			// we now may need to call subscribe
+			for (const kind of listeners.keys()) {
+				sendSubscribe(kind);
+			}
+			return;
+		}
+
 		// we expect a frames announcement
-		if (missingKinds.length === 0) {
+		if (missingImages.length === 0) {
 			const d = JSON.parse(wsMsg.data);
+
+			// the server checks if we're still alive
+			if (d === 'Ping') {
+				ws.send(JSON.stringify('Pong'));
+				return;
+			}
+
+			// mfd frames are available to every role
+			if (typeof d === 'object' && 'Welcome' in d)
+				return;
+
 			if (typeof d !== 'object' || !('list' in d)) {
 				console.log('received unexpected message', d);
 				throw new Error('invalid message');
 			}
 
+			for (const { name: kind, mime, keyframe, tiles } of d.list) {
+				const images = [];
+				if (keyframe)
+					images.push({ kind, mime, keyframe, x: 0, y: 0 });
+				for (const { x, y } of tiles) {
+					images.push({ kind, mime, keyframe: false, x, y });
+				}
+				images[images.length - 1].last = true;
+
+				missingImages.push(...images);
+			}
+
 			// reverse it so we can just call pop
-			missingKinds = d.list.reverse();
+			missingImages.reverse();
 			return;
 		}
 
-		const kind = missingKinds.pop();
+		const image = missingImages.pop();
 
-		// send aknowledge if we received all kinds
+		// send aknowledge if we received all images
 		// this tells the server it can send another frame
-		if (missingKinds.length === 0)
+		if (missingImages.length === 0)
 			ws.send(JSON.stringify('Aknowledge'));
 
-		// we expect it to be a jpeg blob
+		// the announcement tells us the format of the image
 		let blob = wsMsg.data;
-		blob = blob.slice(0, blob.size, 'image/jpeg');
-		const reader = new FileReader;
-		const readerLoaded = () => {
-			reader.removeEventListener('load', readerLoaded);
-
-			const img = new Image;
-			img.src = reader.result;
-			const imgLoaded = () => {
-				img.removeEventListener('load', imgLoaded);
-
-				currentFrames.set(kind, img);
-				notify(kind);
-			};
-			img.addEventListener('load', imgLoaded);
-			
-		};
-		reader.addEventListener('load', readerLoaded);
-		reader.readAsDataURL(blob);
+		blob = blob.slice(0, blob.size, image.mime);
+		const loading = loadImage(blob);
+
+		// tiles need to be drawn in the order they were received
+		const prev = drawing.get(image.kind) ?? Promise.resolve();
+		const next = prev
+			.then(() => loading)
+			.then(img => drawImage(image, img))
+			.catch(e => console.log('could not draw frame', e));
+		drawing.set(image.kind, next);
 	});
 }
 
+function loadImage(blob) {
+	return new Promise((resolve, reject) => {
+		const img = new Image;
+		img.addEventListener('load', () => {
+			URL.revokeObjectURL(img.src);
+			resolve(img);
+		}, { once: true });
+		img.addEventListener('error', reject, { once: true });
+		img.src = URL.createObjectURL(blob);
+	});
+}
+
+// every kind has a canvas where the keyframe and the tiles get drawn on
+function drawImage({ kind, keyframe, x, y, last }, img) {
+	let canvas = currentFrames.get(kind);
+
+	if (keyframe) {
+		if (!canvas) {
+			canvas = document.createElement('canvas');
+			currentFrames.set(kind, canvas);
+		}
+		canvas.width = img.width;
+		canvas.height = img.height;
+	}
+
+	// tiles are useless without a keyframe
+	if (!canvas)
+		return;
+
+	canvas.getContext('2d').drawImage(img, x, y);
+
+	if (last)
+		notify(kind);
+}
+
 function closeWs() {
 	if (!ws)
 		return;
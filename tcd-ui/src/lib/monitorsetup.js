@@ -0,0 +1,30 @@
+import { request } from './api.js';
+
+/// returns { main: { x, y, width, height }, display_x, display_y }
+export async function getSettings() {
+	return await request('GET', '/monitor-setup/settings');
+}
+
+/// installed MonitorSetup files get updated automatically
+export async function setSettings(settings) {
+	await request('POST', '/monitor-setup/settings', { settings });
+}
+
+/// returns { file_name, lua }
+export async function getLua() {
+	return await request('GET', '/monitor-setup');
+}
+
+/// writes the lua file to the dcs saved games folder, returns the paths
+export async function install() {
+	const resp = await request('POST', '/monitor-setup/install');
+	return resp.paths;
+}
+
+/// imports an existing MonitorSetup file replacing the settings and the
+/// displays, screen ({ width, height }) is only needed if the file uses it
+///
+/// returns { settings, displays }
+export async function importLua(lua, screen = null) {
+	return await request('POST', '/monitor-setup/import', { lua, screen });
+}
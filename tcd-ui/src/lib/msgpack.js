@@ -0,0 +1,120 @@
+const decoder = new TextDecoder;
+
+/// decodes a MessagePack encoded ArrayBuffer
+///
+/// Only supports the types the server sends (no extension types).
+export function decode(buffer) {
+	const reader = new Reader(buffer);
+	const v = reader.value();
+
+	if (reader.pos !== reader.view.byteLength)
+		throw new Error('msgpack: unexpected trailing bytes');
+
+	return v;
+}
+
+class Reader {
+	constructor(buffer) {
+		this.view = new DataView(buffer);
+		this.bytes = new Uint8Array(buffer);
+		this.pos = 0;
+	}
+
+	value() {
+		const b = this.u8();
+
+		// positive fixint
+		if (b <= 0x7f)
+			return b;
+		// fixmap
+		if (b <= 0x8f)
+			return this.map(b & 0x0f);
+		// fixarray
+		if (b <= 0x9f)
+			return this.array(b & 0x0f);
+		// fixstr
+		if (b <= 0xbf)
+			return this.str(b & 0x1f);
+		// negative fixint
+		if (b >= 0xe0)
+			return b - 0x100;
+
+		switch (b) {
+			case 0xc0: return null;
+			case 0xc2: return false;
+			case 0xc3: return true;
+			case 0xc4: return this.bin(this.u8());
+			case 0xc5: return this.bin(this.u16());
+			case 0xc6: return this.bin(this.u32());
+			case 0xca: return this.read(4, o => this.view.getFloat32(o));
+			case 0xcb: return this.read(8, o => this.view.getFloat64(o));
+			case 0xcc: return this.u8();
+			case 0xcd: return this.u16();
+			case 0xce: return this.u32();
+			case 0xcf:
+				return Number(this.read(8, o => this.view.getBigUint64(o)));
+			case 0xd0: return this.read(1, o => this.view.getInt8(o));
+			case 0xd1: return this.read(2, o => this.view.getInt16(o));
+			case 0xd2: return this.read(4, o => this.view.getInt32(o));
+			case 0xd3:
+				return Number(this.read(8, o => this.view.getBigInt64(o)));
+			case 0xd9: return this.str(this.u8());
+			case 0xda: return this.str(this.u16());
+			case 0xdb: return this.str(this.u32());
+			case 0xdc: return this.array(this.u16());
+			case 0xdd: return this.array(this.u32());
+			case 0xde: return this.map(this.u16());
+			case 0xdf: return this.map(this.u32());
+		}
+
+		throw new Error('msgpack: unsupported type 0x' + b.toString(16));
+	}
+
+	read(len, fn) {
+		if (this.pos + len > this.view.byteLength)
+			throw new Error('msgpack: unexpected end');
+
+		const v = fn(this.pos);
+		this.pos += len;
+		return v;
+	}
+
+	u8() {
+		return this.read(1, o => this.view.getUint8(o));
+	}
+
+	u16() {
+		return this.read(2, o => this.view.getUint16(o));
+	}
+
+	u32() {
+		return this.read(4, o => this.view.getUint32(o));
+	}
+
+	str(len) {
+		return this.read(len, o => {
+			return decoder.decode(this.bytes.subarray(o, o + len));
+		});
+	}
+
+	bin(len) {
+		return this.read(len, o => this.bytes.slice(o, o + len));
+	}
+
+	array(len) {
+		const arr = [];
+		for (let i = 0; i < len; i++) {
+			arr.push(this.value());
+		}
+		return arr;
+	}
+
+	map(len) {
+		const obj = {};
+		for (let i = 0; i < len; i++) {
+			const key = this.value();
+			obj[key] = this.value();
+		}
+		return obj;
+	}
+}
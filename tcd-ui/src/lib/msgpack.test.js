@@ -0,0 +1,37 @@
+import { test } from 'node:test';
+import assert from 'node:assert/strict';
+import { readFileSync } from 'node:fs';
+
+import { decode } from './msgpack.js';
+
+// generated with rmp-serde by the msgpack_fixtures test of the server
+const fixtures = JSON.parse(readFileSync(
+	new URL('./msgpack.fixtures.json', import.meta.url)
+));
+
+function bytes(hex) {
+	const arr = new Uint8Array(hex.length / 2);
+	for (let i = 0; i < arr.length; i++) {
+		arr[i] = parseInt(hex.substr(i * 2, 2), 16);
+	}
+	return arr;
+}
+
+test('decodes what rmp-serde encodes', () => {
+	for (const { msgpack, value } of fixtures) {
+		assert.deepEqual(decode(bytes(msgpack).buffer), value);
+	}
+});
+
+test('binary data', () => {
+	const v = decode(bytes('c403010203').buffer);
+	assert.deepEqual(v, new Uint8Array([1, 2, 3]));
+});
+
+test('invalid data', () => {
+	// a str of 3 bytes with only 2
+	assert.throws(() => decode(bytes('a36162').buffer), /unexpected end/);
+	assert.throws(() => decode(bytes('0101').buffer), /trailing bytes/);
+	// ext types are not supported
+	assert.throws(() => decode(bytes('d40000').buffer), /unsupported type/);
+});
@@ -1,4 +1,4 @@
-import { subscribe, send, Input } from './../../../lib/controlsapi.js';
+import { subscribe, send, release, Input } from './../../../lib/controlsapi.js';
 import { timeout } from 'fire/util.js';
 
 export const CENTER = 0;
@@ -105,11 +105,12 @@ export default class IcpDataControlSwitch {
 			state = num == 1 ? 2 : 0;
 		}
 
-		send(Input.integer(name, state));
+		if (!send(Input.integer(name, state)))
+			return;
 		// wait until the right state is set
 		await this.onChange(num);
 		// then but the switch to the center again
-		send(Input.integer(name, 1));
+		release(Input.integer(name, 1));
 	}
 
 	destroy() {
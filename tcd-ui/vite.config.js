@@ -11,6 +11,15 @@ export default defineConfig(({ mode }) => {
 				preventAssignment: true,
 				IN_DEBUG: mode !== 'production'
 			})
-		]
+		],
+		server: {
+			// the ui and the api need to be on the same origin
+			proxy: {
+				'/api': {
+					target: 'http://127.0.0.1:3511',
+					ws: true
+				}
+			}
+		}
 	};
 });
\ No newline at end of file
@@ -0,0 +1,43 @@
+use serde::{Serialize, Deserialize};
+
+// the values need to be normalized
+// to the correct (x, y) values
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct Displays {
+	pub inner: Vec<Display>
+}
+
+/// A viewport the server wan't's to receive, the server assigns the ids
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Display {
+	/// needs to be sent with every frame of this display
+	pub id: u8,
+	/// the name of the viewport in dcs
+	pub name: String,
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32,
+	pub format: Format
+}
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub enum Format {
+	Jpeg {
+		quality: u8
+	},
+	Png,
+	Webp
+}
+
+impl Format {
+	/// the server needs to know the format of every frame
+	pub fn header_byte(&self) -> u8 {
+		match self {
+			Self::Jpeg { .. } => 0,
+			Self::Png => 1,
+			Self::Webp => 2
+		}
+	}
+}
@@ -0,0 +1,119 @@
+use crate::displays::Format;
+use crate::pixels;
+
+use std::io::{Write, Seek};
+
+use image::{RgbaImage, ImageOutputFormat, ColorType, ImageResult};
+use image::codecs::webp::WebPEncoder;
+
+/// Encodes rgba pixels with the format of the display and appends the result
+/// to `out`.
+///
+/// Does nothing if the buffer does not match the size.
+pub fn encode<W: Write + Seek>(
+	format: Format,
+	width: u32,
+	height: u32,
+	mut buffer: Vec<u8>,
+	out: &mut W
+) -> ImageResult<()> {
+	if !matches!(format, Format::Jpeg { .. }) {
+		pixels::set_opaque(&mut buffer);
+	}
+
+	let image = match RgbaImage::from_vec(width, height, buffer) {
+		Some(i) => i,
+		None => return Ok(())
+	};
+
+	match format {
+		Format::Jpeg { quality } => {
+			image.write_to(out, ImageOutputFormat::Jpeg(quality))
+		},
+		Format::Png => image.write_to(out, ImageOutputFormat::Png),
+		Format::Webp => WebPEncoder::new_lossless(out).encode(
+			&image,
+			width,
+			height,
+			ColorType::Rgba8
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	use std::io::Cursor;
+
+	use image::ImageFormat;
+
+	fn gradient(width: u32, height: u32) -> Vec<u8> {
+		let mut buffer = vec![];
+		for y in 0..height {
+			for x in 0..width {
+				buffer.extend_from_slice(&[x as u8 * 8, y as u8 * 8, 128, 0]);
+			}
+		}
+		buffer
+	}
+
+	fn decode(data: &[u8], format: ImageFormat) -> image::RgbaImage {
+		image::load_from_memory_with_format(data, format)
+			.unwrap()
+			.into_rgba8()
+	}
+
+	fn encode_to_vec(format: Format, width: u32, height: u32) -> Vec<u8> {
+		let mut out = Cursor::new(vec![]);
+		encode(format, width, height, gradient(width, height), &mut out)
+			.unwrap();
+		out.into_inner()
+	}
+
+	#[test]
+	fn png_is_lossless_and_opaque() {
+		let data = encode_to_vec(Format::Png, 16, 8);
+		let image = decode(&data, ImageFormat::Png);
+
+		let mut expected = gradient(16, 8);
+		pixels::set_opaque(&mut expected);
+		assert_eq!(image.dimensions(), (16, 8));
+		assert_eq!(image.into_raw(), expected);
+	}
+
+	#[test]
+	fn webp_is_lossless_and_opaque() {
+		let data = encode_to_vec(Format::Webp, 16, 8);
+		let image = decode(&data, ImageFormat::WebP);
+
+		let mut expected = gradient(16, 8);
+		pixels::set_opaque(&mut expected);
+		assert_eq!(image.into_raw(), expected);
+	}
+
+	#[test]
+	fn jpeg_keeps_the_size() {
+		let data = encode_to_vec(Format::Jpeg { quality: 80 }, 24, 16);
+		let image = decode(&data, ImageFormat::Jpeg);
+		assert_eq!(image.dimensions(), (24, 16));
+	}
+
+	#[test]
+	fn appends_to_the_output() {
+		let mut out = Cursor::new(vec![1, 2, 3]);
+		out.set_position(3);
+		encode(Format::Png, 4, 4, gradient(4, 4), &mut out).unwrap();
+
+		let data = out.into_inner();
+		assert_eq!(&data[..3], [1, 2, 3]);
+		assert!(image::load_from_memory(&data[3..]).is_ok());
+	}
+
+	#[test]
+	fn wrong_size_is_skipped() {
+		let mut out = Cursor::new(vec![]);
+		encode(Format::Png, 4, 4, vec![0; 3], &mut out).unwrap();
+		assert!(out.into_inner().is_empty());
+	}
+}
@@ -0,0 +1,13 @@
+//! The frame pipeline of the virtual display driver, without anything
+//! windows specific.
+//!
+//! The driver captures the whole virtual screen, crops every display out of
+//! it, encodes the display as a keyframe or the tiles which changed and
+//! sends it to the server.
+
+pub mod displays;
+pub mod screen;
+pub mod pixels;
+pub mod tiles;
+pub mod encode;
+pub mod protocol;
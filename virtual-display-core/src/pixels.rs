@@ -0,0 +1,45 @@
+/// every format the driver receives has four bytes per pixel
+pub const BYTES_PER_PIXEL: usize = 4;
+
+/// Converts the pixels in place from bgra, the format of the desktop, to
+/// rgba which the encoders expect.
+pub fn bgra_to_rgba(buffer: &mut [u8]) {
+	// is quite fast (max around 1ms)
+	for bgra in buffer.chunks_exact_mut(BYTES_PER_PIXEL) {
+		bgra.swap(0, 2);
+	}
+}
+
+/// The alpha channel of the desktop is not defined, lossless formats would
+/// keep it.
+pub fn set_opaque(buffer: &mut [u8]) {
+	for px in buffer.chunks_exact_mut(BYTES_PER_PIXEL) {
+		px[3] = 255;
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn swaps_red_and_blue() {
+		let mut buffer = vec![1, 2, 3, 4, 5, 6, 7, 8];
+		bgra_to_rgba(&mut buffer);
+		assert_eq!(buffer, [3, 2, 1, 4, 7, 6, 5, 8]);
+	}
+
+	#[test]
+	fn ignores_incomplete_pixels() {
+		let mut buffer = vec![1, 2, 3, 4, 5, 6];
+		bgra_to_rgba(&mut buffer);
+		assert_eq!(buffer, [3, 2, 1, 4, 5, 6]);
+	}
+
+	#[test]
+	fn opaque_only_changes_alpha() {
+		let mut buffer = vec![1, 2, 3, 0, 5, 6, 7, 128];
+		set_opaque(&mut buffer);
+		assert_eq!(buffer, [1, 2, 3, 255, 5, 6, 7, 255]);
+	}
+}
@@ -0,0 +1,788 @@
+//! The protocol between the driver and the server.
+//!
+//! The server sends the displays it wan't's to receive whenever they change:
+//!
+//! ┌───┬──────────────┐
+//! │Len│   Displays   │
+//! ├───┼──────────────┤
+//! │32 │$Len*8 (json) │
+//! └───┴──────────────┘
+//!
+//! For every captured frame the driver sends how many displays follow and
+//! then every display which changed:
+//!
+//! ┌────────────┐
+//! │Displays Len│
+//! ├────────────┤
+//! │     8      │
+//! └────────────┘
+//!
+//! ┌──┬──────┬────┬───┬──────┐
+//! │Id│Format│Kind│Len│ Data │
+//! ├──┼──────┼────┼───┼──────┤
+//! │8 │  8   │ 8  │32 │$Len*8│
+//! └──┴──────┴────┴───┴──────┘
+//!
+//! Kind 0 is a keyframe where data contains the image, kind 1 contains the
+//! tiles which changed since the last frame:
+//!
+//! ┌─────┬──┬──┬───┬──────┐
+//! │Count│X │Y │Len│ Data │
+//! ├─────┼──┼──┼───┼──────┤
+//! │ 16  │16│16│32 │$Len*8│
+//! └─────┴──┴──┴───┴──────┘
+//! X, Y, Len and Data are repeated for every tile
+//!
+//! Whenever the mode of the screen changes the driver sends it instead of a
+//! frame, the displays len is then 255:
+//!
+//! ┌──────┬─────┬──────┐
+//! │Marker│Width│Height│
+//! ├──────┼─────┼──────┤
+//! │  8   │ 32  │  32  │
+//! └──────┴─────┴──────┘
+
+use crate::displays::{Displays, Display};
+use crate::screen::Screen;
+use crate::pixels::BYTES_PER_PIXEL;
+use crate::tiles::{self, TILE_SIZE};
+use crate::encode::encode;
+
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use rayon::prelude::*;
+
+use simple_bytes::{Bytes, BytesOwned, BytesRead, BytesSeek, BytesWrite};
+
+/// the port the server listens on for the driver
+pub const ADDR: &str = "127.0.0.1:5476";
+
+pub const HEADER_LEN: usize = 7;
+/// after how many delta frames a keyframe is sent
+pub const KEYFRAME_INTERVAL: u32 = 120;
+
+pub const KIND_KEYFRAME: u8 = 0;
+pub const KIND_DELTA: u8 = 1;
+
+/// sent instead of the displays len, so a frame can contain at most 254
+/// displays
+pub const MODE_MARKER: u8 = 255;
+/// the width and height after the mode marker
+pub const MODE_LEN: usize = 8;
+
+/// Reads the displays after the length was read, the driver only reads the
+/// length if it is available.
+pub fn read_displays_body<R: Read>(
+	reader: &mut R,
+	len: u32
+) -> io::Result<Option<Displays>> {
+	let mut buf = vec![0; len as usize];
+	reader.read_exact(&mut buf)?;
+
+	serde_json::from_slice(&buf).map_err(io::Error::other)
+}
+
+/// Reads the displays the server sends, None means the server does not
+/// want any display.
+pub fn read_displays<R: Read>(reader: &mut R) -> io::Result<Option<Displays>> {
+	let mut len = [0u8; 4];
+	reader.read_exact(&mut len)?;
+	read_displays_body(reader, u32::from_be_bytes(len))
+}
+
+/// Tells the server which mode windows uses, so it can warn about displays
+/// outside of the screen.
+pub fn write_mode<W: Write>(
+	writer: &mut W,
+	screen: &Screen
+) -> io::Result<()> {
+	let mut buf = BytesOwned::with_capacity(9);
+	buf.write_u8(MODE_MARKER);
+	buf.write_u32(screen.width);
+	buf.write_u32(screen.height);
+	writer.write_all(buf.as_slice())
+}
+
+/// The resolution of the screen the driver captures
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Mode {
+	pub width: u32,
+	pub height: u32
+}
+
+impl Mode {
+	/// parses what follows the mode marker
+	pub fn from_bytes(bytes: [u8; MODE_LEN]) -> Self {
+		let mut bytes = Bytes::from(&bytes[..]);
+		Self {
+			width: bytes.read_u32(),
+			height: bytes.read_u32()
+		}
+	}
+}
+
+/// The header in front of the data of every display in a frame
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DisplayHeader {
+	pub id: u8,
+	/// see [`Format::header_byte`](crate::displays::Format::header_byte)
+	pub format: u8,
+	/// [`KIND_KEYFRAME`] or [`KIND_DELTA`]
+	pub kind: u8,
+	/// how many bytes of data follow
+	pub len: u32
+}
+
+impl DisplayHeader {
+	pub fn from_bytes(bytes: [u8; HEADER_LEN]) -> Self {
+		let mut bytes = Bytes::from(&bytes[..]);
+		Self {
+			id: bytes.read_u8(),
+			format: bytes.read_u8(),
+			kind: bytes.read_u8(),
+			len: bytes.read_u32()
+		}
+	}
+}
+
+/// What the driver sends, see the module documentation
+#[derive(Debug)]
+pub enum Message {
+	/// the displays which changed with their data
+	Frame(Vec<(DisplayHeader, Vec<u8>)>),
+	Mode(Mode)
+}
+
+/// Reads a frame or a mode like the server does.
+pub fn read_message<R: Read>(reader: &mut R) -> io::Result<Message> {
+	let mut len = [0u8; 1];
+	reader.read_exact(&mut len)?;
+
+	if len[0] == MODE_MARKER {
+		let mut mode = [0u8; MODE_LEN];
+		reader.read_exact(&mut mode)?;
+		return Ok(Message::Mode(Mode::from_bytes(mode)))
+	}
+
+	let displays = (0..len[0])
+		.map(|_| {
+			let mut header = [0u8; HEADER_LEN];
+			reader.read_exact(&mut header)?;
+			let header = DisplayHeader::from_bytes(header);
+
+			let mut data = vec![0; header.len as usize];
+			reader.read_exact(&mut data)?;
+			Ok((header, data))
+		})
+		.collect::<io::Result<_>>()?;
+
+	Ok(Message::Frame(displays))
+}
+
+/// A tile of a delta frame, the position is relative to the display
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedTile<'a> {
+	pub x: u16,
+	pub y: u16,
+	/// the encoded image
+	pub data: &'a [u8]
+}
+
+/// Parses the data of a delta frame.
+pub fn parse_tiles(data: &[u8]) -> io::Result<Vec<EncodedTile<'_>>> {
+	let mut bytes = Bytes::from(data);
+	let invalid = || {
+		io::Error::new(io::ErrorKind::InvalidData, "invalid tiles")
+	};
+
+	let count = bytes.try_read_u16().map_err(|_| invalid())?;
+	let mut tiles = Vec::with_capacity(count as usize);
+	for _ in 0..count {
+		let x = bytes.try_read_u16().map_err(|_| invalid())?;
+		let y = bytes.try_read_u16().map_err(|_| invalid())?;
+		let len = bytes.try_read_u32().map_err(|_| invalid())?;
+		// the data needs to borrow from the input not from bytes
+		let start = bytes.position();
+		bytes.try_read(len as usize).map_err(|_| invalid())?;
+		let data = &data[start..start + len as usize];
+
+		tiles.push(EncodedTile { x, y, data });
+	}
+
+	if !bytes.remaining().is_empty() {
+		return Err(invalid())
+	}
+
+	Ok(tiles)
+}
+
+/// A display could not be encoded
+#[derive(Debug)]
+pub struct EncodeError {
+	pub display: String,
+	pub error: image::ImageError
+}
+
+impl fmt::Display for EncodeError {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		write!(f, "could not encode {} {}", self.display, self.error)
+	}
+}
+
+/// Remembers what was last sent of a display, so only the changes need to
+/// be sent.
+#[derive(Debug, Clone, Default)]
+pub struct DisplayEncoder {
+	/// the raw pixels of the last frame
+	prev: Vec<u8>,
+	/// the hash of the region of the last frame
+	hash: Option<u64>,
+	since_keyframe: u32
+}
+
+impl DisplayEncoder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Writes a keyframe or the changed tiles of the display to `out`, if
+	/// nothing changed `out` stays empty.
+	///
+	/// The display needs to be inside of the screen.
+	pub fn encode_screen(
+		&mut self,
+		display: &Display,
+		screen: &Screen,
+		data: &[u8],
+		out: &mut BytesOwned
+	) -> image::ImageResult<()> {
+		out.resize(0);
+
+		// most of the time only the main view changes, so check the region
+		// before copying and comparing it
+		let hash = screen.hash(display, data);
+		if self.hash == Some(hash) {
+			return Ok(())
+		}
+
+		let buffer = screen.crop(display, data);
+		self.encode_pixels(display, buffer, out)?;
+		self.hash = Some(hash);
+
+		Ok(())
+	}
+
+	/// Writes a keyframe or the changed tiles of the display to `out`, if
+	/// nothing changed `out` stays empty.
+	///
+	/// The buffer contains the rgba pixels of the display.
+	pub fn encode_pixels(
+		&mut self,
+		display: &Display,
+		buffer: Vec<u8>,
+		out: &mut BytesOwned
+	) -> image::ImageResult<()> {
+		out.resize(0);
+		// the hash is only valid for frames from the same screen
+		self.hash = None;
+
+		let mut keyframe = self.prev.len() != buffer.len() ||
+			self.since_keyframe >= KEYFRAME_INTERVAL;
+
+		let changed = if keyframe {
+			vec![]
+		} else {
+			tiles::changed_tiles(
+				&self.prev,
+				&buffer,
+				display.width,
+				display.height,
+				BYTES_PER_PIXEL
+			)
+		};
+
+		// if most of the display changed a keyframe is smaller
+		let total_tiles = display.width.div_ceil(TILE_SIZE) *
+			display.height.div_ceil(TILE_SIZE);
+		keyframe |= changed.len() * 2 > total_tiles as usize;
+
+		if !keyframe && changed.is_empty() {
+			return Ok(())
+		}
+
+		out.resize(HEADER_LEN);
+		out.seek(HEADER_LEN);
+
+		if keyframe {
+			encode(
+				display.format,
+				display.width,
+				display.height,
+				buffer.clone(),
+				out
+			)?;
+			self.since_keyframe = 0;
+		} else {
+			out.write_u16(changed.len() as u16);
+			for tile in &changed {
+				out.write_u16(tile.x as u16);
+				out.write_u16(tile.y as u16);
+				let len_pos = out.position();
+				out.write_u32(0);
+
+				let tile_buffer = tiles::copy_tile(
+					&buffer,
+					tile,
+					display.width as usize * BYTES_PER_PIXEL,
+					BYTES_PER_PIXEL
+				);
+				encode(
+					display.format,
+					tile.width,
+					tile.height,
+					tile_buffer,
+					out
+				)?;
+
+				let end = out.position();
+				out.seek(len_pos);
+				out.write_u32((end - len_pos - 4) as u32);
+				out.seek(end);
+			}
+			self.since_keyframe += 1;
+		}
+
+		self.prev = buffer;
+
+		out.seek(0);
+		out.write_u8(display.id);
+		out.write_u8(display.format.header_byte());
+		out.write_u8(if keyframe { KIND_KEYFRAME } else { KIND_DELTA });
+		let len = out.len() - HEADER_LEN;
+		out.write_u32(len as u32);
+
+		Ok(())
+	}
+}
+
+/// Encodes every display of a frame in parallel and writes the result in
+/// the protocol format.
+#[derive(Debug, Default)]
+pub struct FrameEncoder {
+	encoders: Vec<DisplayEncoder>,
+	buffers: Vec<BytesOwned>
+}
+
+impl FrameEncoder {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Needs to be called after new displays were received, the next frame
+	/// of every display will be a keyframe.
+	pub fn reset(&mut self) {
+		self.encoders.clear();
+	}
+
+	fn prepare(&mut self, len: usize) {
+		if self.encoders.len() != len {
+			self.encoders.clear();
+			self.encoders.resize(len, DisplayEncoder::new());
+		}
+		// the buffers get reused, to not allocate for every frame
+		self.buffers.resize_with(len, BytesOwned::new);
+	}
+
+	/// Encodes every display from the captured frame of the screen.
+	///
+	/// Displays which could not be encoded are skipped, their next frame
+	/// will be a keyframe.
+	pub fn encode_screen(
+		&mut self,
+		displays: &Displays,
+		screen: &Screen,
+		data: &[u8]
+	) -> Vec<EncodeError> {
+		self.prepare(displays.inner.len());
+
+		displays.inner.par_iter()
+			.zip(&mut self.encoders)
+			.zip(&mut self.buffers)
+			.filter_map(|((display, encoder), buffer)| {
+				// a display outside of the screen cannot be cropped
+				if !screen.contains(display) {
+					buffer.resize(0);
+					return None
+				}
+
+				let r = encoder.encode_screen(display, screen, data, buffer);
+				Self::handle_error(display, encoder, buffer, r)
+			})
+			.collect()
+	}
+
+	/// Encodes the rgba pixels of every display, `images` needs to be in the
+	/// same order as the displays.
+	pub fn encode_pixels(
+		&mut self,
+		displays: &Displays,
+		images: Vec<Vec<u8>>
+	) -> Vec<EncodeError> {
+		self.prepare(displays.inner.len());
+
+		displays.inner.par_iter()
+			.zip(images)
+			.zip(&mut self.encoders)
+			.zip(&mut self.buffers)
+			.filter_map(|(((display, image), encoder), buffer)| {
+				let r = encoder.encode_pixels(display, image, buffer);
+				Self::handle_error(display, encoder, buffer, r)
+			})
+			.collect()
+	}
+
+	fn handle_error(
+		display: &Display,
+		encoder: &mut DisplayEncoder,
+		buffer: &mut BytesOwned,
+		r: image::ImageResult<()>
+	) -> Option<EncodeError> {
+		let error = r.err()?;
+		buffer.resize(0);
+		// the next frame needs to be a keyframe
+		*encoder = DisplayEncoder::new();
+
+		Some(EncodeError {
+			display: display.name.clone(),
+			error
+		})
+	}
+
+	/// Writes how many displays changed followed by the changed displays.
+	pub fn write_to<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+		let changed: Vec<_> = self.buffers.iter()
+			.filter(|b| b.len() > 0)
+			.collect();
+
+		writer.write_all(&[changed.len() as u8])?;
+		for buffer in changed {
+			writer.write_all(buffer.as_slice())?;
+		}
+
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::displays::Format;
+	use crate::screen::PixelFormat;
+
+	use image::ImageFormat;
+
+	fn display(id: u8, x: u32, width: u32, height: u32) -> Display {
+		Display {
+			id,
+			name: format!("D{id}"),
+			x,
+			y: 0,
+			width,
+			height,
+			format: Format::Png
+		}
+	}
+
+	#[derive(Debug)]
+	struct ReceivedDisplay {
+		id: u8,
+		format: u8,
+		kind: u8,
+		data: Vec<u8>
+	}
+
+	/// reads a frame with the functions the server uses
+	fn parse_frame(data: &[u8]) -> Vec<ReceivedDisplay> {
+		let mut reader = data;
+		let frame = match read_message(&mut reader).unwrap() {
+			Message::Frame(f) => f,
+			m => panic!("expected a frame {m:?}")
+		};
+		assert!(reader.is_empty());
+
+		frame.into_iter()
+			.map(|(header, data)| ReceivedDisplay {
+				id: header.id,
+				format: header.format,
+				kind: header.kind,
+				data
+			})
+			.collect()
+	}
+
+	/// returns the position and the decoded pixels of every tile
+	fn decode_tiles(data: &[u8]) -> Vec<(u16, u16, Vec<u8>)> {
+		parse_tiles(data).unwrap()
+			.into_iter()
+			.map(|tile| {
+				let image = image::load_from_memory_with_format(
+					tile.data,
+					ImageFormat::Png
+				).unwrap();
+				(tile.x, tile.y, image.into_rgba8().into_raw())
+			})
+			.collect()
+	}
+
+	fn solid(width: u32, height: u32, value: u8) -> Vec<u8> {
+		vec![value; (width * height) as usize * BYTES_PER_PIXEL]
+	}
+
+	#[test]
+	fn reads_displays() {
+		let json = br#"[{"id":1,"name":"LEFT","x":0,"y":0,"width":640,
+			"height":640,"format":{"Jpeg":{"quality":80}}}]"#;
+		let mut data = (json.len() as u32).to_be_bytes().to_vec();
+		data.extend_from_slice(json);
+		data.extend_from_slice(&4u32.to_be_bytes());
+		data.extend_from_slice(b"null");
+
+		let mut reader = &data[..];
+		let displays = read_displays(&mut reader).unwrap().unwrap();
+		assert_eq!(displays.inner.len(), 1);
+		assert_eq!(displays.inner[0].name, "LEFT");
+		assert_eq!(displays.inner[0].format.header_byte(), 0);
+
+		assert!(read_displays(&mut reader).unwrap().is_none());
+		assert!(read_displays(&mut reader).is_err());
+	}
+
+	#[test]
+	fn mode_and_frames() {
+		let screen = Screen::new(200, 100, PixelFormat::Rgba);
+		let displays = Displays { inner: vec![display(4, 0, 100, 100)] };
+		let mut encoder = FrameEncoder::new();
+		encoder.encode_screen(&displays, &screen, &solid(200, 100, 10));
+
+		let mut out = vec![];
+		write_mode(&mut out, &screen).unwrap();
+		encoder.write_to(&mut out).unwrap();
+
+		let mut reader = &out[..];
+		match read_message(&mut reader).unwrap() {
+			Message::Mode(mode) => {
+				assert_eq!(mode, Mode { width: 200, height: 100 });
+			},
+			m => panic!("expected a mode {m:?}")
+		}
+		match read_message(&mut reader).unwrap() {
+			Message::Frame(frame) => {
+				assert_eq!(frame.len(), 1);
+				let (header, data) = &frame[0];
+				assert_eq!(header.id, 4);
+				assert_eq!(header.kind, KIND_KEYFRAME);
+				assert_eq!(header.len as usize, data.len());
+			},
+			m => panic!("expected a frame {m:?}")
+		}
+		assert!(reader.is_empty());
+		assert!(read_message(&mut reader).is_err());
+	}
+
+	#[test]
+	fn invalid_tiles() {
+		let display = display(0, 0, 128, 128);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		let mut buffer = solid(128, 128, 10);
+		encoder.encode_pixels(&display, buffer.clone(), &mut out).unwrap();
+		buffer[0] = 200;
+		encoder.encode_pixels(&display, buffer, &mut out).unwrap();
+		assert_eq!(out.as_slice()[2], KIND_DELTA);
+		let data = &out.as_slice()[HEADER_LEN..];
+		assert_eq!(parse_tiles(data).unwrap().len(), 1);
+
+		// the data of the tile is missing a byte
+		assert!(parse_tiles(&data[..data.len() - 1]).is_err());
+		let mut longer = data.to_vec();
+		longer.push(0);
+		assert!(parse_tiles(&longer).is_err());
+		assert!(parse_tiles(&[0]).is_err());
+	}
+
+	#[test]
+	fn first_frame_is_a_keyframe() {
+		let display = display(3, 0, 100, 100);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		encoder.encode_pixels(&display, solid(100, 100, 10), &mut out)
+			.unwrap();
+		let data = out.as_slice();
+		assert_eq!(data[..3], [3, Format::Png.header_byte(), KIND_KEYFRAME]);
+		let len = u32::from_be_bytes(data[3..7].try_into().unwrap());
+		assert_eq!(len as usize, data.len() - HEADER_LEN);
+
+		let image = image::load_from_memory(&data[HEADER_LEN..]).unwrap();
+		assert_eq!((image.width(), image.height()), (100, 100));
+	}
+
+	#[test]
+	fn unchanged_frame_is_empty() {
+		let display = display(0, 0, 100, 100);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		encoder.encode_pixels(&display, solid(100, 100, 10), &mut out)
+			.unwrap();
+		encoder.encode_pixels(&display, solid(100, 100, 10), &mut out)
+			.unwrap();
+		assert_eq!(out.len(), 0);
+	}
+
+	#[test]
+	fn sends_changed_tiles() {
+		let (width, height) = (200, 130);
+		let display = display(0, 0, width, height);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		let mut buffer = solid(width, height, 10);
+		encoder.encode_pixels(&display, buffer.clone(), &mut out).unwrap();
+
+		// change a pixel in the bottom right tile, which is smaller
+		let pos = ((height - 1) * width + width - 1) as usize;
+		buffer[pos * BYTES_PER_PIXEL] = 200;
+		encoder.encode_pixels(&display, buffer, &mut out).unwrap();
+
+		let data = out.as_slice();
+		assert_eq!(data[2], KIND_DELTA);
+
+		let tiles = decode_tiles(&data[HEADER_LEN..]);
+		assert_eq!(tiles.len(), 1);
+		let (x, y, pixels) = &tiles[0];
+		assert_eq!((*x, *y), (192, 128));
+		// 8x2 pixels
+		assert_eq!(pixels.len(), 8 * 2 * BYTES_PER_PIXEL);
+		assert_eq!(pixels[pixels.len() - BYTES_PER_PIXEL], 200);
+	}
+
+	#[test]
+	fn big_changes_are_sent_as_keyframe() {
+		let display = display(0, 0, 128, 128);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		encoder.encode_pixels(&display, solid(128, 128, 10), &mut out)
+			.unwrap();
+		encoder.encode_pixels(&display, solid(128, 128, 20), &mut out)
+			.unwrap();
+		assert_eq!(out.as_slice()[2], KIND_KEYFRAME);
+	}
+
+	#[test]
+	fn keyframe_after_interval() {
+		let display = display(0, 0, 128, 128);
+		let mut encoder = DisplayEncoder::new();
+		let mut out = BytesOwned::new();
+
+		let mut buffer = solid(128, 128, 10);
+		encoder.encode_pixels(&display, buffer.clone(), &mut out).unwrap();
+
+		for i in 0..KEYFRAME_INTERVAL {
+			buffer[0] = i as u8;
+			encoder.encode_pixels(&display, buffer.clone(), &mut out).unwrap();
+			assert_eq!(out.as_slice()[2], KIND_DELTA);
+		}
+
+		buffer[0] = 255;
+		encoder.encode_pixels(&display, buffer, &mut out).unwrap();
+		assert_eq!(out.as_slice()[2], KIND_KEYFRAME);
+	}
+
+	#[test]
+	fn frame_only_contains_changed_displays() {
+		let screen = Screen::new(200, 100, PixelFormat::Rgba);
+		let displays = Displays {
+			inner: vec![display(0, 0, 100, 100), display(1, 100, 100, 100)]
+		};
+		let mut data = solid(200, 100, 10);
+		let mut encoder = FrameEncoder::new();
+
+		assert!(encoder.encode_screen(&displays, &screen, &data).is_empty());
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		let received = parse_frame(&out);
+		assert_eq!(received.len(), 2);
+		assert!(received.iter().all(|d| d.kind == KIND_KEYFRAME));
+		assert_eq!(received[1].id, 1);
+		assert_eq!(received[1].format, Format::Png.header_byte());
+
+		// change a pixel of the second display
+		data[150 * BYTES_PER_PIXEL] = 200;
+		encoder.encode_screen(&displays, &screen, &data);
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		let received = parse_frame(&out);
+		assert_eq!(received.len(), 1);
+		assert_eq!(received[0].id, 1);
+		assert_eq!(received[0].kind, KIND_DELTA);
+		assert_eq!(decode_tiles(&received[0].data)[0].0, 0);
+
+		// nothing changed
+		encoder.encode_screen(&displays, &screen, &data);
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		assert_eq!(out, [0]);
+	}
+
+	#[test]
+	fn reset_sends_keyframes() {
+		let screen = Screen::new(100, 100, PixelFormat::Rgba);
+		let displays = Displays { inner: vec![display(0, 0, 100, 100)] };
+		let data = solid(100, 100, 10);
+		let mut encoder = FrameEncoder::new();
+
+		encoder.encode_screen(&displays, &screen, &data);
+		encoder.reset();
+		encoder.encode_screen(&displays, &screen, &data);
+
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		assert_eq!(parse_frame(&out)[0].kind, KIND_KEYFRAME);
+	}
+
+	#[test]
+	fn displays_outside_are_skipped() {
+		let screen = Screen::new(100, 100, PixelFormat::Rgba);
+		let displays = Displays { inner: vec![display(0, 50, 100, 100)] };
+		let mut encoder = FrameEncoder::new();
+
+		encoder.encode_screen(&displays, &screen, &solid(100, 100, 10));
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		assert_eq!(out, [0]);
+	}
+
+	#[test]
+	fn encodes_rendered_pixels() {
+		let displays = Displays {
+			inner: vec![display(0, 0, 10, 10), display(1, 0, 20, 10)]
+		};
+		let mut encoder = FrameEncoder::new();
+
+		let errors = encoder.encode_pixels(
+			&displays,
+			vec![solid(10, 10, 1), solid(20, 10, 2)]
+		);
+		assert!(errors.is_empty());
+
+		let mut out = vec![];
+		encoder.write_to(&mut out).unwrap();
+		let received = parse_frame(&out);
+		let image = image::load_from_memory(&received[1].data).unwrap();
+		assert_eq!((image.width(), image.height()), (20, 10));
+	}
+}
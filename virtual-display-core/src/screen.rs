@@ -0,0 +1,233 @@
+use crate::displays::Display;
+use crate::pixels::{self, BYTES_PER_PIXEL};
+use crate::tiles::{self, Tile};
+
+/// How the pixels of the screen are stored
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelFormat {
+	/// the format of the windows desktop
+	Bgra,
+	Rgba
+}
+
+impl PixelFormat {
+	/// the number the ffi uses
+	pub fn from_u32(num: u32) -> Option<Self> {
+		match num {
+			0 => Some(Self::Bgra),
+			1 => Some(Self::Rgba),
+			_ => None
+		}
+	}
+}
+
+/// The virtual screen the driver captures, all displays get cropped out of
+/// it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Screen {
+	pub width: u32,
+	pub height: u32,
+	/// how many bytes a row has, the gpu can add padding to every row so
+	/// this can be more than `width * 4`
+	pub stride: u32,
+	pub format: PixelFormat
+}
+
+impl Screen {
+	/// the mode the driver uses until it reported one
+	pub const DEFAULT: Self = Self::new(1920, 1080, PixelFormat::Bgra);
+
+	/// a screen without any padding
+	pub const fn new(width: u32, height: u32, format: PixelFormat) -> Self {
+		Self {
+			width, height,
+			stride: width * BYTES_PER_PIXEL as u32,
+			format
+		}
+	}
+
+	/// returns None if the rows don't fit into the stride
+	pub fn with_stride(
+		width: u32,
+		height: u32,
+		stride: u32,
+		format: PixelFormat
+	) -> Option<Self> {
+		let min_stride = width as u64 * BYTES_PER_PIXEL as u64;
+		(stride as u64 >= min_stride).then_some(Self {
+			width, height, stride, format
+		})
+	}
+
+	/// how many bytes a captured frame has
+	pub fn len(&self) -> usize {
+		self.stride as usize * self.height as usize
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.len() == 0
+	}
+
+	/// returns true if the display is completely inside of the screen
+	pub fn contains(&self, display: &Display) -> bool {
+		// use u64 so big values cannot overflow
+		display.x as u64 + display.width as u64 <= self.width as u64 &&
+			display.y as u64 + display.height as u64 <= self.height as u64
+	}
+
+	fn region(display: &Display) -> Tile {
+		Tile {
+			x: display.x,
+			y: display.y,
+			width: display.width,
+			height: display.height
+		}
+	}
+
+	/// Copies the pixels of the display out of the frame and converts them
+	/// to rgba.
+	///
+	/// The display needs to be inside of the screen.
+	pub fn crop(&self, display: &Display, data: &[u8]) -> Vec<u8> {
+		let mut buffer = tiles::copy_tile(
+			data,
+			&Self::region(display),
+			self.stride as usize,
+			BYTES_PER_PIXEL
+		);
+
+		// only convert the cropped part, most of the screen is never sent
+		if self.format == PixelFormat::Bgra {
+			pixels::bgra_to_rgba(&mut buffer);
+		}
+
+		buffer
+	}
+
+	/// Hashes the pixels of the display, used to find out cheaply if it
+	/// changed without copying it.
+	pub fn hash(&self, display: &Display, data: &[u8]) -> u64 {
+		tiles::hash_tile(
+			data,
+			&Self::region(display),
+			self.stride as usize,
+			BYTES_PER_PIXEL
+		)
+	}
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+	use crate::displays::Format;
+
+	fn display(x: u32, y: u32, width: u32, height: u32) -> Display {
+		Display {
+			id: 0,
+			name: "TEST".into(),
+			x, y, width, height,
+			format: Format::Png
+		}
+	}
+
+	/// every pixel contains it's position, the padding is filled with 0xff
+	fn frame(screen: &Screen) -> Vec<u8> {
+		let mut data = Vec::with_capacity(screen.len());
+		for y in 0..screen.height {
+			for x in 0..screen.width {
+				data.extend_from_slice(&[x as u8, y as u8, 0, 255]);
+			}
+			data.resize((y + 1) as usize * screen.stride as usize, 0xff);
+		}
+		data
+	}
+
+	fn rgba(width: u32, height: u32) -> Screen {
+		Screen::new(width, height, PixelFormat::Rgba)
+	}
+
+	#[test]
+	fn crops_the_display() {
+		let screen = rgba(8, 6);
+		let data = frame(&screen);
+
+		let cropped = screen.crop(&display(2, 3, 3, 2), &data);
+		assert_eq!(cropped.len(), 3 * 2 * BYTES_PER_PIXEL);
+
+		let positions: Vec<_> = cropped.chunks_exact(BYTES_PER_PIXEL)
+			.map(|px| (px[0], px[1]))
+			.collect();
+		assert_eq!(
+			positions,
+			[(2, 3), (3, 3), (4, 3), (2, 4), (3, 4), (4, 4)]
+		);
+	}
+
+	#[test]
+	fn crops_the_whole_screen() {
+		let screen = rgba(4, 4);
+		let data = frame(&screen);
+
+		assert_eq!(screen.crop(&display(0, 0, 4, 4), &data), data);
+	}
+
+	#[test]
+	fn crops_with_a_stride() {
+		// ultrawide with padding at the end of every row
+		let screen = Screen::with_stride(344, 144, 1400, PixelFormat::Rgba)
+			.unwrap();
+		let data = frame(&screen);
+		assert_eq!(data.len(), 1400 * 144);
+
+		let cropped = screen.crop(&display(340, 142, 4, 2), &data);
+		let positions: Vec<_> = cropped.chunks_exact(BYTES_PER_PIXEL)
+			.map(|px| (px[0], px[1], px[3]))
+			.collect();
+		assert_eq!(positions, [
+			(84, 142, 255), (85, 142, 255), (86, 142, 255), (87, 142, 255),
+			(84, 143, 255), (85, 143, 255), (86, 143, 255), (87, 143, 255)
+		]);
+	}
+
+	#[test]
+	fn stride_needs_to_fit_the_row() {
+		let format = PixelFormat::Bgra;
+		let screen = Screen::with_stride(2560, 1440, 2560 * 4, format);
+		assert_eq!(screen, Some(Screen::new(2560, 1440, format)));
+		assert!(Screen::with_stride(2560, 1440, 2559 * 4, format).is_none());
+	}
+
+	#[test]
+	fn bgra_gets_converted() {
+		let screen = Screen::new(2, 1, PixelFormat::Bgra);
+		let data = [1, 2, 3, 4, 5, 6, 7, 8];
+
+		let cropped = screen.crop(&display(0, 0, 2, 1), &data);
+		assert_eq!(cropped, [3, 2, 1, 4, 7, 6, 5, 8]);
+	}
+
+	#[test]
+	fn contains() {
+		let screen = Screen::DEFAULT;
+		assert!(screen.contains(&display(0, 0, 1920, 1080)));
+		assert!(screen.contains(&display(1280, 440, 640, 640)));
+		assert!(!screen.contains(&display(1281, 440, 640, 640)));
+		assert!(!screen.contains(&display(0, u32::MAX, 1, 1)));
+	}
+
+	#[test]
+	fn hash_only_depends_on_the_display() {
+		let screen = rgba(8, 8);
+		let mut data = frame(&screen);
+		let display = display(0, 0, 4, 4);
+		let hash = screen.hash(&display, &data);
+
+		// outside of the display
+		data[(7 * 8 + 7) * BYTES_PER_PIXEL] = 100;
+		assert_eq!(screen.hash(&display, &data), hash);
+
+		// inside of the display
+		data[(3 * 8 + 3) * BYTES_PER_PIXEL] = 100;
+		assert_ne!(screen.hash(&display, &data), hash);
+	}
+}
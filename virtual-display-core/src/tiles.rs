@@ -0,0 +1,193 @@
+/// the width and height of a tile, tiles at the right and bottom edge can be
+/// smaller
+pub const TILE_SIZE: u32 = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tile {
+	pub x: u32,
+	pub y: u32,
+	pub width: u32,
+	pub height: u32
+}
+
+/// Returns all tiles which are different in `prev` and `current`.
+///
+/// Both buffers need to contain an image with the given size.
+pub fn changed_tiles(
+	prev: &[u8],
+	current: &[u8],
+	width: u32,
+	height: u32,
+	bytes_per_pixel: usize
+) -> Vec<Tile> {
+	let mut tiles = vec![];
+	let stride = width as usize * bytes_per_pixel;
+
+	for y in (0..height).step_by(TILE_SIZE as usize) {
+		for x in (0..width).step_by(TILE_SIZE as usize) {
+			let tile = Tile {
+				x, y,
+				width: TILE_SIZE.min(width - x),
+				height: TILE_SIZE.min(height - y)
+			};
+
+			let changed = rows(&tile, stride, bytes_per_pixel)
+				.any(|range| prev[range.clone()] != current[range]);
+			if changed {
+				tiles.push(tile);
+			}
+		}
+	}
+
+	tiles
+}
+
+/// Copies the tile out of the image into a new buffer.
+///
+/// `stride` is how many bytes a row of the image has.
+pub fn copy_tile(
+	image: &[u8],
+	tile: &Tile,
+	stride: usize,
+	bytes_per_pixel: usize
+) -> Vec<u8> {
+	let mut buffer = Vec::with_capacity(
+		(tile.width * tile.height) as usize * bytes_per_pixel
+	);
+	for range in rows(tile, stride, bytes_per_pixel) {
+		buffer.extend_from_slice(&image[range]);
+	}
+
+	buffer
+}
+
+/// Hashes the pixels of the tile, used to find out cheaply if a region of the
+/// image changed without copying it.
+pub fn hash_tile(
+	image: &[u8],
+	tile: &Tile,
+	stride: usize,
+	bytes_per_pixel: usize
+) -> u64 {
+	// fxhash, we don't need a good distribution just speed
+	const SEED: u64 = 0x51_7c_c1_b7_27_22_0a_95;
+	let add = |hash: u64, word: u64| {
+		(hash.rotate_left(5) ^ word).wrapping_mul(SEED)
+	};
+
+	let mut hash = 0;
+	for range in rows(tile, stride, bytes_per_pixel) {
+		let row = &image[range];
+		let mut words = row.chunks_exact(8);
+		for word in &mut words {
+			hash = add(hash, u64::from_ne_bytes(word.try_into().unwrap()));
+		}
+		for byte in words.remainder() {
+			hash = add(hash, *byte as u64);
+		}
+	}
+
+	hash
+}
+
+/// returns the byte range of every row of the tile
+fn rows(
+	tile: &Tile,
+	stride: usize,
+	bytes_per_pixel: usize
+) -> impl Iterator<Item=std::ops::Range<usize>> {
+	let tile = *tile;
+
+	(tile.y..tile.y + tile.height).map(move |y| {
+		let start = y as usize * stride + tile.x as usize * bytes_per_pixel;
+		start..start + tile.width as usize * bytes_per_pixel
+	})
+}
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	const BPP: usize = 4;
+
+	fn image(width: u32, height: u32) -> Vec<u8> {
+		(0..width * height * BPP as u32).map(|i| i as u8).collect()
+	}
+
+	fn set_pixel(image: &mut [u8], width: u32, x: u32, y: u32) {
+		let pos = (y * width + x) as usize * BPP;
+		image[pos] = image[pos].wrapping_add(1);
+	}
+
+	#[test]
+	fn no_changes() {
+		let prev = image(100, 100);
+		assert!(changed_tiles(&prev, &prev, 100, 100, BPP).is_empty());
+	}
+
+	#[test]
+	fn finds_changed_tiles() {
+		let prev = image(200, 100);
+		let mut current = prev.clone();
+		set_pixel(&mut current, 200, 0, 0);
+		set_pixel(&mut current, 200, 130, 70);
+
+		let tiles = changed_tiles(&prev, &current, 200, 100, BPP);
+		assert_eq!(tiles, [
+			Tile { x: 0, y: 0, width: 64, height: 64 },
+			Tile { x: 128, y: 64, width: 64, height: 36 }
+		]);
+	}
+
+	#[test]
+	fn edge_tiles_are_smaller() {
+		let prev = image(70, 70);
+		let mut current = prev.clone();
+		set_pixel(&mut current, 70, 69, 69);
+
+		let tiles = changed_tiles(&prev, &current, 70, 70, BPP);
+		assert_eq!(tiles, [Tile { x: 64, y: 64, width: 6, height: 6 }]);
+	}
+
+	#[test]
+	fn copies_the_tile() {
+		let width = 4;
+		let image = image(width, 3);
+		let tile = Tile { x: 1, y: 1, width: 2, height: 2 };
+
+		let copied = copy_tile(&image, &tile, width as usize * BPP, BPP);
+		// the first pixel of the tile in the second and third row
+		let row_1 = (width as usize + 1) * BPP;
+		let row_2 = (width as usize * 2 + 1) * BPP;
+		let mut expected = image[row_1..row_1 + 2 * BPP].to_vec();
+		expected.extend_from_slice(&image[row_2..row_2 + 2 * BPP]);
+		assert_eq!(copied, expected);
+	}
+
+	#[test]
+	fn hash_changes_with_the_tile() {
+		let mut image = image(100, 100);
+		let tile = Tile { x: 10, y: 10, width: 13, height: 5 };
+		let hash = hash_tile(&image, &tile, 100 * BPP, BPP);
+		assert_eq!(hash_tile(&image, &tile, 100 * BPP, BPP), hash);
+
+		// the last byte of a row is not a full word
+		set_pixel(&mut image, 100, 22, 14);
+		assert_ne!(hash_tile(&image, &tile, 100 * BPP, BPP), hash);
+	}
+
+	#[test]
+	fn rows_honor_the_stride() {
+		// every row has two pixels of padding at the end
+		let stride = 6 * BPP;
+		let image: Vec<u8> = (0..stride * 3).map(|i| i as u8).collect();
+		let tile = Tile { x: 1, y: 1, width: 4, height: 2 };
+
+		let copied = copy_tile(&image, &tile, stride, BPP);
+		let row_1 = stride + BPP;
+		let row_2 = stride * 2 + BPP;
+		let mut expected = image[row_1..row_1 + 4 * BPP].to_vec();
+		expected.extend_from_slice(&image[row_2..row_2 + 4 * BPP]);
+		assert_eq!(copied, expected);
+	}
+}
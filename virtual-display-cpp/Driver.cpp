@@ -25,7 +25,14 @@ using namespace Microsoft::WRL;
 
 #pragma region SampleMonitors
 
-const struct SampleMonitorMode MONITOR_MODE = { 1920, 1080, 60 };
+// the first mode is the preferred one, the larger ones allow 1440p and
+// ultrawide monitors
+const struct SampleMonitorMode MONITOR_MODES[] = {
+	{ 1920, 1080, 60 },
+	{ 2560, 1440, 60 },
+	{ 3440, 1440, 60 },
+	{ 3840, 2160, 60 }
+};
 
 #pragma endregion
 
@@ -336,22 +343,12 @@ void SwapChainProcessor::RunCore()
 		return;
 	}
 
-	// create staging texture
-	D3D11_TEXTURE2D_DESC StagingTextureDesc = {
-		1920, 1080, 1, 1,
-		DXGI_FORMAT_B8G8R8A8_UNORM,
-		{ 1, 0 },
-		D3D11_USAGE_STAGING,
-		0,
-		D3D11_CPU_ACCESS_READ,
-		0
-	};
-	ID3D11Texture2D *StagingTexture;
-	hr = m_Device->Device->CreateTexture2D(&StagingTextureDesc, nullptr, &StagingTexture);
-	if (FAILED(hr))
-	{
-		return;
-	}
+	// the staging texture gets created with the mode of the first frame
+	// and recreated if the mode changes
+	D3D11_TEXTURE2D_DESC StagingTextureDesc = {};
+	ComPtr<ID3D11Texture2D> StagingTexture;
+	// the row pitch is only known after mapping, 0 means it was not reported
+	UINT ReportedRowPitch = 0;
 
 	// Acquire and release buffers in a loop
 	for (;;)
@@ -404,43 +401,89 @@ void SwapChainProcessor::RunCore()
 		{
 
 			// create a Texture2D
-			ID3D11Texture2D *FrameTexture2d;
-			hr = AcquiredBuffer.Get()->QueryInterface(__uuidof(ID3D11Texture2D), (void **)&FrameTexture2d);
+			ComPtr<ID3D11Texture2D> FrameTexture2d;
+			hr = AcquiredBuffer.As(&FrameTexture2d);
 			if (FAILED(hr))
 			{
 				break;
 			}
 
-			D3D11_MAPPED_SUBRESOURCE Mapped;
-
-			m_Device->DeviceContext->CopyResource(StagingTexture, FrameTexture2d);
+			D3D11_TEXTURE2D_DESC FrameDesc;
+			FrameTexture2d->GetDesc(&FrameDesc);
 
-			hr = m_Device->DeviceContext->Map(StagingTexture, 0, D3D11_MAP_READ, 0, &Mapped);
-			if (FAILED(hr))
+			DWORD VdFormat;
+			switch (FrameDesc.Format)
 			{
+			case DXGI_FORMAT_B8G8R8A8_UNORM:
+			case DXGI_FORMAT_B8G8R8A8_UNORM_SRGB:
+				VdFormat = VD_FORMAT_BGRA;
+				break;
+			case DXGI_FORMAT_R8G8B8A8_UNORM:
+			case DXGI_FORMAT_R8G8B8A8_UNORM_SRGB:
+				VdFormat = VD_FORMAT_RGBA;
 				break;
+			default:
+				// for example hdr, we only support 4 bytes per pixel
+				VdFormat = MAXDWORD;
 			}
 
-			DWORD Len = StagingTextureDesc.Width * StagingTextureDesc.Height * 4;// 4 bytes per pixel
-			BYTE* TextureBuffer = VdCreateTextureBuffer(pVdData, Len);
-
-			BYTE* Dest = TextureBuffer;
-			BYTE* Source = static_cast<BYTE*>(Mapped.pData);
-			DWORD DestStride = StagingTextureDesc.Width * 4;
-
-			for (DWORD i = 0; i < StagingTextureDesc.Height; i++)
+			if (VdFormat != MAXDWORD)
 			{
-				memcpy(Dest, Source, DestStride);
-
-				Source += Mapped.RowPitch;
-				Dest += DestStride;
+				if (!StagingTexture ||
+					FrameDesc.Width != StagingTextureDesc.Width ||
+					FrameDesc.Height != StagingTextureDesc.Height ||
+					FrameDesc.Format != StagingTextureDesc.Format)
+				{
+					StagingTextureDesc = {
+						FrameDesc.Width, FrameDesc.Height, 1, 1,
+						FrameDesc.Format,
+						{ 1, 0 },
+						D3D11_USAGE_STAGING,
+						0,
+						D3D11_CPU_ACCESS_READ,
+						0
+					};
+					StagingTexture.Reset();
+					ReportedRowPitch = 0;
+
+					hr = m_Device->Device->CreateTexture2D(&StagingTextureDesc, nullptr, &StagingTexture);
+					if (FAILED(hr))
+					{
+						break;
+					}
+				}
+
+				D3D11_MAPPED_SUBRESOURCE Mapped;
+
+				m_Device->DeviceContext->CopyResource(StagingTexture.Get(), FrameTexture2d.Get());
+
+				hr = m_Device->DeviceContext->Map(StagingTexture.Get(), 0, D3D11_MAP_READ, 0, &Mapped);
+				if (FAILED(hr))
+				{
+					break;
+				}
+
+				// rust crops the displays with the mode so the rows can
+				// be copied with their padding
+				if (Mapped.RowPitch != ReportedRowPitch &&
+					VdSetMode(pVdData, StagingTextureDesc.Width, StagingTextureDesc.Height, Mapped.RowPitch, VdFormat))
+				{
+					ReportedRowPitch = Mapped.RowPitch;
+				}
+
+				if (Mapped.RowPitch == ReportedRowPitch)
+				{
+					DWORD Len = Mapped.RowPitch * StagingTextureDesc.Height;
+					BYTE* TextureBuffer = VdCreateTextureBuffer(pVdData, Len);
+
+					memcpy(TextureBuffer, Mapped.pData, Len);
+
+					VdSendTexture(pVdData, TextureBuffer, Len);
+				}
+
+				m_Device->DeviceContext->Unmap(StagingTexture.Get(), 0);
 			}
 
-			// todo we should somehow convert bgra to rgba on the gpu
-			// bot for the moment that will be done on the cpu
-
-			VdSendTexture(pVdData, TextureBuffer, Len);
-
 		}
 
 		// We have finished processing this frame hence we release the reference on it.
@@ -669,14 +712,23 @@ NTSTATUS IddSampleParseMonitorDescription(const IDARG_IN_PARSEMONITORDESCRIPTION
 	// this sample driver, we hard-code the EDID, so this function can generate known modes.
 	// ==============================
 
-	pOutArgs->MonitorModeBufferOutputCount = 1;
+	pOutArgs->MonitorModeBufferOutputCount = ARRAYSIZE(MONITOR_MODES);
 
-	pInArgs->pMonitorModes[0] = CreateIddCxMonitorMode(
-		MONITOR_MODE.Width,
-		MONITOR_MODE.Height,
-		MONITOR_MODE.VSync,
-		IDDCX_MONITOR_MODE_ORIGIN_MONITORDESCRIPTOR
-	);
+	if (pInArgs->MonitorModeBufferInputCount < ARRAYSIZE(MONITOR_MODES))
+	{
+		// Return success if there was no buffer, since the caller was only asking for a count of modes
+		return (pInArgs->MonitorModeBufferInputCount > 0) ? STATUS_BUFFER_TOO_SMALL : STATUS_SUCCESS;
+	}
+
+	for (DWORD ModeIndex = 0; ModeIndex < ARRAYSIZE(MONITOR_MODES); ModeIndex++)
+	{
+		pInArgs->pMonitorModes[ModeIndex] = CreateIddCxMonitorMode(
+			MONITOR_MODES[ModeIndex].Width,
+			MONITOR_MODES[ModeIndex].Height,
+			MONITOR_MODES[ModeIndex].VSync,
+			IDDCX_MONITOR_MODE_ORIGIN_MONITORDESCRIPTOR
+		);
+	}
 
 	pOutArgs->PreferredMonitorModeIdx = 0;
 
@@ -697,18 +749,25 @@ NTSTATUS IddSampleMonitorGetDefaultModes(IDDCX_MONITOR MonitorObject, const IDAR
 
 	if (pInArgs->DefaultMonitorModeBufferInputCount == 0)
 	{
-		pOutArgs->DefaultMonitorModeBufferOutputCount = 1;
+		pOutArgs->DefaultMonitorModeBufferOutputCount = ARRAYSIZE(MONITOR_MODES);
+	}
+	else if (pInArgs->DefaultMonitorModeBufferInputCount < ARRAYSIZE(MONITOR_MODES))
+	{
+		return STATUS_BUFFER_TOO_SMALL;
 	}
 	else
 	{
-		pInArgs->pDefaultMonitorModes[0] = CreateIddCxMonitorMode(
-			MONITOR_MODE.Width,
-			MONITOR_MODE.Height,
-			MONITOR_MODE.VSync,
-			IDDCX_MONITOR_MODE_ORIGIN_DRIVER
-		);
+		for (DWORD ModeIndex = 0; ModeIndex < ARRAYSIZE(MONITOR_MODES); ModeIndex++)
+		{
+			pInArgs->pDefaultMonitorModes[ModeIndex] = CreateIddCxMonitorMode(
+				MONITOR_MODES[ModeIndex].Width,
+				MONITOR_MODES[ModeIndex].Height,
+				MONITOR_MODES[ModeIndex].VSync,
+				IDDCX_MONITOR_MODE_ORIGIN_DRIVER
+			);
+		}
 
-		pOutArgs->DefaultMonitorModeBufferOutputCount = 1;
+		pOutArgs->DefaultMonitorModeBufferOutputCount = ARRAYSIZE(MONITOR_MODES);
 		pOutArgs->PreferredMonitorModeIdx = 0;
 	}
 
@@ -726,11 +785,14 @@ NTSTATUS IddSampleMonitorQueryModes(IDDCX_MONITOR MonitorObject, const IDARG_IN_
 	// monitor's descriptor and instead are based on the static processing capability of the device. The OS will
 	// report the available set of modes for a given output as the intersection of monitor modes with target modes.
 
-	TargetModes.push_back(CreateIddCxTargetMode(
-		MONITOR_MODE.Width,
-		MONITOR_MODE.Height,
-		MONITOR_MODE.VSync
-	));
+	for (const auto& Mode : MONITOR_MODES)
+	{
+		TargetModes.push_back(CreateIddCxTargetMode(
+			Mode.Width,
+			Mode.Height,
+			Mode.VSync
+		));
+	}
 
 	pOutArgs->TargetModeBufferOutputCount = (UINT)TargetModes.size();
 
@@ -1,12 +1,13 @@
 
 mod texture_buffer;
-mod displays;
 mod virtual_display;
 use virtual_display::VirtualDisplay;
 
 use std::ffi::c_void;
 use std::sync::Arc;
 
+use virtual_display_core::screen::{Screen, PixelFormat};
+
 // ## Note
 // The pointer is *const Mutex<VirtualDisplay> comming from an arc
 
@@ -43,6 +44,31 @@ pub extern "C" fn VdShouldSendTexture(
 	has_changed
 }
 
+/// Returns false if the mode is not supported, frames with that mode will
+/// not be sent.
+#[no_mangle]
+pub extern "C" fn VdSetMode(
+	data: *const c_void,
+	width: u32,
+	height: u32,
+	stride: u32,
+	format: u32
+) -> bool {
+	let data = data_from_ptr(data);
+
+	let screen = PixelFormat::from_u32(format).and_then(|format| {
+		Screen::with_stride(width, height, stride, format)
+	});
+
+	match screen {
+		Some(screen) => {
+			data.set_mode(screen);
+			true
+		},
+		None => false
+	}
+}
+
 #[no_mangle]
 pub extern "C" fn VdCreateTextureBuffer(
 	data: *const c_void,
@@ -60,9 +86,14 @@ pub extern "C" fn VdCreateTextureBuffer(
 		}
 	}
 
-	let buffer = buffer.get_mut();
+	let frame = buffer.get_mut();
 	data.set_locked(true);
 
+	// the frame always needs to be cropped with the mode it was captured
+	// with
+	frame.screen = data.mode();
+	let buffer = &mut frame.data;
+
 	if buffer.capacity() != len as usize {
 		*buffer = Vec::with_capacity(len as usize);
 	}
@@ -83,17 +114,13 @@ pub extern "C" fn VdSendTexture(data: *const c_void, ptr: *mut u8, len: u32) {
 	// validate the buffer
 	{
 		let buffer = unsafe {
-			buffer.unsafe_get_mut()
+			&mut buffer.unsafe_get_mut().data
 		};
 
 		assert_eq!(buffer.len(), len as usize);
 		assert_eq!(buffer.as_mut_ptr(), ptr);
 
-		// convert from bgra to rgba
-		// is quite fast (max around 1ms)
-		for bgra in buffer.chunks_exact_mut(4) {
-			bgra.swap(0, 2);
-		}
+		// the pixels get converted to rgba when the displays get cropped
 	}
 
 	// we now are sure that we have the same data
@@ -4,6 +4,8 @@ use std::cell::UnsafeCell;
 use parking_lot::RawMutex;
 use parking_lot::lock_api::RawMutex as RawMutexTrait;
 
+use virtual_display_core::screen::Screen;
+
 // struct TextureBuffer<T> {
 // 	active: AtomicBool,
 // 	data: [Mutex<T>; 2]
@@ -12,20 +14,36 @@ use parking_lot::lock_api::RawMutex as RawMutexTrait;
 // if a write get's to it
 // check which one is active
 
+#[derive(Debug, Clone)]
+pub struct Frame {
+	pub data: Vec<u8>,
+	/// the mode the data was captured with
+	pub screen: Screen
+}
+
 pub struct TextureBuffer {
 	mutex: RawMutex,
-	data: UnsafeCell<Vec<u8>>
+	data: UnsafeCell<Frame>
 }
 
 impl TextureBuffer {
 	pub fn new() -> Self {
 		Self {
 			mutex: RawMutex::INIT,
-			data: UnsafeCell::new(vec![])
+			data: UnsafeCell::new(Frame {
+				data: vec![],
+				screen: Screen::DEFAULT
+			})
 		}
 	}
 
-	pub fn get_mut(&self) -> &mut Vec<u8> {
+	/// Locks the buffer until `unlock` get's called.
+	// the reference is exclusive because the lock is only released by
+	// `unlock`, which requires the reference to be dropped, a guard cannot
+	// be used since the lock is held between VdCreateTextureBuffer and
+	// VdSendTexture
+	#[allow(clippy::mut_from_ref)]
+	pub fn get_mut(&self) -> &mut Frame {
 		self.mutex.lock();
 		unsafe {
 			&mut *self.data.get()
@@ -33,8 +51,10 @@ impl TextureBuffer {
 	}
 
 	/// You need to own the lock and not already have a reference to the
-	/// Vec
-	pub unsafe fn unsafe_get_mut(&self) -> &mut Vec<u8> {
+	/// Frame
+	// same as get_mut, the lock makes the reference exclusive
+	#[allow(clippy::mut_from_ref)]
+	pub unsafe fn unsafe_get_mut(&self) -> &mut Frame {
 		&mut *self.data.get()
 	}
 
@@ -44,7 +64,7 @@ impl TextureBuffer {
 		self.mutex.unlock();
 	}
 
-	pub fn get(&self) -> Vec<u8> {
+	pub fn get(&self) -> Frame {
 		self.mutex.lock();
 
 		let v = unsafe {
@@ -1,26 +1,22 @@
-
 use crate::texture_buffer::TextureBuffer;
-use crate::displays::{Displays, Display};
 
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 #[cfg(feature = "log-framerate")]
 use std::time::Instant;
-use std::{thread, fs};
+use std::{thread, fs, fmt};
 use std::net::TcpStream;
 use std::io::{self, Write, BufReader, Read};
 use std::path::Path;
 
-use image::{RgbaImage, ImageOutputFormat};
-
-use rayon::prelude::*;
-
-use simple_bytes::{BytesOwned, BytesRead, BytesSeek, BytesWrite};
+use virtual_display_core::displays::Displays;
+use virtual_display_core::screen::Screen;
+use virtual_display_core::protocol::{self, FrameEncoder, ADDR};
 
 use crossbeam_utils::sync::{Parker, Unparker};
 
-const ADDR: &str = "127.0.0.1:5476";
+use parking_lot::Mutex;
 
 // use crossbeam::channel::Receiver;
 
@@ -32,6 +28,8 @@ struct Inner {
 pub struct VirtualDisplay {
 	// tracks if the swap chain as locked the buffer
 	has_locked: AtomicBool,
+	/// the mode the driver reported last, get's stored with every frame
+	mode: Mutex<Screen>,
 	unparker: Unparker,
 	inner: Arc<Inner>
 }
@@ -62,7 +60,7 @@ impl VirtualDisplay {
 						thread::sleep(Duration::from_secs(5));
 					},
 					Err(e) => {
-						log(&format!("connection error {:?}\n", e));
+						log(&format!("connection error {e}\n"));
 						thread::sleep(Duration::from_secs(1));
 					},
 				}
@@ -71,6 +69,7 @@ impl VirtualDisplay {
 
 		Self {
 			has_locked: AtomicBool::new(false),
+			mode: Mutex::new(Screen::DEFAULT),
 			unparker, inner
 		}
 	}
@@ -83,6 +82,14 @@ impl VirtualDisplay {
 		self.has_locked.store(locked, Ordering::SeqCst)
 	}
 
+	pub fn mode(&self) -> Screen {
+		*self.mode.lock()
+	}
+
+	pub fn set_mode(&self, mode: Screen) {
+		*self.mode.lock() = mode;
+	}
+
 	pub fn buffer(&self) -> &TextureBuffer {
 		&self.inner.buffer
 	}
@@ -102,33 +109,15 @@ impl Drop for VirtualDisplay {
 #[derive(Debug)]
 enum Error {
 	Connecting(io::Error),
-	Transmission(io::Error),
-	Serde(serde_json::Error),
-	Image(image::ImageError)
+	Transmission(io::Error)
 }
 
-const TOTAL_WIDTH: usize = 1920;
-const TOTAL_HEIGHT: usize = 1080;
-const BYTES_PER_PIXEL: usize = 4;
-const LEN: usize = TOTAL_WIDTH * TOTAL_HEIGHT * BYTES_PER_PIXEL;
-const EXPECTED_LEN: usize = 640 * 640 * BYTES_PER_PIXEL;
-
-fn copy_display_frame(display: &Display, data: &[u8], to: &mut [u8]) {
-	for n_y in 0..display.height {
-		let n_y = n_y as usize;
-		let m_y = display.y as usize;
-		let m_w = display.width as usize;
-
-		let r_y = m_y + n_y;
-		let r_x = display.x as usize;// end is display.width
-
-		let data_start = (r_y * TOTAL_WIDTH + r_x) * BYTES_PER_PIXEL;
-
-		let to_start = n_y * m_w * BYTES_PER_PIXEL;
-		let bytes_len = m_w * BYTES_PER_PIXEL;
-		to[to_start..(to_start + bytes_len)].copy_from_slice(
-			&data[data_start..(data_start + bytes_len)]
-		);
+impl fmt::Display for Error {
+	fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+		match self {
+			Self::Connecting(e) => write!(f, "could not connect {e}"),
+			Self::Transmission(e) => write!(f, "transmission failed {e}")
+		}
 	}
 }
 
@@ -139,7 +128,6 @@ fn log(s: &str) {
 	}
 
 	let mut file = fs::OpenOptions::new()
-		.write(true)
 		.append(true)
 		.open(path)
 		.expect("failed to open logs.txt");
@@ -156,20 +144,13 @@ fn connection_loop(
 		.map_err(Error::Connecting)?;
 	let mut reader = BufReader::new(stream);
 
-	// ┌────────────┐
-	// |Displays Len│
-	// ├────────────┤
-	// │     8      │
-	// └────────────┘
-	//
-	// ┌────┬───┬──────┐
-	// │Kind│Len│ Data │
-	// ├────┼───┼──────┤
-	// │ 8  │32 │$Len*8│
-	// └────┴───┴──────┘
-	// reserved 5bytes
-	let mut image_buffers = vec![];
-	let mut recv_buffer = Vec::with_capacity(1024);
+	// the first frame of every display needs to be a keyframe
+	let mut encoder = FrameEncoder::new();
+	let mut screen = Screen::DEFAULT;
+	// if the displays were checked against the mode
+	let mut checked = false;
+	// the mode the server knows about
+	let mut reported = None;
 
 	loop {
 		parker.park();
@@ -194,21 +175,33 @@ fn connection_loop(
 			.map_err(Error::Transmission)?;
 
 		if let Some(len) = len {
-			let len = len as usize;
-			recv_buffer.resize(len, 0);
-			reader.read_exact(&mut recv_buffer[..len])
+			*displays = protocol::read_displays_body(&mut reader, len)
 				.map_err(Error::Transmission)?;
-
-			*displays = serde_json::from_slice(&recv_buffer[..len])
-				.map_err(Error::Serde)?;
+			encoder.reset();
+			checked = false;
 		}
 
 		// get some data
-		let data = inner.buffer.get();
-		if data.len() != LEN {
+		let frame = inner.buffer.get();
+		if frame.screen != screen {
+			log(&format!("mode changed to {:?}\n", frame.screen));
+			screen = frame.screen;
+			// the displays now show something else
+			encoder.reset();
+			checked = false;
+		}
+
+		if frame.data.len() != screen.len() {
 			continue
 		}
 
+		// the server checks the displays against the mode
+		if reported != Some(screen) {
+			protocol::write_mode(reader.get_mut(), &screen)
+				.map_err(Error::Transmission)?;
+			reported = Some(screen);
+		}
+
 		let displays = match &displays {
 			Some(displays) => displays,
 			None => {
@@ -219,61 +212,26 @@ fn connection_loop(
 			}
 		};
 
+		if !checked {
+			// they will not be sent
+			let outside = displays.inner.iter()
+				.filter(|d| !screen.contains(d));
+			for display in outside {
+				log(&format!("{} is outside of the screen\n", display.name));
+			}
+			checked = true;
+		}
+
 		#[cfg(feature = "log-framerate")]
 		let start = Instant::now();
 
-		// let's send how many displays we will send
-		let displays_len = displays.inner.len() as u8;
-		reader.get_mut().write_all(&[displays_len])
-			.map_err(Error::Transmission)?;
-
-		if displays_len as usize != image_buffers.len() {
-			image_buffers.resize(
-				displays_len as usize,
-				BytesOwned::with_capacity(EXPECTED_LEN)
-			);
+		for e in encoder.encode_screen(displays, &screen, &frame.data) {
+			log(&format!("image error {e}\n"));
 		}
 
-		displays.inner.par_iter().zip(&mut image_buffers)
-			.for_each(|((kind, display), mut image_buffer)| {
-				let size = display.width * display.height *
-					BYTES_PER_PIXEL as u32;
-				let mut buffer = vec![0; size as usize];
-
-				// now get the range from data
-				copy_display_frame(display, &data, &mut buffer);
-
-				image_buffer.resize(5);
-				image_buffer.seek(5);
-
-				// now convert the raw bytes to a jpeg
-				let image = RgbaImage::from_vec(
-					display.width,
-					display.height,
-					buffer
-				);
-				if let Some(image) = image {
-					let r = image.write_to(
-						&mut image_buffer,
-						ImageOutputFormat::Jpeg(80)
-					).map_err(Error::Image);
-					if let Err(e) = r {
-						log(&format!("image error {:?}", e));
-						return;
-					}
-				}
-
-				image_buffer.seek(0);
-				image_buffer.write_u8(kind.as_u8());
-				let len = image_buffer.len() - 5;
-				image_buffer.write_u32(len as u32);
-			});
-
-		for image_buffer in &image_buffers {
-			// now send the data
-			reader.get_mut().write_all(image_buffer.as_slice())
-				.map_err(Error::Transmission)?;
-		}
+		// unchanged displays are skipped
+		encoder.write_to(reader.get_mut())
+			.map_err(Error::Transmission)?;
 
 		#[cfg(feature = "log-framerate")]
 		{
@@ -281,4 +239,4 @@ fn connection_loop(
 			log(&format!("display loop took: {}ms\n", took.as_millis()));
 		}
 	}
-}
\ No newline at end of file
+}
@@ -13,7 +13,23 @@ extern "C" {
 
 	bool VdShouldSendTexture(const void* data, bool has_changed);
 
+	// the pixel formats VdSetMode accepts
+	const DWORD VD_FORMAT_BGRA = 0;
+	const DWORD VD_FORMAT_RGBA = 1;
+
+	// reports the mode of the following textures
+	// stride is how many bytes a row has (RowPitch)
+	// returns false if the mode is not supported
+	bool VdSetMode(
+		const void* data,
+		DWORD width,
+		DWORD height,
+		DWORD stride,
+		DWORD format
+	);
+
 	// returns an array with exacly the len
+	// which should be stride * height of the mode
 	// you are not allowed to call this concurrently
 	BYTE* VdCreateTextureBuffer(const void* data, DWORD len);
 